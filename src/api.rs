@@ -4,7 +4,7 @@ use std::time::{Duration, Instant, SystemTime};
 use std::collections::{HashMap, HashSet};
 use actix_files;
 
-use rand::{thread_rng, Rng};
+use rand::Rng;
 
 use actix_multipart::Multipart;
 use futures::StreamExt;
@@ -13,13 +13,19 @@ use regex::Regex;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value, Map};
 use std::fs;
-use std::io::prelude::*;
 use std::sync::Mutex;
 use actix_web_actors::ws;
 
 use crate::db;
+use crate::auth;
+use crate::oauth;
+use crate::body;
+use crate::upload;
+use crate::graphql;
+use crate::inspector;
 use crate::websocket::WsChatSession;
 use crate::server;
+use crate::store;
 use actix::*;
 use crate::mock;
 use crate::{int, float, timestamp};
@@ -57,6 +63,13 @@ pub async fn get_api_doc_data(req_get: web::Query<ApiDocDataRequest>, data: web:
         } else {
             return HttpResponse::Ok().body("");
         }
+    } else if req_get.filename == "_types.json5" {
+        return HttpResponse::Ok().json(json!({
+            "name": "Types",
+            "desc": "",
+            "filename": "_types.json5",
+            "types": db::get_custom_types(&data.custom_types)
+        }));
     }
 
     for (_, doc) in api_docs {
@@ -95,9 +108,12 @@ pub async fn get_api_doc_basic(data: web::Data<Mutex<db::Database>>) -> HttpResp
     if let Some(auth_doc) = &data.auth_doc {
         docs.push(json!({"name":auth_doc.name, "desc":auth_doc.desc, "order":0, "filename":"_auth.json5"}));
     }
-    if let Some(settings) = &data.settings {
+    if data.settings.is_some() {
         docs.push(json!({"name":"Settings", "desc":"", "order":0, "filename":"_settings.json5"}));
     }
+    if data.custom_types.len() > 0 {
+        docs.push(json!({"name":"Types", "desc":"", "order":0, "filename":"_types.json5"}));
+    }
     for (_, doc) in api_docs {
         docs.push(json!({ "name": doc.name, "desc": doc.desc, "order": doc.order, "filename": doc.filename }));
     }
@@ -153,68 +169,378 @@ pub async fn chat_route(
     req: HttpRequest,
     stream: web::Payload,
     srv: web::Data<Addr<server::ChatServer>>,
+    db_data: web::Data<Mutex<db::Database>>,
 ) -> Result<HttpResponse, Error> {
-    println!("s {:?}", req);
-    ws::start(
-        WsChatSession {
-            id: 0,
-            hb: Instant::now(),
-            room: "Main".to_owned(),
-            name: None,
-            addr: srv.get_ref().clone(),
-        },
-        &req,
-        stream,
-    )
+    let req_path = req.path();
+    let mut session = WsChatSession {
+        id: 0,
+        hb: Instant::now(),
+        room: "Main".to_owned(),
+        name: None,
+        addr: srv.get_ref().clone(),
+        api_url: "".to_string(),
+        db: db_data.clone(),
+        user: Value::Null,
+        authed: true,
+        history: 0,
+    };
+
+    {
+        let db_data = db_data.lock().unwrap();
+        let api_url = match find_websocket_api_url(req_path, &db_data) {
+            Some(v) => v,
+            None => {
+                return Ok(HttpResponse::NotFound().json(json!({
+                    "code": -1,
+                    "msg": format!("this api address {} no websocket api match", req_path)
+                })));
+            }
+        };
+        let a_api_data = db_data.api_data[&api_url]["WEBSOCKET"].lock().unwrap();
+
+        // chat设置: {room: "Main", history: 20, token_param: "token", auth_message: true}
+        let chat = &a_api_data.chat;
+        if let Some(room) = chat.get("room").and_then(|x| x.as_str()) {
+            session.room = room.to_string();
+        }
+        session.history = chat.get("history").and_then(|x| x.as_u64()).unwrap_or(0) as usize;
+
+        // 浏览器的websocket不能设置header，token可以放在query中，或者连接后第一条消息发送
+        if let Some(auth_doc) = &db_data.auth_doc {
+            let token_param = chat.get("token_param").and_then(|x| x.as_str()).unwrap_or("token");
+            let mut token = match web::Query::<HashMap<String, String>>::from_query(req.query_string()) {
+                Ok(query) => query.get(token_param).cloned().unwrap_or_default(),
+                Err(_) => "".to_string()
+            };
+            if token == "" {
+                token = auth::get_token_from_request(&req, auth_doc, &Value::Null, &Value::Null);
+            }
+            match auth::find_user_by_token(auth_doc, &token) {
+                Some((user, group)) => {
+                    if a_api_data.auth {
+                        let group = auth_doc.groups.iter().find(|x| &x.name == group).unwrap();
+                        if let Some(no_perm_response) = group_perm_validator(group, &api_url, "WEBSOCKET", &auth_doc.no_perm_response) {
+                            return Ok(HttpResponse::Forbidden().json(no_perm_response));
+                        }
+                    }
+                    session.set_user(user.clone());
+                }
+                None => {
+                    if a_api_data.auth {
+                        if chat.get("auth_message").and_then(|x| x.as_bool()).unwrap_or(true) {
+                            session.authed = false;
+                        } else {
+                            return Ok(HttpResponse::Unauthorized().json(&auth_doc.no_perm_response));
+                        }
+                    }
+                }
+            }
+        }
+        session.api_url = api_url.clone();
+    }
+
+    ws::start(session, &req, stream)
 }
 
 
-/// 处理post、put、delete 请求
-///
-pub async fn action_handle(req: HttpRequest, request_body: Option<web::Json<Value>>, request_query: Option<web::Query<Value>>, request_form_data: Option<Multipart>, db_data: web::Data<Mutex<db::Database>>) -> HttpResponse {
-    let req_path = req.path();
-    let body_mode = get_request_body_mode(&req);
-    let req_method = req.method().as_str();
+/// 判断是否是websocket握手请求
+pub fn is_websocket_request(head: &actix_web::dev::RequestHead) -> bool {
+    match head.headers().get("upgrade").and_then(|x| x.to_str().ok()) {
+        Some(v) => v.eq_ignore_ascii_case("websocket"),
+        None => false
+    }
+}
 
-    if req_method == "OPTIONS" {
-        return HttpResponse::Ok().body("");
+
+/// 找到请求地址对应的websocket接口地址
+fn find_websocket_api_url(req_path: &str, db_data: &db::Database) -> Option<String> {
+    for (api_url, a_api_data) in &db_data.api_data {
+        if a_api_data.contains_key("WEBSOCKET") && ResourceDef::new(api_url).is_match(req_path) {
+            return Some(api_url.to_string());
+        }
     }
+    None
+}
 
-    let form_data = if &body_mode == "form-data" {
-        get_request_form_data(request_form_data).await
-    } else {
-        Value::Null
+
+/// 获取websocket接口中的push设置
+pub fn get_websocket_pushes(api_url: &str, db_data: &db::Database) -> Vec<Value> {
+    let a_api_data = match db_data.api_data.get(api_url).and_then(|x| x.get("WEBSOCKET")) {
+        Some(v) => v,
+        None => return Vec::new()
     };
+    let a_api_data = a_api_data.lock().unwrap();
+    match a_api_data.push.as_array() {
+        Some(v) => v.clone(),
+        None => Vec::new()
+    }
+}
+
 
-    let request_body = match request_body {
-        Some(x) => {
-            x.into_inner()
+/// 生成一条推送的消息，message 为固定的消息，response 为字段定义，每次推送生成新的mock数据
+pub fn create_websocket_push_message(push: &Value, types: &db::CustomTypes) -> String {
+    let message = match push.get("message") {
+        Some(v) => v.clone(),
+        None => {
+            match push.get("response") {
+                Some(response) => {
+                    match try_create_mock_response(response, types) {
+                        Ok(v) => Value::Object(v),
+                        Err(e) => json!({"code": -1, "msg": format!("websocket push mock response error: {}", e)})
+                    }
+                }
+                None => Value::Null
+            }
         }
-        None => Value::Null
     };
+    match message {
+        Value::String(v) => v,
+        v => v.to_string()
+    }
+}
+
+
+#[derive(Deserialize)]
+pub struct WebsocketSendRequest {
+    url: Option<String>,
+    room: Option<String>,
+    message: Value,
+}
+
+
+/// 给websocket接口的连接主动推送一条消息，用于测试中触发事件
+/// POST /__api_docs/websocket/send/ {url: "/ws/orders/", room: "", message: {...}}
+pub async fn websocket_send(body: web::Json<WebsocketSendRequest>, srv: web::Data<Addr<server::ChatServer>>) -> HttpResponse {
+    let body = body.into_inner();
+    let msg = match body.message {
+        Value::String(v) => v,
+        v => v.to_string()
+    };
+    let result = srv.send(server::Inject {
+        route: body.url.unwrap_or_default(),
+        room: body.room.unwrap_or_default(),
+        msg,
+    }).await;
+    match result {
+        Ok(count) => HttpResponse::Ok().json(json!({"code": 1, "msg": "", "count": count})),
+        Err(e) => HttpResponse::Ok().json(json!({"code": -1, "msg": format!("websocket send error: {:?}", e)}))
+    }
+}
+
+
+/// 根据websocket接口的test_data找到收到的消息对应的回复
+/// test_data中 message 为收到的消息，和http的body一样按json匹配，response 为回复的消息，responses 可以回复多条
+pub fn find_websocket_test_reply(api_url: &str, message: &str, db_data: &db::Database) -> Option<Vec<Value>> {
+    let a_api_data = db_data.api_data.get(api_url)?.get("WEBSOCKET")?;
+    let a_api_data = a_api_data.lock().unwrap();
+
+    // 收到的消息不是json的时候，按字符串匹配
+    let message: Value = match serde_json::from_str(message) {
+        Ok(v) => v,
+        Err(_) => Value::String(message.to_string())
+    };
+
+    if let Some(test_data) = a_api_data.test_data.as_array() {
+        for test_case_data in test_data {
+            let pattern = match test_case_data.get("message").or(test_case_data.get("body")) {
+                Some(v) => v,
+                None => continue
+            };
+            if !is_value_equal(&message, pattern) {
+                continue;
+            }
+            if let Some(responses) = test_case_data.get("responses").and_then(|x| x.as_array()) {
+                return Some(responses.clone());
+            }
+            return match test_case_data.get("response") {
+                Some(v) => Some(vec![v.clone()]),
+                None => Some(Vec::new())
+            };
+        }
+    }
+    None
+}
+
+
+/// 先按照test_data找到回复，没有匹配到的时候，如果接口设置了response，返回mock数据，否则返回None
+pub fn find_websocket_reply(api_url: &str, message: &str, db_data: &db::Database) -> Option<Vec<Value>> {
+    if let Some(replies) = find_websocket_test_reply(api_url, message, db_data) {
+        return Some(replies);
+    }
+    let a_api_data = db_data.api_data.get(api_url)?.get("WEBSOCKET")?;
+    let a_api_data = a_api_data.lock().unwrap();
+    if a_api_data.response.is_object() {
+        return match try_create_mock_response(&a_api_data.response, &db_data.custom_types) {
+            Ok(v) => Some(vec![Value::Object(v)]),
+            Err(e) => Some(vec![json!({"code": -1, "msg": format!("this api address {} mock response error: {}", api_url, e)})])
+        };
+    }
+    None
+}
+
+
+/// 处理post、put、delete 请求
+/// 每个请求都保存到请求记录中
+pub async fn action_handle(req: HttpRequest, payload: web::Payload, db_data: web::Data<Mutex<db::Database>>, request_log: web::Data<Mutex<inspector::RequestLog>>) -> HttpResponse {
+    if req.method().as_str() == "OPTIONS" {
+        return HttpResponse::Ok().body("");
+    }
+
+    let start = Instant::now();
+    let mut record = inspector::RequestRecord::new(&req);
+    let response = request_handle(&req, payload, db_data.clone(), &mut record).await;
+    record.finish(&response, start);
+    record.redact(&db_data.lock().unwrap().auth_doc);
+    request_log.lock().unwrap().add(record);
+    response
+}
+
 
-    let request_query = match request_query {
-        Some(x) => x.into_inner(),
-        None => Value::Null
+async fn request_handle(req: &HttpRequest, mut payload: web::Payload, db_data: web::Data<Mutex<db::Database>>, record: &mut inspector::RequestRecord) -> HttpResponse {
+    let mut body_mode = get_request_body_mode(req);
+    let req_method = req.method().as_str();
+
+    let request_query = match body::parse_urlencoded(req.query_string().as_bytes(), None) {
+        Ok(v) => v,
+        Err(e) => {
+            return HttpResponse::Ok().json(json!({
+                "code": -1,
+                "msg": format!("request query can not be decoded: {}", e)
+            }));
+        }
     };
 
-    find_response_data(&req, body_mode, request_body, request_query, form_data, db_data)
+    let mut request_body = Value::Null;
+    let mut form_data = Value::Null;
+    let mut files = Value::Null;
+    if &body_mode == "form-data" {
+        let (a_api_data, settings) = {
+            let db = db_data.lock().unwrap();
+            (find_api_data(req, &db), db.settings.clone())
+        };
+        match upload::get_request_form_data(Multipart::new(req.headers(), payload), a_api_data.as_ref(), &settings).await {
+            Ok((v, f)) => {
+                form_data = v;
+                files = f;
+            }
+            Err(e) => {
+                return HttpResponse::Ok().json(json!({
+                    "code": -1,
+                    "msg": format!("upload error: {}", e)
+                }));
+            }
+        }
+    } else if req_method != "GET" {
+        let max_body_size = get_max_body_size(&db_data.lock().unwrap().settings);
+        let mut bytes = web::BytesMut::new();
+        while let Some(chunk) = payload.next().await {
+            match chunk {
+                Ok(chunk) => {
+                    if bytes.len() + chunk.len() > max_body_size {
+                        return HttpResponse::Ok().json(json!({
+                            "code": -1,
+                            "msg": format!("request body is larger than {} bytes", max_body_size)
+                        }));
+                    }
+                    bytes.extend_from_slice(&chunk)
+                }
+                Err(e) => {
+                    return HttpResponse::Ok().json(json!({
+                        "code": -1,
+                        "msg": format!("read request body error: {}", e)
+                    }));
+                }
+            }
+        }
+
+        // 没有content-type的请求，按照接口文档中的body_mode解码
+        if &body_mode == "" && !bytes.is_empty() {
+            let db = db_data.lock().unwrap();
+            body_mode = find_api_data(req, &db).map(|x| x.body_mode.to_lowercase()).unwrap_or_default();
+        }
+
+        let content_type = req.headers().get("content-type").and_then(|x| x.to_str().ok()).unwrap_or("");
+        let media_type = body::parse_media_type(content_type);
+        let v = match body::parse_request_body(&body_mode, media_type.charset(), &bytes) {
+            Ok(v) => v,
+            Err(e) => {
+                return HttpResponse::Ok().json(json!({
+                    "code": -1,
+                    "msg": format!("request body can not be decoded as {}: {}", body_mode, e)
+                }));
+            }
+        };
+        // urlencoded 表单和 multipart 表单一样用 form-data 匹配
+        if &body_mode == "form-urlencoded" || &body_mode == "form-data" {
+            form_data = v;
+        } else {
+            request_body = v;
+        }
+    }
+
+    record.query = request_query.clone();
+    record.body = request_body.clone();
+    record.form_data = form_data.clone();
+    record.files = files.clone();
+    find_response_data(req, body_mode, request_body, request_query, form_data, files, db_data, record)
+}
+
+
+/// 请求body的最大字节数，_settings.json5 中的 max_body_size，默认为32KB
+pub fn get_max_body_size(settings: &Option<Value>) -> usize {
+    settings.as_ref().and_then(|x| x.get("max_body_size")).and_then(|x| x.as_u64()).unwrap_or(32 * 1024) as usize
+}
+
+
+/// 事件流接口: method 为 SSE，或者 body_mode 为 sse 的 GET、POST 等接口
+pub fn is_sse_api(a_api_data: &db::ApiData) -> bool {
+    a_api_data.method.contains(&"SSE".to_string()) || a_api_data.body_mode.to_lowercase() == "sse"
+}
+
+
+/// 找到请求对应的接口
+fn find_api_data(req: &HttpRequest, db_data: &db::Database) -> Option<db::ApiData> {
+    for (api_url, a_api_data) in &db_data.api_data {
+        if ResourceDef::new(api_url).is_match(req.path()) {
+            let a_api_data = match a_api_data.get(req.method().as_str()).or(a_api_data.get("*")) {
+                Some(v) => v,
+                None => continue
+            };
+            return Some(a_api_data.lock().unwrap().clone());
+        }
+    }
+    None
 }
 
 
 /// 找到对应url 对应请求的数据
 ///
-fn find_response_data(req: &HttpRequest, body_mode: String, request_body: Value, request_query: Value, form_data: Value, db_data: web::Data<Mutex<db::Database>>) -> HttpResponse {
-    let db_data = db_data.lock().unwrap();
-    let api_data = &db_data.api_data;
+fn find_response_data(req: &HttpRequest, body_mode: String, request_body: Value, request_query: Value, form_data: Value, files: Value, db_data: web::Data<Mutex<db::Database>>, record: &mut inspector::RequestRecord) -> HttpResponse {
+    let mut db_guard = db_data.lock().unwrap();
+    let db_data = &mut *db_guard;
     let req_path = req.path();
     let req_method = req.method().as_str();
 
+    // _auth.json5 中设置的登录、退出、获取当前用户接口
+    if let Some(auth_doc) = &mut db_data.auth_doc {
+        oauth::remove_expired_grants(auth_doc);
+        if let Some(response) = auth::auth_api_handle(req, &request_body, &request_query, &form_data, auth_doc) {
+            record.api = req_path.to_string();
+            record.api_name = "auth".to_string();
+            return response;
+        }
+    }
+
+    let api_data = &db_data.api_data;
+    // GraphQL接口在释放数据锁之后执行，生成大量数据时不会阻塞其他请求
+    let mut graphql_call = None;
+
     for (api_url, a_api_data) in api_data {
         // 匹配
         let res = ResourceDef::new(api_url);
         if res.is_match(req_path) {
-            let a_api_data = match a_api_data.get(req_method) {
+            // SSE接口可以用任意方法请求
+            let a_api_data = match a_api_data.get(req_method).or(a_api_data.get("SSE")) {
                 Some(v) => v,
                 None => {
                     match a_api_data.get("*") {
@@ -230,19 +556,53 @@ fn find_response_data(req: &HttpRequest, body_mode: String, request_body: Value,
             };
 
             let a_api_data = a_api_data.lock().unwrap();
+            record.api = api_url.to_string();
+            record.api_name = a_api_data.name.clone();
             if a_api_data.auth {
-                if let Some(auth_valid_errors) = auth_validator(&req, &a_api_data.url, &db_data.auth_doc) {
+                if let Some(auth_valid_errors) = auth_validator(&req, &a_api_data.url, &db_data.auth_doc, &request_body, &form_data) {
                     return HttpResponse::Ok().json(auth_valid_errors);
                 }
             }
 
+            // 当前请求的用户 (token, user, group)，用于按用户匹配test_data和替换返回中的 {{user.id}}
+            let current_user = match &db_data.auth_doc {
+                Some(auth_doc) => {
+                    let token = auth::get_token_from_request(&req, auth_doc, &request_body, &form_data);
+                    auth::find_user_by_token(auth_doc, &token).map(|(user, group)| (token.clone(), user.clone(), group.to_string()))
+                }
+                None => None
+            };
+            if let Some((_, user, group)) = &current_user {
+                record.user = auth::get_public_user(user);
+                record.group = group.to_string();
+            }
+            let render_response = |response: &Value| -> Value {
+                render_user_response(response, &files, current_user.as_ref())
+            };
+
+            // 事件流接口，返回 text/event-stream
+            let is_sse = is_sse_api(&a_api_data);
+
+            // GraphQL接口，query、operationName、variables 在body中，GET请求在query string中
+            if !a_api_data.graphql.is_null() {
+                let graphql_request = if request_body.get("query").is_some() { &request_body } else { &request_query };
+                graphql_call = Some((a_api_data.graphql.clone(), a_api_data.graphql_schema.clone(), a_api_data.test_data.clone(),
+                                     graphql_request.clone(), db_data.custom_types.clone(), current_user.clone()));
+                break;
+            }
+
             let test_data = &a_api_data.test_data;
 
             if let Some(test_data) = test_data.as_array() {
-                for test_case_data in test_data {
+                for (i, test_case_data) in test_data.iter().enumerate() {
                     // 如果在test_data中设置了url，那么就要进行url匹配，如果不设置就不进行
                     let mut is_all_match = true;
 
+                    // 设置了user、group的测试数据，只有对应的用户才能匹配
+                    if !auth::is_test_case_user_match(test_case_data, current_user.as_ref()) {
+                        continue;
+                    }
+
                     if let Some(url) = test_case_data.get("url") {
                         if url != req_path {
                             is_all_match = false;
@@ -291,6 +651,13 @@ fn find_response_data(req: &HttpRequest, body_mode: String, request_body: Value,
                         is_all_match = false;
                     }
 
+                    // 上传文件的信息，只比较设置了的字段，如 files: {avatar: {mime: "image/png"}}
+                    if let Some(v) = test_case_data.get("files") {
+                        if !is_value_contains(&files, v) {
+                            is_all_match = false;
+                        }
+                    }
+
 
                     let v = match test_case_data.get("query") {
                         Some(v) => v,
@@ -307,17 +674,75 @@ fn find_response_data(req: &HttpRequest, body_mode: String, request_body: Value,
                     };
 
                     if is_all_match {
-                        return HttpResponse::Ok().json(case_response);
+                        record.test_case = Some(i);
+                        if is_sse {
+                            let events = match test_case_data.get("events").and_then(|x| x.as_array()) {
+                                Some(events) => events.iter().map(|x| render_response(x)).collect(),
+                                None => vec![json!({"data": render_response(case_response)})]
+                            };
+                            return create_sse_response(&events, test_case_data.get("end"), &db_data.custom_types);
+                        }
+                        return body::create_response(&a_api_data.response_mode, &render_response(case_response));
                     }
                 }
             }
 
-            let x = create_mock_response(&a_api_data.response);
-            return HttpResponse::Ok().json(x);
+            // 有状态的数据，按当前用户保存新增、修改、删除的数据
+            if !a_api_data.store.is_null() {
+                let result = store::handle(&a_api_data.store, api_url, req_path, req_method, &request_body, current_user.as_ref(), &mut db_data.stores);
+                return HttpResponse::Ok().json(render_response(&result));
+            }
+
+            if is_sse {
+                // 没有匹配的test_data时，按sse设置生成count个事件，每个事件的data由response生成
+                let sse = &a_api_data.sse;
+                let count = sse.get("count").and_then(|x| x.as_u64()).unwrap_or(5);
+                let interval = sse.get("interval").and_then(|x| x.as_u64()).unwrap_or(1000);
+                let mut events = Vec::new();
+                for i in 0..count {
+                    let delay = if i == 0 { 0 } else { interval };
+                    let mut event = json!({"id": i + 1, "delay": delay, "response": a_api_data.response.clone()});
+                    if let Some(v) = sse.get("event") {
+                        event["event"] = v.clone();
+                    }
+                    if i == 0 {
+                        if let Some(v) = sse.get("retry") {
+                            event["retry"] = v.clone();
+                        }
+                    }
+                    events.push(event);
+                }
+                return create_sse_response(&events, sse.get("end"), &db_data.custom_types);
+            }
+
+            // binary 返回的response可以直接设置为 _data/ 下的文件
+            if a_api_data.response.is_string() {
+                return body::create_response(&a_api_data.response_mode, &a_api_data.response);
+            }
+
+            let x = if !a_api_data.pagination.is_null() {
+                create_paginated_mock_response(&a_api_data.response, &a_api_data.pagination, &request_query, &db_data.custom_types)
+            } else {
+                try_create_mock_response(&a_api_data.response, &db_data.custom_types).map(Value::Object)
+            };
+            return match x {
+                Ok(x) => body::create_response(&a_api_data.response_mode, &render_response(&x)),
+                Err(e) => HttpResponse::Ok().json(json!({
+                    "code": -1,
+                    "msg": format!("this api address {} mock response error: {}", req_path, e)
+                }))
+            };
         }
     };
 
 
+    if let Some((setting, schema, test_data, graphql_request, types, current_user)) = graphql_call {
+        drop(db_guard);
+        let (result, test_case) = graphql::execute(&setting, schema.as_deref(), &test_data, &graphql_request, &types);
+        record.test_case = test_case;
+        return HttpResponse::Ok().json(render_user_response(&result, &files, current_user.as_ref()));
+    }
+
     HttpResponse::Ok().json(json!({
         "code": - 1,
         "msg": format ! ("this api address {} no api url match", req_path)
@@ -325,6 +750,89 @@ fn find_response_data(req: &HttpRequest, body_mode: String, request_body: Value,
 }
 
 
+/// 替换返回数据中的上传文件和当前用户模板，如 {{files.avatar.url}}、{{user.id}}
+fn render_user_response(response: &Value, files: &Value, current_user: Option<&(String, Value, String)>) -> Value {
+    let response = upload::render_files_template(response, files);
+    match current_user {
+        Some((token, user, group)) => auth::render_user_template(&response, token, user, group),
+        None => response
+    }
+}
+
+
+/// 把一个事件转换为SSE的文本格式
+/// 事件可以设置 event、id、retry，data为固定的数据，response为字段定义，生成mock数据
+fn create_sse_event_text(event: &Value, types: &db::CustomTypes) -> String {
+    let mut text = String::new();
+    if let Some(v) = event.get("retry").and_then(|x| x.as_u64()) {
+        text.push_str(&format!("retry: {}\n", v));
+    }
+    for key in ["id", "event"].iter() {
+        match event.get(*key) {
+            Some(Value::String(v)) => text.push_str(&format!("{}: {}\n", key, v)),
+            Some(Value::Null) | None => (),
+            Some(v) => text.push_str(&format!("{}: {}\n", key, v))
+        }
+    }
+
+    let data = match event.get("data") {
+        Some(v) => v.clone(),
+        None => {
+            match event.get("response") {
+                Some(response) if !response.is_null() => {
+                    match try_create_mock_response(response, types) {
+                        Ok(v) => Value::Object(v),
+                        Err(e) => json!({"code": -1, "msg": format!("sse event mock response error: {}", e)})
+                    }
+                }
+                _ => Value::Null
+            }
+        }
+    };
+    let data = match data {
+        Value::String(v) => v,
+        Value::Null => "".to_string(),
+        v => v.to_string()
+    };
+    // 多行数据每行都要加 data:
+    for line in data.split('\n') {
+        text.push_str(&format!("data: {}\n", line));
+    }
+    text.push('\n');
+    text
+}
+
+
+/// 生成事件流返回，每个事件的 delay 为距离上一个事件的毫秒数
+/// end 为结束事件，如 {event: "done", data: "[DONE]"}，发送完所有事件后关闭连接
+fn create_sse_response(events: &Vec<Value>, end: Option<&Value>, types: &db::CustomTypes) -> HttpResponse {
+    let mut items: Vec<(u64, String)> = Vec::new();
+    for event in events {
+        let delay = event.get("delay").and_then(|x| x.as_u64()).unwrap_or(0);
+        items.push((delay, create_sse_event_text(event, types)));
+    }
+    if let Some(end) = end {
+        if !end.is_null() && end.as_bool() != Some(false) {
+            let end = if end.is_object() { end.clone() } else { json!({"event": "end", "data": end}) };
+            let delay = end.get("delay").and_then(|x| x.as_u64()).unwrap_or(0);
+            items.push((delay, create_sse_event_text(&end, types)));
+        }
+    }
+
+    let stream = futures::stream::iter(items).then(|(delay, text)| async move {
+        if delay > 0 {
+            actix_rt::time::delay_for(Duration::from_millis(delay)).await;
+        }
+        Ok::<_, Error>(web::Bytes::from(text))
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .streaming(Box::pin(stream))
+}
+
+
 /// 把request_query 转换为api query的格式
 fn parse_request_query_to_api_query_format(request_query: &Value, api_query: &Value) -> Value {
     if api_query.is_null() {
@@ -377,7 +885,24 @@ fn parse_request_query_to_api_query_format(request_query: &Value, api_query: &Va
 /// 判断两个serde value的值是否相等
 /// 只要value2中要求的每个字段，value1中都有，就表示相等, 也就是说value1的字段可能会比value2多
 /// 改为两个value1，value2中的字段必须完全相等
-fn is_value_equal(value1: &Value, value2: &Value) -> bool {
+/// pattern中设置了的字段都相等就匹配，没有设置的字段不比较
+pub fn is_value_contains(value: &Value, pattern: &Value) -> bool {
+    match (value, pattern) {
+        (Value::Object(value), Value::Object(pattern)) => {
+            pattern.iter().all(|(k, v)| match value.get(k) {
+                Some(value_v) => is_value_contains(value_v, v),
+                None => v.is_null()
+            })
+        }
+        (Value::Array(value), Value::Array(pattern)) => {
+            value.len() == pattern.len() && value.iter().zip(pattern).all(|(x, p)| is_value_contains(x, p))
+        }
+        _ => value == pattern
+    }
+}
+
+
+pub fn is_value_equal(value1: &Value, value2: &Value) -> bool {
     if value1.is_null() && value2.is_null() {
         return true;
     }
@@ -443,79 +968,13 @@ fn is_value_equal(value1: &Value, value2: &Value) -> bool {
                 }
             }
         }
-        _ => {
-            println!("Invalid Json Struct {:?}", value1);
-        }
+        // 字符串、数字等直接比较，如 text、xml 的body
+        _ => return value1 == value2
     }
     false
 }
 
 
-/// 从请求中获取form_data里面的数据以及文件上传
-async fn get_request_form_data(request_form_data: Option<Multipart>) -> Value {
-    let mut form_data: Map<String, Value> = Map::new();
-    if let Some(mut payload) = request_form_data {
-        // 如果是文件上传
-        while let Some(item) = payload.next().await {
-            if let Ok(mut field) = item {
-                let content_type = match field.content_disposition() {
-                    Some(v) => v,
-                    None => {
-                        break;
-                    }
-                };
-                let x = field.headers().clone();
-                let x = x.get("content-disposition").unwrap().to_str().unwrap();
-                let re = Regex::new(r#"form-data; name="\w+""#).unwrap();
-
-                let mut field_name = "";
-                if let Some(m) = re.find(x) {
-                    field_name = &x[m.start() + 17..m.end() - 1];
-                };
-
-                let mut filename = "";
-                if let Some(f) = content_type.get_filename() {
-                    filename = f;
-                }
-
-                match std::fs::create_dir_all("./_data/_upload") {
-                    Ok(_) => (),
-                    Err(e) => {
-                        println!("create folder failed _data/_upload {:?}", e);
-                    }
-                }
-
-                let filepath = format!("./_data/_upload/{}", filename);
-                let filepath2 = &format!("./_data/_upload/{}", filename);
-
-                if let Ok(mut f) = web::block(|| std::fs::File::create(filepath)).await {
-                    while let Some(chunk) = field.next().await {
-                        let data = chunk.unwrap();
-
-                        if let Ok(_) = f.write_all(&data) {
-                            form_data.insert(field_name.to_string(), Value::String(filename.to_string()));
-                            form_data.insert(format!("__{}", field_name), Value::String(format!("/_upload/{}", filename)));
-                        } else {
-                            println!("create file error {}", filepath2);
-                        }
-                    }
-                } else {
-                    while let Some(chunk) = field.next().await {
-                        let data = chunk.unwrap();
-                        let x = data.to_vec();
-                        let v = std::str::from_utf8(&x).unwrap();
-                        form_data.insert(field_name.to_string(), Value::String(v.to_string()));
-                    }
-                }
-                continue;
-            }
-            break;
-        }
-    }
-    Value::Object(form_data)
-}
-
-
 /// 获取请求的request_body
 fn get_request_body_mode(req: &HttpRequest) -> String {
     let req_method = req.method().as_str();
@@ -523,25 +982,10 @@ fn get_request_body_mode(req: &HttpRequest) -> String {
         return "".to_string();
     }
 
-    if let Some(head_value) = req.headers().get("content-type") {
-        if let Ok(value_str) = head_value.to_str() {
-            if value_str == "application/json" {
-                return "json".to_string();
-            } else if value_str.starts_with("multipart/form-data;") {
-                return "form-data".to_string();
-            } else if value_str == "text/plain" {
-                return "text".to_string();
-            } else if value_str == "application/javascript" {
-                return "javascript".to_string();
-            } else if value_str == "text/html" {
-                return "html".to_string();
-            } else if value_str == "application/xml" {
-                return "xml".to_string();
-            }
-        }
+    match req.headers().get("content-type").and_then(|x| x.to_str().ok()) {
+        Some(v) => body::get_body_mode_of_content_type(v),
+        None => "".to_string()
     }
-
-    "".to_string()
 }
 
 /// 判断是否是websocket连接请求
@@ -561,46 +1005,103 @@ fn is_websocket_connect(req: &HttpRequest) -> bool {
 }
 
 
-fn get_token_from_request(req: &HttpRequest) -> String {
-    let headers = req.headers();
-    let mut token = "";
-    if let Some(x) = headers.get("authorization") {
-        if let Ok(x) = x.to_str() {
-            token = x.trim_start_matches("Bearer ").trim();
+/// 判断权限设置中的地址是否匹配接口地址
+/// re: 开头为正则，包含 {} 的按路由匹配，包含 * 的按通配符匹配，* 匹配一级路径，** 和结尾的 /* 匹配剩余所有路径
+fn is_perm_url_match(pattern: &str, url: &str) -> bool {
+    if pattern == url || pattern == "*" {
+        return true;
+    }
+    if pattern.starts_with("re:") {
+        return match Regex::new(pattern.trim_start_matches("re:")) {
+            Ok(re) => re.is_match(url),
+            Err(_) => false
+        };
+    }
+    if pattern.contains("{") {
+        return ResourceDef::new(pattern).is_match(url);
+    }
+    if pattern.contains("*") {
+        let mut re_str = String::from("^");
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            if chars[i] == '*' {
+                if i + 1 < chars.len() && chars[i + 1] == '*' {
+                    re_str.push_str(".*");
+                    i += 1;
+                } else if i + 1 == chars.len() && i > 0 && chars[i - 1] == '/' {
+                    re_str.push_str(".*");
+                } else {
+                    re_str.push_str("[^/]*");
+                }
+            } else {
+                re_str.push_str(&regex::escape(&chars[i].to_string()));
+            }
+            i += 1;
         }
+        re_str.push('$');
+        return match Regex::new(&re_str) {
+            Ok(re) => re.is_match(url),
+            Err(_) => false
+        };
     }
-    token.to_string()
+    false
 }
 
 
-/// 判断是否有某个url的权限
-fn is_has_perm(url: &str, method: &str, perms: &HashMap<String, HashSet<String>>) -> bool {
-    if let Some(methods) = perms.get("*") {
-        // 如果有所有网址权限，再判断方法上的权限是否满足
-        if methods.contains(method) || methods.contains("*") || methods.len() == 0 {
-            return true;
+fn is_method_match(methods: &HashSet<String>, method: &str) -> bool {
+    methods.contains(method) || methods.contains("*") || methods.len() == 0
+}
+
+
+/// 找到匹配url和方法的权限设置，先匹配完全相同的地址，再匹配 *，最后按地址长度从长到短匹配通配符、路由和正则
+fn find_perm_rule<'a>(url: &str, method: &str, perms: &'a HashMap<String, HashSet<String>>) -> Option<(&'a String, &'a HashSet<String>)> {
+    if let Some((pattern, methods)) = perms.get_key_value(url) {
+        if is_method_match(methods, method) {
+            return Some((pattern, methods));
+        }
+    }
+    if let Some((pattern, methods)) = perms.get_key_value("*") {
+        if is_method_match(methods, method) {
+            return Some((pattern, methods));
         }
     }
 
-    match perms.get(url) {
-        Some(methods) => {
-            // 如果有所有网址权限，再判断方法上的权限是否满足
-            if methods.contains(method) || methods.contains("*") || methods.len() == 0 {
-                return true;
-            }
+    let mut patterns: Vec<(&String, &HashSet<String>)> = perms.iter().filter(|(k, _)| k.as_str() != url && k.as_str() != "*").collect();
+    patterns.sort_by(|a, b| b.0.len().cmp(&a.0.len()).then(a.0.cmp(b.0)));
+    for (pattern, methods) in patterns {
+        if is_method_match(methods, method) && is_perm_url_match(pattern, url) {
+            return Some((pattern, methods));
         }
-        None => return false
     }
+    None
+}
 
-    false
+
+/// 判断是否有某个url的权限
+fn is_has_perm(url: &str, method: &str, perms: &HashMap<String, HashSet<String>>) -> bool {
+    find_perm_rule(url, method, perms).is_some()
 }
 
 
 /// 判断用户是否有当前接口访问权限，如果有权限返回None，如果没有权限 返回报错信息
-fn auth_validator<'a>(req: &HttpRequest, api_url: &str, auth_doc: &'a Option<db::AuthDoc>) -> Option<&'a Value> {
-    let token = get_token_from_request(req);
-
+fn auth_validator<'a>(req: &HttpRequest, api_url: &str, auth_doc: &'a Option<db::AuthDoc>, request_body: &Value, form_data: &Value) -> Option<&'a Value> {
     if let Some(auth_data) = auth_doc {
+        // Session模式先校验会话是否过期和csrf token，再按登录时的权限组判断权限
+        if auth_data.session.is_some() {
+            let session = match auth::check_session(req, auth_data, request_body, form_data) {
+                Ok(v) => v,
+                Err(response) => return Some(response)
+            };
+            for group in &auth_data.groups {
+                if group.name == session.group && group.users.contains_key(&session.user_token) {
+                    return group_perm_validator(group, api_url, req.method().as_str(), &auth_data.no_perm_response);
+                }
+            }
+            return Some(&auth_data.no_perm_response);
+        }
+
+        let token = auth::get_token_from_request(req, auth_data, request_body, form_data);
         let no_perm_response = &auth_data.no_perm_response;
 
         // 判断token是否符合生成规则
@@ -608,22 +1109,51 @@ fn auth_validator<'a>(req: &HttpRequest, api_url: &str, auth_doc: &'a Option<db:
             return Some(no_perm_response);
         }
 
+        // 已经退出登录的token
+        if auth_data.revoked_tokens.contains(&token) {
+            return Some(no_perm_response);
+        }
+
         let req_method = req.method().as_str();
 
-        for group in &auth_data.groups {
-            let mut group_no_perm_response = &group.no_perm_response;
-            if group_no_perm_response.is_null() {
-                group_no_perm_response = no_perm_response;
+        if let Some(grant) = auth_data.oauth_access_tokens.get(&token) {
+            // OAuth access_token，先判断权限组，再判断授权的scope
+            if grant.expires_at < auth::now_timestamp() {
+                return Some(no_perm_response);
             }
+            for group in &auth_data.groups {
+                if group.name == grant.group {
+                    let result = group_perm_validator(group, api_url, req_method, no_perm_response);
+                    if result.is_some() {
+                        return result;
+                    }
+                    return oauth_scope_validator(auth_data, &grant.scope, api_url, req_method);
+                }
+            }
+            return Some(no_perm_response);
+        }
+
+        if let Some(jwt) = &auth_data.jwt {
+            // JWT模式，校验签名、过期时间、audience，再按token中的group判断权限
+            return match auth::decode_jwt_token(jwt, &token, true) {
+                Ok(claims) => {
+                    let group_name = claims.get("group").and_then(|x| x.as_str()).unwrap_or("");
+                    for group in &auth_data.groups {
+                        if group.name == group_name {
+                            return group_perm_validator(group, api_url, req_method, no_perm_response);
+                        }
+                    }
+                    Some(no_perm_response)
+                }
+                Err(auth::JwtError::Expired) => Some(&jwt.expired_response),
+                Err(auth::JwtError::Invalid) => Some(&jwt.invalid_response)
+            };
+        }
+
+        for group in &auth_data.groups {
             for (t, _) in &group.users {
                 if t == &token {
-                    // 判断请求是否在权限范围内
-                    if is_has_perm(api_url, req_method, &group.no_perms) {
-                        return Some(group_no_perm_response);
-                    } else if is_has_perm(api_url, req_method, &group.has_perms) {
-                        return None;
-                    }
-                    return Some(group_no_perm_response);
+                    return group_perm_validator(group, api_url, req_method, no_perm_response);
                 }
             }
         }
@@ -633,6 +1163,132 @@ fn auth_validator<'a>(req: &HttpRequest, api_url: &str, auth_doc: &'a Option<db:
 }
 
 
+/// 判断OAuth授权的scope是否有接口的访问权限，没有在oauth.scopes中设置的scope不限制接口
+fn oauth_scope_validator<'a>(auth_data: &'a db::AuthDoc, scope: &str, api_url: &str, req_method: &str) -> Option<&'a Value> {
+    let oauth = match &auth_data.oauth {
+        Some(v) => v,
+        None => return None
+    };
+    let scopes: Vec<&db::OAuthScope> = scope.split_whitespace().filter_map(|x| oauth.scopes.get(x)).collect();
+    if scopes.is_empty() {
+        return None;
+    }
+    for s in scopes {
+        if !is_has_perm(api_url, req_method, &s.no_perms) && is_has_perm(api_url, req_method, &s.has_perms) {
+            return None;
+        }
+    }
+    Some(&auth_data.no_perm_response)
+}
+
+
+/// 判断权限组是否有接口的访问权限，没有权限的返回报错信息
+pub fn group_perm_validator<'a>(group: &'a db::AuthData, api_url: &str, req_method: &str, no_perm_response: &'a Value) -> Option<&'a Value> {
+    let mut group_no_perm_response = &group.no_perm_response;
+    if group_no_perm_response.is_null() {
+        group_no_perm_response = no_perm_response;
+    }
+    // 判断请求是否在权限范围内
+    if is_has_perm(api_url, req_method, &group.no_perms) {
+        return Some(group_no_perm_response);
+    } else if is_has_perm(api_url, req_method, &group.has_perms) {
+        return None;
+    }
+    Some(group_no_perm_response)
+}
+
+
+#[derive(Deserialize)]
+pub struct PermExplainRequest {
+    token: String,
+    url: String,
+    method: Option<String>,
+}
+
+
+/// 说明某个token访问某个接口时，是哪条权限设置允许或者拒绝的
+/// GET /__api_docs/auth/explain/?token=xxx&url=/user/1/&method=GET
+pub async fn explain_perm(req_get: web::Query<PermExplainRequest>, data: web::Data<Mutex<db::Database>>) -> HttpResponse {
+    let mut data = data.lock().unwrap();
+    if let Some(auth_doc) = &mut data.auth_doc {
+        oauth::remove_expired_grants(auth_doc);
+    }
+    let auth_doc = match &data.auth_doc {
+        Some(v) => v,
+        None => return HttpResponse::Ok().json(json!({"code": -1, "msg": "_auth.json5 is not loaded"}))
+    };
+    let method = req_get.method.clone().unwrap_or("GET".to_string()).to_uppercase();
+    let url = req_get.url.as_str();
+
+    // 找到请求地址对应的接口文档地址
+    let mut api_url = url.to_string();
+    let mut auth_required = Value::Null;
+    for (a_api_url, a_api_data) in &data.api_data {
+        if ResourceDef::new(a_api_url).is_match(url) {
+            api_url = a_api_url.to_string();
+            if let Some(a) = a_api_data.get(&method).or(a_api_data.get("*")) {
+                auth_required = json!(a.lock().unwrap().auth);
+            }
+            break;
+        }
+    }
+
+    let rule_json = |from: &str, rule: Option<(&String, &HashSet<String>)>| -> Value {
+        match rule {
+            Some((pattern, methods)) => {
+                let mut methods: Vec<&String> = methods.iter().collect();
+                methods.sort();
+                json!({"from": from, "pattern": pattern, "methods": methods})
+            }
+            None => Value::Null
+        }
+    };
+
+    let mut result = json!({"code": 1, "url": url, "api_url": api_url, "method": method, "auth_required": auth_required});
+
+    let (group_name, scope) = match auth_doc.oauth_access_tokens.get(&req_get.token) {
+        Some(grant) => (Some(grant.group.clone()), Some(grant.scope.clone())),
+        None => (auth::find_user_by_token(auth_doc, &req_get.token).map(|(_, g)| g.to_string()), None)
+    };
+    let group = match group_name.and_then(|g| auth_doc.groups.iter().find(|x| x.name == g)) {
+        Some(v) => v,
+        None => {
+            result["allowed"] = json!(false);
+            result["reason"] = json!("token does not belong to any auth group");
+            return HttpResponse::Ok().json(result);
+        }
+    };
+    result["group"] = json!(group.name);
+
+    if let Some(rule) = find_perm_rule(&api_url, &method, &group.no_perms) {
+        result["allowed"] = json!(false);
+        result["rule"] = rule_json("no_perms", Some(rule));
+        result["reason"] = json!(format!("denied by no_perms of group {}", group.name));
+        return HttpResponse::Ok().json(result);
+    }
+    let rule = match find_perm_rule(&api_url, &method, &group.has_perms) {
+        Some(v) => v,
+        None => {
+            result["allowed"] = json!(false);
+            result["reason"] = json!(format!("no has_perms of group {} matches", group.name));
+            return HttpResponse::Ok().json(result);
+        }
+    };
+    result["allowed"] = json!(true);
+    result["rule"] = rule_json("has_perms", Some(rule));
+    result["reason"] = json!(format!("allowed by has_perms of group {}", group.name));
+
+    if let Some(scope) = scope {
+        if oauth_scope_validator(auth_doc, &scope, &api_url, &method).is_some() {
+            result["allowed"] = json!(false);
+            result["reason"] = json!(format!("oauth scope {} does not allow this api", scope));
+        }
+        result["scope"] = json!(scope);
+    }
+    HttpResponse::Ok().json(result)
+}
+
+
 pub fn get_field_type(field_attr: &Value) -> String {
     let field_type = match field_attr.get("type") {
         Some(v) => v.as_str().unwrap(),
@@ -669,20 +1325,22 @@ pub fn get_field_type(field_attr: &Value) -> String {
 
 macro_rules! get_mock_enum_value {
     ( $enum_data:expr, $rng:expr, $result:expr, $field_key:expr ) => {
-    let list = $enum_data.as_array().unwrap();
-    let n = $rng.gen_range(0, list.len());
-    let v = &list[n];
-    match v {
-        Value::Object(v2) => {
-            if let Some(v3) = v2.get("-value") {
-                $result.insert($field_key.clone(), v3.clone());
-            } else {
-                $result.insert($field_key.clone(), v.clone());
+    // 按照weight权重随机选取一个枚举值，只返回value
+    let items = db::parse_enum_items($enum_data);
+    let total_weight: u64 = items.iter().map(|x| x.2).sum();
+    if total_weight > 0 {
+        let mut n = $rng.gen_range(0, total_weight);
+        for (value, _, weight) in &items {
+            if n < *weight {
+                $result.insert($field_key.clone(), value.clone());
+                break;
             }
+            n -= *weight;
         }
-        _ => {
-            $result.insert($field_key.clone(), v.clone());
-        }
+    } else if items.len() > 0 {
+        // 所有权重都为0的时候，平均选取
+        let n = $rng.gen_range(0, items.len());
+        $result.insert($field_key.clone(), items[n].0.clone());
     }
     };
 }
@@ -759,19 +1417,58 @@ macro_rules! get_string_value {
 
 
 
-/// 根据response定义生成返回给前端的mock数据
-///
-pub fn create_mock_response(response_model: &Value) -> Map<String, Value> {
+/// 根据response定义生成mock数据，如果有unique等约束无法满足，返回错误信息
+/// types 为自定义的字段类型，字段中的自定义类型在生成时展开
+pub fn try_create_mock_response(response_model: &Value, types: &db::CustomTypes) -> Result<Map<String, Value>, String> {
+    let mut errors = Vec::new();
+    let result = create_mock_object(response_model, &[], types, &mut errors);
+    if errors.len() > 0 {
+        return Err(errors.join("; "));
+    }
+    Ok(result)
+}
+
+
+/// 生成一个对象的mock数据
+/// parent_scopes 是上级已经生成的数据，用于字段中 $../field 这样的引用
+/// errors 收集生成过程中无法满足的约束
+fn create_mock_object(response_model: &Value, parent_scopes: &[&Map<String, Value>], types: &db::CustomTypes, errors: &mut Vec<String>) -> Map<String, Value> {
     let mut result: Map<String, Value> = Map::new();
     if response_model.is_object() {
         let response_model = response_model.as_object().unwrap();
-        let mut rng = thread_rng();
+        let mut rng = mock::rng();
+
+        // 按照字段之间的依赖顺序生成，被引用的字段先生成
+        let field_keys = match mock::expr::field_order(response_model) {
+            Ok(keys) => keys,
+            Err(cycle) => {
+                errors.push(format!("field dependency cycle: {}", cycle.join(" -> ")));
+                return result;
+            }
+        };
 
-        for (field_key, field_attr) in response_model {
+        for field_key in &field_keys {
             if field_key == "-type" || field_key == "-name" || field_key == "-desc" || field_key == "-length" || field_key == "-min_length" || field_key == "-max_length" {
                 continue;
             }
 
+            let is_computed_value = match response_model[field_key].get("value") {
+                Some(v) => mock::expr::is_expr(v),
+                None => false
+            };
+            // 把 min_value: "$start_date" 这样的引用替换为已经生成的字段值
+            let field_attr = {
+                let mut scopes: Vec<&Map<String, Value>> = parent_scopes.to_vec();
+                scopes.push(&result);
+                mock::expr::resolve_field_attr(&response_model[field_key], &scopes)
+            };
+            // 自定义类型 type: "Money" 展开为实际的字段定义
+            let field_attr = match db::resolve_custom_type(&field_attr, types) {
+                Some(v) => v,
+                None => field_attr
+            };
+            let field_attr = &field_attr;
+
             let field_type = get_field_type(field_attr);
             let field_type = field_type.as_str();
 
@@ -794,10 +1491,21 @@ pub fn create_mock_response(response_model: &Value) -> Map<String, Value> {
                 }
             }
 
+            if let Some((start, step, is_global, key)) = get_sequence_setting(field_key, field_attr) {
+                // 自增字段，跨请求的从全局计数器中获取，数组里面的在生成数组后按顺序设置
+                if is_global {
+                    result.insert(field_key.clone(), json!(mock::basic::sequence_next(&key, start, step)));
+                } else {
+                    result.insert(field_key.clone(), json!(start));
+                }
+                continue;
+            }
+
             if let Some(value1) = field_attr.get("value") {
                 // 如果设定了value，那么就只返回一个固定的值
-                if let Some(value1) = value1.as_i64() {
-                    result.insert(field_key.clone(), Value::from(value1));
+                // 计算字段，如 value: "=sum(items[].price)" 也在这里返回
+                if !value1.is_null() || is_computed_value {
+                    result.insert(field_key.clone(), value1.clone());
                     continue;
                 }
             }
@@ -1018,13 +1726,21 @@ pub fn create_mock_response(response_model: &Value) -> Map<String, Value> {
                     result.insert(field_key.clone(), Value::String(mock::basic::image(size, foreground, background, format, text)));
                 }
                 "object" => {
-                    let v = create_mock_response(field_attr);
+                    let v = {
+                        let mut scopes: Vec<&Map<String, Value>> = parent_scopes.to_vec();
+                        scopes.push(&result);
+                        create_mock_object(field_attr, &scopes, types, errors)
+                    };
                     result.insert(field_key.clone(), Value::Object(v));
                 }
                 "array" => {
                     if let Some(field_attr_array) = field_attr.as_array() {
                         if field_attr_array.len() > 0 {
-                            let field_attr_one = &field_attr_array[0];
+                            let field_attr_one = match db::resolve_custom_type(&field_attr_array[0], types) {
+                                Some(v) => v,
+                                None => field_attr_array[0].clone()
+                            };
+                            let field_attr_one = &field_attr_one;
                             let field_type2 = get_field_type(field_attr_one);
 
                             let mut length = 0;
@@ -1052,31 +1768,114 @@ pub fn create_mock_response(response_model: &Value) -> Map<String, Value> {
                                 length = rng.gen_range(min_length, max_length);
                             }
 
-                            match field_type2.to_lowercase().as_str() {
+                            let mut scopes: Vec<&Map<String, Value>> = parent_scopes.to_vec();
+                            scopes.push(&result);
+                            let vec = match field_type2.to_lowercase().as_str() {
                                 "object" => {
+                                    // unique: true 的字段，在数组里面不能重复
+                                    let unique_fields = get_unique_fields(field_attr_one);
+                                    let mut seen: HashMap<String, HashSet<String>> = HashMap::new();
+                                    for uf in &unique_fields {
+                                        if let Some(e) = check_unique_enum_size(&field_attr_one[uf], length) {
+                                            errors.push(format!("{}[].{} {}", field_key, uf, e));
+                                        }
+                                        seen.insert(uf.to_string(), HashSet::new());
+                                    }
+
                                     let mut vec = Vec::with_capacity(length as usize);
                                     while length > 0 {
-                                        let v = create_mock_response(field_attr_one);
+                                        let mut v = create_mock_object(field_attr_one, &scopes, types, errors);
+                                        for uf in &unique_fields {
+                                            let mut tries = 0;
+                                            loop {
+                                                let key = match v.get(uf) {
+                                                    Some(x) => x.to_string(),
+                                                    None => break
+                                                };
+                                                if seen.get_mut(uf).unwrap().insert(key) {
+                                                    break;
+                                                }
+                                                tries += 1;
+                                                if tries > UNIQUE_MAX_TRIES {
+                                                    errors.push(format!("{}[].{} can not generate a unique value", field_key, uf));
+                                                    break;
+                                                }
+                                                // 只重新生成重复的字段
+                                                let mut one = Map::new();
+                                                one.insert(uf.to_string(), field_attr_one[uf].clone());
+                                                let v2 = create_mock_object(&Value::Object(one), &scopes, types, errors);
+                                                if let Some(x) = v2.get(uf) {
+                                                    v.insert(uf.to_string(), x.clone());
+                                                }
+                                            }
+                                        }
                                         vec.push(Value::Object(v));
                                         length -= 1;
                                     }
-                                    result.insert(field_key.clone(), Value::Array(vec));
+
+                                    // 在数组里面按顺序自增的字段
+                                    if let Some(field_attr_obj) = field_attr_one.as_object() {
+                                        for (k, attr) in field_attr_obj {
+                                            if let Some((start, step, is_global, _)) = get_sequence_setting(k, attr) {
+                                                if !is_global {
+                                                    for (i, item) in vec.iter_mut().enumerate() {
+                                                        if let Some(item) = item.as_object_mut() {
+                                                            if item.contains_key(k) {
+                                                                item.insert(k.to_string(), json!(start + step * i as i64));
+                                                            }
+                                                        }
+                                                    }
+                                                }
+                                            }
+                                        }
+                                    }
+                                    vec
                                 }
                                 "array" | _ => {
+                                    let is_unique = match field_attr_one.get("unique") {
+                                        Some(v) => v.as_bool().unwrap_or(false),
+                                        None => false
+                                    };
+                                    if is_unique {
+                                        if let Some(e) = check_unique_enum_size(field_attr_one, length) {
+                                            errors.push(format!("{}[] {}", field_key, e));
+                                        }
+                                    }
+                                    let mut seen: HashSet<String> = HashSet::new();
+
                                     let mut result2: Map<String, Value> = Map::new();
                                     result2.insert("key".to_string(), field_attr_one.clone());
                                     let mut vec = Vec::with_capacity(length as usize);
                                     while length > 0 {
-                                        let v = create_mock_response(&Value::Object({ &result2 }.clone()));
-                                        if v.contains_key("key") {
-                                            vec.push(v["key"].clone());
+                                        let mut tries = 0;
+                                        loop {
+                                            let v = create_mock_object(&Value::Object({ &result2 }.clone()), &scopes, types, errors);
+                                            if let Some(x) = v.get("key") {
+                                                if is_unique && !seen.insert(x.to_string()) {
+                                                    tries += 1;
+                                                    if tries <= UNIQUE_MAX_TRIES {
+                                                        continue;
+                                                    }
+                                                    errors.push(format!("{}[] can not generate a unique value", field_key));
+                                                }
+                                                vec.push(x.clone());
+                                            }
+                                            break;
                                         }
                                         length -= 1;
                                     }
 
-                                    result.insert(field_key.clone(), Value::Array(vec));
+                                    if let Some((start, step, is_global, _)) = get_sequence_setting(field_key, field_attr_one) {
+                                        if !is_global {
+                                            for (i, item) in vec.iter_mut().enumerate() {
+                                                *item = json!(start + step * i as i64);
+                                            }
+                                        }
+                                    }
+                                    vec
                                 }
-                            }
+                            };
+                            result.insert(field_key.clone(), Value::Array(vec));
                         }
                     }
                 }
@@ -1091,4 +1890,272 @@ pub fn create_mock_response(response_model: &Value) -> Map<String, Value> {
 }
 
 
+/// unique字段重新生成的最多次数
+const UNIQUE_MAX_TRIES: usize = 100;
+
+
+/// 获取对象中设置了 unique: true 的字段
+fn get_unique_fields(model: &Value) -> Vec<String> {
+    let mut fields = Vec::new();
+    if let Some(model) = model.as_object() {
+        for (k, v) in model {
+            if let Some(unique) = v.get("unique") {
+                if unique.as_bool() == Some(true) {
+                    fields.push(k.to_string());
+                }
+            }
+        }
+    }
+    fields
+}
+
+
+/// unique字段如果设置了enum，enum的数量不能少于数组的长度
+fn check_unique_enum_size(field_attr: &Value, length: u64) -> Option<String> {
+    if let Some(enum_data) = field_attr.get("enum") {
+        let n = db::parse_enum_items(enum_data).len() as u64;
+        if n < length {
+            return Some(format!("is unique, but enum has only {} values for {} items", n, length));
+        }
+    }
+    None
+}
+
+
+/// 获取字段的自增设置，返回 (start, step, 是否跨请求自增, 计数器名称)
+/// sequence: true 或者 sequence: {start: 1, step: 1, scope: "global", key: "order_id"}
+fn get_sequence_setting(field_key: &str, field_attr: &Value) -> Option<(i64, i64, bool, String)> {
+    let sequence = field_attr.get("sequence")?;
+    match sequence {
+        Value::Bool(true) => Some((1, 1, false, field_key.to_string())),
+        Value::Object(setting) => {
+            let start = setting.get("start").and_then(|x| x.as_i64()).unwrap_or(1);
+            let step = setting.get("step").and_then(|x| x.as_i64()).unwrap_or(1);
+            let is_global = setting.get("scope").and_then(|x| x.as_str()) == Some("global");
+            let key = match setting.get("key").and_then(|x| x.as_str()) {
+                Some(v) => v.to_string(),
+                None => field_key.to_string()
+            };
+            Some((start, step, is_global, key))
+        }
+        _ => None
+    }
+}
+
+
+/// 获取分页设置里面的数字
+fn get_pagination_number(pagination: &Value, key: &str, default_value: i64) -> i64 {
+    match pagination.get(key) {
+        Some(v) => v.as_i64().unwrap_or(default_value),
+        None => default_value
+    }
+}
+
+
+/// 从query中获取分页参数，query里面的值可能是字符串
+fn get_query_number(request_query: &Value, key: &str) -> Option<i64> {
+    match request_query.get(key) {
+        Some(Value::String(v)) => v.trim().parse::<i64>().ok(),
+        Some(v) => v.as_i64(),
+        None => None
+    }
+}
+
+
+/// 把 data.items 这样的路径转换为json pointer
+fn field_path_to_pointer(path: &str) -> String {
+    format!("/{}", path.trim().replace(".", "/"))
+}
+
+
+/// 按照 data.total 这样的路径设置值，中间的对象不存在时自动创建
+fn set_value_by_path(value: &mut Value, path: &str, v: Value) {
+    let keys: Vec<&str> = path.split('.').collect();
+    let mut current = value;
+    for (i, key) in keys.iter().enumerate() {
+        if !current.is_object() {
+            *current = Value::Object(Map::new());
+        }
+        let obj = current.as_object_mut().unwrap();
+        if i == keys.len() - 1 {
+            obj.insert(key.to_string(), v);
+            return;
+        }
+        current = obj.entry(key.to_string()).or_insert(Value::Object(Map::new()));
+    }
+}
+
+
+/// 每页最多的数据条数
+const MAX_PAGE_SIZE: i64 = 1000;
+
 
+/// 设置了seed的分页列表，第 offset + i 条数据用 seed + offset + i 生成
+/// 数组中按顺序自增的字段从 offset 开始计算，翻页时继续增加
+fn create_seeded_list_items(model: &Value, list_pointer: &str, seed: u64, offset: i64, count: i64, types: &db::CustomTypes) -> Result<Vec<Value>, String> {
+    let mut model = model.clone();
+    let item_model = match model.pointer_mut(&format!("{}/0", list_pointer)) {
+        Some(v) => {
+            if let Some(item) = v.as_object_mut() {
+                item.insert("-length".to_string(), json!(1));
+            }
+            v.clone()
+        }
+        None => return Ok(Vec::new())
+    };
+
+    let mut items = Vec::with_capacity(count as usize);
+    for i in 0..count {
+        let index = offset.saturating_add(i);
+        let one = mock::with_seed(seed.wrapping_add(index as u64), || try_create_mock_response(&model, types))?;
+        let mut item = match Value::Object(one).pointer(&format!("{}/0", list_pointer)) {
+            Some(v) => v.clone(),
+            None => continue
+        };
+        if let (Some(item), Some(item_model)) = (item.as_object_mut(), item_model.as_object()) {
+            for (k, attr) in item_model {
+                if let Some((start, step, false, _)) = get_sequence_setting(k, attr) {
+                    if item.contains_key(k) {
+                        item.insert(k.to_string(), json!(start.saturating_add(step.saturating_mul(index))));
+                    }
+                }
+            }
+        }
+        items.push(item);
+    }
+    Ok(items)
+}
+
+
+/// 分页的列表接口，根据query中的page、page_size返回对应数量的数据
+/// pagination: {
+///     page_param: "page", page_size_param: "page_size", first_page: 1,
+///     default_page_size: 10, max_page_size: 100,
+///     total: 95 或者 min_total: 0, max_total: 200, seed: 1,
+///     list: "data.items", total_key: "data.total", page_key: "data.page",
+///     page_size_key: "data.page_size", has_next_key: "data.has_next", page_count_key: "data.page_count"
+/// }
+/// 设置了seed的时候，总数和每一条数据每次请求都一样，第n条数据用 seed + n 生成，翻页时数据保持一致
+pub fn create_paginated_mock_response(response_model: &Value, pagination: &Value, request_query: &Value, types: &db::CustomTypes) -> Result<Value, String> {
+    let page_param = pagination.get("page_param").and_then(|x| x.as_str()).unwrap_or("page");
+    let page_size_param = pagination.get("page_size_param").and_then(|x| x.as_str()).unwrap_or("page_size");
+    let first_page = get_pagination_number(pagination, "first_page", 1);
+    let mut default_page_size = get_pagination_number(pagination, "default_page_size", 10);
+    if default_page_size <= 0 {
+        default_page_size = 10;
+    }
+    // max_page_size 小于等于0时不限制，但是也不能超过 MAX_PAGE_SIZE
+    let mut max_page_size = get_pagination_number(pagination, "max_page_size", 100);
+    if max_page_size <= 0 || max_page_size > MAX_PAGE_SIZE {
+        max_page_size = MAX_PAGE_SIZE;
+    }
+
+    let mut page = get_query_number(request_query, page_param).unwrap_or(first_page);
+    if page < first_page {
+        page = first_page;
+    }
+    let mut page_size = get_query_number(request_query, page_size_param).unwrap_or(default_page_size);
+    if page_size <= 0 {
+        page_size = default_page_size;
+    }
+    if page_size > max_page_size {
+        page_size = max_page_size;
+    }
+
+    let seed = pagination.get("seed").and_then(|x| x.as_u64());
+    let total = match pagination.get("total").and_then(|x| x.as_i64()) {
+        Some(v) => v.max(0),
+        None => {
+            let min_total = get_pagination_number(pagination, "min_total", 0).max(0);
+            let max_total = get_pagination_number(pagination, "max_total", 100);
+            if max_total <= min_total {
+                min_total
+            } else {
+                let max_total = max_total.saturating_add(1);
+                match seed {
+                    Some(seed) => mock::with_seed(seed, || mock::rng().gen_range(min_total, max_total)),
+                    None => mock::rng().gen_range(min_total, max_total)
+                }
+            }
+        }
+    };
+
+    let offset = page.saturating_sub(first_page).saturating_mul(page_size);
+    let count = total.saturating_sub(offset).max(0).min(page_size);
+    let has_next = offset.saturating_add(page_size) < total;
+    let page_count = if total == 0 { 0 } else { (total - 1) / page_size + 1 };
+
+    // 找到列表字段，没有设置的时候用response中的第一个数组字段
+    let list_key = match pagination.get("list").and_then(|x| x.as_str()) {
+        Some(v) => v.to_string(),
+        None => {
+            let mut key = "".to_string();
+            if let Some(response_obj) = response_model.as_object() {
+                for (k, v) in response_obj {
+                    if v.is_array() {
+                        key = k.to_string();
+                        break;
+                    }
+                }
+            }
+            key
+        }
+    };
+
+    // 数组生成时 -length 为0表示随机长度，所以至少生成一条，再截断
+    let mut model = response_model.clone();
+    if list_key != "" {
+        if let Some(item) = model.pointer_mut(&format!("{}/0", field_path_to_pointer(&list_key))) {
+            if let Some(item) = item.as_object_mut() {
+                let length = if count > 0 { count } else { 1 };
+                item.insert("-length".to_string(), json!(length));
+            }
+        }
+    }
+
+    // 先生成当前页的数据，作为列表字段的固定值再生成整个返回
+    // 这样 =len(items)、=sum(items[].price) 这样的计算字段按当前页的数据计算
+    if list_key != "" {
+        let list_pointer = field_path_to_pointer(&list_key);
+        let items = match seed {
+            Some(seed) => create_seeded_list_items(&model, &list_pointer, seed, offset, count, types)?,
+            None => {
+                let first = Value::Object(try_create_mock_response(&model, types)?);
+                let mut items = first.pointer(&list_pointer).and_then(|x| x.as_array()).cloned().unwrap_or_default();
+                items.truncate(count as usize);
+                items
+            }
+        };
+        if let Some(list) = model.pointer_mut(&list_pointer) {
+            if list.is_array() {
+                *list = json!({"value": items});
+            }
+        }
+    }
+
+    let mut result = match seed {
+        Some(seed) => Value::Object(mock::with_seed(seed, || try_create_mock_response(&model, types))?),
+        None => Value::Object(try_create_mock_response(&model, types)?)
+    };
+
+    let envelope = [
+        ("total_key", "total", json!(total)),
+        ("page_key", "page", json!(page)),
+        ("page_size_key", "page_size", json!(page_size)),
+        ("has_next_key", "has_next", json!(has_next)),
+        ("page_count_key", "page_count", json!(page_count)),
+    ];
+    for (config_key, default_key, v) in envelope.iter() {
+        match pagination.get(*config_key).and_then(|x| x.as_str()) {
+            Some(path) => set_value_by_path(&mut result, path, v.clone()),
+            None => {
+                // 没有设置的时候，只有response中定义了该字段才设置
+                if response_model.get(*default_key).is_some() {
+                    set_value_by_path(&mut result, default_key, v.clone());
+                }
+            }
+        }
+    }
+
+    Ok(result)
+}
@@ -0,0 +1,652 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web::dev::ResourceDef;
+use actix_web::http::HeaderMap;
+use serde_json::{json, Value, Map};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::time::SystemTime;
+use regex::Regex;
+use jsonwebtoken::{encode, decode, Header, Algorithm, EncodingKey, DecodingKey, Validation};
+use jsonwebtoken::errors::ErrorKind;
+
+use crate::db;
+use crate::oauth;
+
+
+/// 判断请求地址是否和设置的地址匹配
+fn is_url_match(url: &str, req_path: &str) -> bool {
+    if url == "" {
+        return false;
+    }
+    url == req_path || ResourceDef::new(url).is_match(req_path)
+}
+
+
+/// 从body、form-data、query中依次获取提交的字段值
+fn get_request_field(key: &str, request_body: &Value, form_data: &Value, request_query: &Value) -> Option<String> {
+    for data in [request_body, form_data, request_query].iter() {
+        if let Some(v) = data.get(key) {
+            return match v.as_str() {
+                Some(v) => Some(v.to_string()),
+                None => Some(format!("{}", v))
+            };
+        }
+    }
+    None
+}
+
+
+/// 按照auth_place、auth_key从请求中获取原始的凭证，并去掉auth_scheme前缀
+fn get_credential_from_request(req: &HttpRequest, auth_doc: &db::AuthDoc, request_body: &Value, form_data: &Value) -> String {
+    get_credential(req.headers(), req.query_string(), auth_doc, request_body, form_data)
+}
+
+
+fn get_credential(headers: &HeaderMap, query_string: &str, auth_doc: &db::AuthDoc, request_body: &Value, form_data: &Value) -> String {
+    let key = auth_doc.auth_key.as_str();
+    let mut credential = "".to_string();
+
+    match auth_doc.auth_place.as_str() {
+        "query" => {
+            if let Ok(query) = web::Query::<HashMap<String, String>>::from_query(query_string) {
+                if let Some(v) = query.get(key) {
+                    credential = v.to_string();
+                }
+            }
+        }
+        "cookie" => {
+            for header_value in headers.get_all("cookie") {
+                if let Ok(cookies) = header_value.to_str() {
+                    for cookie in cookies.split(';') {
+                        let cookie: Vec<&str> = cookie.trim().splitn(2, '=').collect();
+                        if cookie.len() == 2 && cookie[0] == key {
+                            credential = cookie[1].to_string();
+                        }
+                    }
+                }
+            }
+        }
+        "form" => {
+            for data in [form_data, request_body].iter() {
+                if let Some(v) = data.get(key).and_then(|x| x.as_str()) {
+                    credential = v.to_string();
+                    break;
+                }
+            }
+        }
+        _ => {
+            if let Some(x) = headers.get(key) {
+                if let Ok(x) = x.to_str() {
+                    credential = x.to_string();
+                }
+            }
+        }
+    }
+
+    let credential = credential.trim();
+    let scheme = auth_doc.auth_scheme.as_str();
+    if scheme != "" {
+        if let Some(prefix) = credential.get(..scheme.len()) {
+            if prefix.eq_ignore_ascii_case(scheme) {
+                return credential[scheme.len()..].trim().to_string();
+            }
+        }
+    }
+    credential.to_string()
+}
+
+
+/// 从请求中获取token
+/// Basic认证时校验用户名密码，ApiKey认证时校验用户的api_key，都返回对应用户的token
+pub fn get_token_from_request(req: &HttpRequest, auth_doc: &db::AuthDoc, request_body: &Value, form_data: &Value) -> String {
+    let credential = get_credential_from_request(req, auth_doc, request_body, form_data);
+    get_token_from_credential(credential, auth_doc)
+}
+
+
+/// 只根据header和query获取token，用于还没有读取body的中间件
+pub fn get_token_from_head(headers: &HeaderMap, query_string: &str, auth_doc: &db::AuthDoc) -> String {
+    let credential = get_credential(headers, query_string, auth_doc, &Value::Null, &Value::Null);
+    get_token_from_credential(credential, auth_doc)
+}
+
+
+fn get_token_from_credential(credential: String, auth_doc: &db::AuthDoc) -> String {
+    if credential == "" {
+        return credential;
+    }
+
+    let auth_type = auth_doc.auth_type.to_lowercase();
+    if auth_doc.session.is_some() {
+        // Session模式下cookie中是session id，返回会话对应用户的token
+        return match auth_doc.sessions.get(&credential) {
+            Some(session) if session.expires_at >= now_timestamp() => session.user_token.clone(),
+            _ => "".to_string()
+        };
+    } else if auth_type.contains("basic") {
+        let decoded = match base64::decode(&credential) {
+            Ok(v) => String::from_utf8(v).unwrap_or_default(),
+            Err(_) => return "".to_string()
+        };
+        let decoded: Vec<&str> = decoded.splitn(2, ':').collect();
+        if decoded.len() != 2 {
+            return "".to_string();
+        }
+        let (username_field, password_field) = match &auth_doc.login {
+            Some(login) => (login.username_field.as_str(), login.password_field.as_str()),
+            None => ("username", "password")
+        };
+        return match find_user_by_password(auth_doc, username_field, decoded[0], password_field, decoded[1]) {
+            Some((token, _, _)) => token.to_string(),
+            None => "".to_string()
+        };
+    } else if auth_type.contains("api") {
+        for group in &auth_doc.groups {
+            for (token, user) in &group.users {
+                if user.get("api_key").and_then(|x| x.as_str()) == Some(credential.as_str()) {
+                    return token.to_string();
+                }
+            }
+        }
+    }
+
+    credential
+}
+
+
+/// 用户信息返回给前端的时候，去掉密码
+pub fn get_public_user(user: &Value) -> Value {
+    match user.as_object() {
+        Some(user_obj) => {
+            let mut user_obj = user_obj.clone();
+            user_obj.remove("password");
+            Value::Object(user_obj)
+        }
+        None => user.clone()
+    }
+}
+
+
+/// 替换返回模板中的 {{token}} {{user}} {{user.id}} {{group}}
+pub fn render_user_template(template: &Value, token: &str, user: &Value, group: &str) -> Value {
+    match template {
+        Value::String(s) => {
+            let t = s.trim();
+            if t == "{{token}}" {
+                return Value::String(token.to_string());
+            } else if t == "{{user}}" {
+                return get_public_user(user);
+            } else if t == "{{group}}" {
+                return Value::String(group.to_string());
+            } else if t.starts_with("{{user.") && t.ends_with("}}") {
+                let key = &t[7..t.len() - 2];
+                if key != "password" {
+                    if let Some(v) = user.pointer(&format!("/{}", key.replace(".", "/"))) {
+                        return v.clone();
+                    }
+                }
+                return Value::Null;
+            }
+            let s = s.replace("{{token}}", token).replace("{{group}}", group);
+            if !s.contains("{{user.") {
+                return Value::String(s);
+            }
+            // 字符串中间的 {{user.id}} 替换为对应的值
+            let re = Regex::new(r"\{\{user\.([\w.]+)\}\}").unwrap();
+            let s = re.replace_all(&s, |caps: &regex::Captures| {
+                let key = &caps[1];
+                if key == "password" {
+                    return "".to_string();
+                }
+                match user.pointer(&format!("/{}", key.replace(".", "/"))) {
+                    Some(Value::String(v)) => v.to_string(),
+                    Some(Value::Null) | None => "".to_string(),
+                    Some(v) => v.to_string()
+                }
+            });
+            Value::String(s.to_string())
+        }
+        Value::Object(obj) => {
+            let mut new_obj = Map::new();
+            for (k, v) in obj {
+                new_obj.insert(k.to_string(), render_user_template(v, token, user, group));
+            }
+            Value::Object(new_obj)
+        }
+        Value::Array(items) => {
+            Value::Array(items.iter().map(|x| render_user_template(x, token, user, group)).collect())
+        }
+        _ => template.clone()
+    }
+}
+
+
+/// 根据用户名和密码，找到对应的用户 (token, user, group name)
+pub fn find_user_by_password<'a>(auth_doc: &'a db::AuthDoc, username_field: &str, username: &str, password_field: &str, password: &str) -> Option<(&'a String, &'a Value, &'a String)> {
+    for group in &auth_doc.groups {
+        for (token, user) in &group.users {
+            let user_username = match user.get(username_field) {
+                Some(v) => match v.as_str() {
+                    Some(v) => v.to_string(),
+                    None => format!("{}", v)
+                },
+                None => continue
+            };
+            let user_password = match user.get(password_field) {
+                Some(v) => match v.as_str() {
+                    Some(v) => v.to_string(),
+                    None => format!("{}", v)
+                },
+                None => continue
+            };
+            if user_username == username && user_password == password {
+                return Some((token, user, &group.name));
+            }
+        }
+    }
+    None
+}
+
+
+/// JWT token 校验失败的原因，过期和无效分别返回不同的内容
+#[derive(Debug)]
+pub enum JwtError {
+    Expired,
+    Invalid,
+}
+
+
+/// jwks 中公钥的 kid
+pub const JWT_KEY_ID: &str = "panda-api";
+
+
+pub fn get_jwt_algorithm(setting: &db::JwtSetting) -> Algorithm {
+    match Algorithm::from_str(&setting.algorithm) {
+        Ok(v) => v,
+        Err(_) => {
+            println!("unsupported jwt algorithm {}, use HS256", setting.algorithm);
+            Algorithm::HS256
+        }
+    }
+}
+
+
+/// 用户在token中的标识，优先使用id
+pub fn get_user_subject(user: &Value) -> String {
+    for key in ["id", "username"].iter() {
+        if let Some(v) = user.get(*key) {
+            return match v.as_str() {
+                Some(v) => v.to_string(),
+                None => format!("{}", v)
+            };
+        }
+    }
+    "".to_string()
+}
+
+
+pub fn now_timestamp() -> u64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs()
+}
+
+
+/// 生成JWT token，claims中包含用户信息(去掉password和token)和所在的权限组
+pub fn create_jwt_token(setting: &db::JwtSetting, user: &Value, group: &str) -> Result<String, String> {
+    let mut claims = Map::new();
+    if let Some(user_obj) = user.as_object() {
+        for (k, v) in user_obj {
+            if k != "password" && k != "token" {
+                claims.insert(k.to_string(), v.clone());
+            }
+        }
+    }
+    let now = now_timestamp();
+    claims.insert("sub".to_string(), json!(get_user_subject(user)));
+    claims.insert("group".to_string(), json!(group));
+    claims.insert("iat".to_string(), json!(now));
+    claims.insert("exp".to_string(), json!(now + setting.expires_in));
+    if setting.issuer != "" {
+        claims.insert("iss".to_string(), json!(setting.issuer));
+    }
+    if setting.audience != "" {
+        claims.insert("aud".to_string(), json!(setting.audience));
+    }
+
+    sign_jwt_claims(setting, claims)
+}
+
+
+/// 用JWT设置中的密钥签名claims，RSA/EC签名的token带上kid，和jwks中的key对应
+pub fn sign_jwt_claims(setting: &db::JwtSetting, claims: Map<String, Value>) -> Result<String, String> {
+    let algorithm = get_jwt_algorithm(setting);
+    let mut header = Header::new(algorithm);
+    let key = match algorithm {
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => Ok(EncodingKey::from_secret(setting.secret.as_bytes())),
+        Algorithm::ES256 | Algorithm::ES384 => {
+            header.kid = Some(JWT_KEY_ID.to_string());
+            EncodingKey::from_ec_pem(&setting.private_key_data)
+        }
+        _ => {
+            header.kid = Some(JWT_KEY_ID.to_string());
+            EncodingKey::from_rsa_pem(&setting.private_key_data)
+        }
+    };
+    let key = key.map_err(|e| format!("jwt private key error: {:?}", e))?;
+
+    encode(&header, &Value::Object(claims), &key).map_err(|e| format!("create jwt token error: {:?}", e))
+}
+
+
+/// 校验JWT token的签名、过期时间、issuer、audience，返回token中的claims
+/// validate_exp 为false时，过期的token也可以通过，用于刷新token
+pub fn decode_jwt_token(setting: &db::JwtSetting, token: &str, validate_exp: bool) -> Result<Value, JwtError> {
+    let algorithm = get_jwt_algorithm(setting);
+    let key = match algorithm {
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => Ok(DecodingKey::from_secret(setting.secret.as_bytes())),
+        Algorithm::ES256 | Algorithm::ES384 => DecodingKey::from_ec_pem(&setting.public_key_data),
+        _ => DecodingKey::from_rsa_pem(&setting.public_key_data),
+    };
+    let key = match key {
+        Ok(key) => key,
+        Err(e) => {
+            println!("jwt public key error: {:?}", e);
+            return Err(JwtError::Invalid);
+        }
+    };
+
+    let mut validation = Validation::new(algorithm);
+    validation.validate_exp = validate_exp;
+    if setting.issuer != "" {
+        validation.iss = Some(setting.issuer.clone());
+    }
+    if setting.audience != "" {
+        let mut aud = HashSet::new();
+        aud.insert(setting.audience.clone());
+        validation.aud = Some(aud);
+    }
+
+    match decode::<Value>(token, &key, &validation) {
+        Ok(data) => Ok(data.claims),
+        Err(e) => {
+            match e.kind() {
+                ErrorKind::ExpiredSignature => Err(JwtError::Expired),
+                _ => Err(JwtError::Invalid)
+            }
+        }
+    }
+}
+
+
+/// 根据JWT claims中的group和sub找到对应的用户
+fn find_user_by_claims<'a>(auth_doc: &'a db::AuthDoc, claims: &Value) -> Option<(&'a Value, &'a String)> {
+    let group_name = claims.get("group").and_then(|x| x.as_str())?;
+    let sub = claims.get("sub").and_then(|x| x.as_str())?;
+    for group in &auth_doc.groups {
+        if group.name != group_name {
+            continue;
+        }
+        for (_, user) in &group.users {
+            if get_user_subject(user) == sub {
+                return Some((user, &group.name));
+            }
+        }
+    }
+    None
+}
+
+
+/// 根据token找到对应的用户 (user, group name)，已经退出的token找不到
+pub fn find_user_by_token<'a>(auth_doc: &'a db::AuthDoc, token: &str) -> Option<(&'a Value, &'a String)> {
+    if token == "" || auth_doc.revoked_tokens.contains(token) {
+        return None;
+    }
+    if auth_doc.oauth_access_tokens.contains_key(token) {
+        return oauth::find_access_token_user(auth_doc, token);
+    }
+    if let Some(jwt) = &auth_doc.jwt {
+        let claims = decode_jwt_token(jwt, token, true).ok()?;
+        return find_user_by_claims(auth_doc, &claims);
+    }
+    for group in &auth_doc.groups {
+        if let Some(user) = group.users.get(token) {
+            return Some((user, &group.name));
+        }
+    }
+    None
+}
+
+
+/// Session模式下校验会话是否存在、是否过期，以及非安全方法的csrf token
+/// 校验失败返回对应的报错信息
+pub fn check_session<'a>(req: &HttpRequest, auth_doc: &'a db::AuthDoc, request_body: &Value, form_data: &Value) -> Result<&'a db::Session, &'a Value> {
+    let setting = match &auth_doc.session {
+        Some(v) => v,
+        None => return Err(&auth_doc.no_perm_response)
+    };
+    let session_id = get_credential_from_request(req, auth_doc, request_body, form_data);
+    let session = match auth_doc.sessions.get(&session_id) {
+        Some(v) => v,
+        None => return Err(&auth_doc.no_perm_response)
+    };
+    if session.expires_at < now_timestamp() {
+        return Err(&setting.expired_response);
+    }
+
+    if setting.csrf {
+        let method = req.method().as_str();
+        if method != "GET" && method != "HEAD" && method != "OPTIONS" {
+            let mut csrf_token = match req.headers().get(setting.csrf_header.as_str()).and_then(|x| x.to_str().ok()) {
+                Some(v) => v.to_string(),
+                None => "".to_string()
+            };
+            if csrf_token == "" {
+                csrf_token = get_request_field(&setting.csrf_field, request_body, form_data, &Value::Null).unwrap_or_default();
+            }
+            if csrf_token != session.csrf_token {
+                return Err(&setting.csrf_response);
+            }
+        }
+    }
+    Ok(session)
+}
+
+
+/// 生成Set-Cookie的值，max_age为0时删除cookie
+fn session_cookie(name: &str, value: &str, path: &str, max_age: u64, http_only: bool) -> String {
+    let mut cookie = format!("{}={}; Path={}; Max-Age={}; SameSite=Lax", name, value, path, max_age);
+    if http_only {
+        cookie.push_str("; HttpOnly");
+    }
+    cookie
+}
+
+
+/// 判断test_data中的 user、group 设置是否和当前用户匹配
+/// user 可以是用户的 id、username 或者它们组成的数组，group 可以是权限组名称或者数组
+pub fn is_test_case_user_match(test_case_data: &Value, current_user: Option<&(String, Value, String)>) -> bool {
+    let is_in = |expected: &Value, values: &[String]| -> bool {
+        let expected = match expected {
+            Value::Array(items) => items.clone(),
+            v => vec![v.clone()]
+        };
+        expected.iter().any(|x| {
+            let x = match x.as_str() {
+                Some(v) => v.to_string(),
+                None => x.to_string()
+            };
+            values.contains(&x)
+        })
+    };
+
+    if let Some(expected) = test_case_data.get("user") {
+        let (_, user, _) = match current_user {
+            Some(v) => v,
+            None => return false
+        };
+        let mut values = vec![get_user_subject(user)];
+        for key in ["id", "username"].iter() {
+            match user.get(*key) {
+                Some(Value::String(v)) => values.push(v.to_string()),
+                Some(v) if !v.is_null() => values.push(v.to_string()),
+                _ => ()
+            }
+        }
+        if !is_in(expected, &values) {
+            return false;
+        }
+    }
+
+    if let Some(expected) = test_case_data.get("group") {
+        let (_, _, group) = match current_user {
+            Some(v) => v,
+            None => return false
+        };
+        if !is_in(expected, &[group.to_string()]) {
+            return false;
+        }
+    }
+    true
+}
+
+
+/// 登录成功后返回给前端的token，JWT模式下每次登录生成新的token
+fn issue_token(auth_doc: &db::AuthDoc, token: &str, user: &Value, group: &str) -> Result<String, String> {
+    match &auth_doc.jwt {
+        Some(jwt) => create_jwt_token(jwt, user, group),
+        None => Ok(token.to_string())
+    }
+}
+
+
+/// 处理_auth.json5中login设置生成的登录、退出、获取当前用户接口
+/// 如果请求的不是这几个接口，返回None
+pub fn auth_api_handle(req: &HttpRequest, request_body: &Value, request_query: &Value, form_data: &Value, auth_doc: &mut db::AuthDoc) -> Option<HttpResponse> {
+    if let Some(setting) = auth_doc.session.clone() {
+        // 会话每次访问后延长有效时间
+        if setting.sliding {
+            let session_id = get_credential_from_request(req, auth_doc, request_body, form_data);
+            let now = now_timestamp();
+            if let Some(session) = auth_doc.sessions.get_mut(&session_id) {
+                if session.expires_at >= now {
+                    session.expires_at = now + setting.expires_in;
+                }
+            }
+        }
+    }
+
+    let login = auth_doc.login.clone()?;
+    let req_path = req.path();
+    let req_method = req.method().as_str();
+
+    if let Some(setting) = auth_doc.session.clone() {
+        if is_url_match(&login.url, req_path) && (login.method == "*" || login.method == req_method) {
+            let username = get_request_field(&login.username_field, request_body, form_data, request_query).unwrap_or_default();
+            let password = get_request_field(&login.password_field, request_body, form_data, request_query).unwrap_or_default();
+            let (token, user, group) = match find_user_by_password(auth_doc, &login.username_field, &username, &login.password_field, &password) {
+                Some((token, user, group)) => (token.to_string(), user.clone(), group.to_string()),
+                None => return Some(HttpResponse::Ok().json(&login.fail_response))
+            };
+
+            // 登录时清理已经过期的会话
+            let now = now_timestamp();
+            auth_doc.sessions.retain(|_, x| x.expires_at >= now);
+
+            let session_id = uuid::Uuid::new_v4().to_simple().to_string();
+            let csrf_token = uuid::Uuid::new_v4().to_simple().to_string();
+            auth_doc.sessions.insert(session_id.clone(), db::Session { user_token: token, group: group.clone(), csrf_token: csrf_token.clone(), expires_at: now + setting.expires_in });
+
+            // Session模式下返回模板中的 {{token}} 为csrf token
+            let response = render_user_template(&login.response, &csrf_token, &user, &group);
+            let mut builder = HttpResponse::Ok();
+            builder.header("Set-Cookie", session_cookie(&setting.cookie_name, &session_id, &setting.cookie_path, setting.expires_in, true));
+            if setting.csrf {
+                builder.header("Set-Cookie", session_cookie(&setting.csrf_cookie_name, &csrf_token, &setting.cookie_path, setting.expires_in, false));
+            }
+            return Some(builder.json(response));
+        }
+
+        if is_url_match(&login.logout_url, req_path) {
+            let session_id = get_credential_from_request(req, auth_doc, request_body, form_data);
+            if auth_doc.sessions.remove(&session_id).is_none() {
+                return Some(HttpResponse::Ok().json(&auth_doc.no_perm_response));
+            }
+            let mut builder = HttpResponse::Ok();
+            builder.header("Set-Cookie", session_cookie(&setting.cookie_name, "", &setting.cookie_path, 0, true));
+            if setting.csrf {
+                builder.header("Set-Cookie", session_cookie(&setting.csrf_cookie_name, "", &setting.cookie_path, 0, false));
+            }
+            return Some(builder.json(&login.logout_response));
+        }
+    }
+
+    if is_url_match(&login.url, req_path) && (login.method == "*" || login.method == req_method) {
+        let username = get_request_field(&login.username_field, request_body, form_data, request_query).unwrap_or_default();
+        let password = get_request_field(&login.password_field, request_body, form_data, request_query).unwrap_or_default();
+
+        let result = match find_user_by_password(auth_doc, &login.username_field, &username, &login.password_field, &password) {
+            Some((token, user, group)) => {
+                match issue_token(auth_doc, token, user, group) {
+                    Ok(token) => {
+                        let response = render_user_template(&login.response, &token, user, group);
+                        Some(Ok((token, response)))
+                    }
+                    Err(e) => Some(Err(e))
+                }
+            }
+            None => None
+        };
+        return match result {
+            Some(Ok((token, response))) => {
+                // 重新登录后，token可以继续使用
+                auth_doc.revoked_tokens.remove(&token);
+                Some(HttpResponse::Ok().json(response))
+            }
+            Some(Err(e)) => Some(HttpResponse::Ok().json(json!({"code": -1, "msg": e}))),
+            None => Some(HttpResponse::Ok().json(&login.fail_response))
+        };
+    }
+
+    if is_url_match(&login.refresh_url, req_path) {
+        // 用旧的token换取新的token，过期的token也可以刷新，被篡改的不行
+        let jwt = match &auth_doc.jwt {
+            Some(jwt) => jwt,
+            None => return None
+        };
+        let token = get_token_from_request(req, auth_doc, request_body, form_data);
+        if token == "" || auth_doc.revoked_tokens.contains(&token) {
+            return Some(HttpResponse::Ok().json(&jwt.invalid_response));
+        }
+        let claims = match decode_jwt_token(jwt, &token, false) {
+            Ok(claims) => claims,
+            Err(_) => return Some(HttpResponse::Ok().json(&jwt.invalid_response))
+        };
+        return match find_user_by_claims(auth_doc, &claims) {
+            Some((user, group)) => {
+                match create_jwt_token(jwt, user, group) {
+                    Ok(new_token) => Some(HttpResponse::Ok().json(render_user_template(&login.response, &new_token, user, group))),
+                    Err(e) => Some(HttpResponse::Ok().json(json!({"code": -1, "msg": e})))
+                }
+            }
+            None => Some(HttpResponse::Ok().json(&jwt.invalid_response))
+        };
+    }
+
+    if is_url_match(&login.logout_url, req_path) {
+        let token = get_token_from_request(req, auth_doc, request_body, form_data);
+        if find_user_by_token(auth_doc, &token).is_none() {
+            return Some(HttpResponse::Ok().json(&auth_doc.no_perm_response));
+        }
+        auth_doc.revoked_tokens.insert(token);
+        return Some(HttpResponse::Ok().json(&login.logout_response));
+    }
+
+    if is_url_match(&login.user_url, req_path) {
+        let token = get_token_from_request(req, auth_doc, request_body, form_data);
+        return match find_user_by_token(auth_doc, &token) {
+            Some((user, group)) => Some(HttpResponse::Ok().json(render_user_template(&login.user_response, &token, user, group))),
+            None => Some(HttpResponse::Ok().json(&auth_doc.no_perm_response))
+        };
+    }
+
+    None
+}
@@ -0,0 +1,332 @@
+//! 请求body的解析和不同格式的返回
+//! 请求按照 content-type 解析为json，用于test_data匹配: xml 转换为对象，表单转换为form-data，文本为字符串，二进制为base64字符串
+//! 返回按照接口的 response_mode 生成: json、xml、text、html、javascript、binary
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use actix_web::HttpResponse;
+use serde_json::{json, Map, Value};
+
+
+/// 解析后的content-type，如 application/json; charset=utf-8
+pub struct MediaType {
+    pub mime: String,
+    pub params: HashMap<String, String>,
+}
+
+
+impl MediaType {
+    pub fn charset(&self) -> Option<&str> {
+        self.params.get("charset").map(|x| x.as_str())
+    }
+}
+
+
+/// 解析content-type，类型和参数名转换为小写，参数值可以带引号
+pub fn parse_media_type(content_type: &str) -> MediaType {
+    let mut parts = content_type.split(';');
+    let mime = parts.next().unwrap_or("").trim().to_lowercase();
+    let mut params = HashMap::new();
+    for part in parts {
+        let mut kv = part.splitn(2, '=');
+        let k = kv.next().unwrap_or("").trim().to_lowercase();
+        let v = kv.next().unwrap_or("").trim().trim_matches('"').to_string();
+        if k != "" {
+            params.insert(k, v);
+        }
+    }
+    MediaType { mime, params }
+}
+
+
+/// 把content-type转换为body_mode，忽略 charset 等参数
+pub fn get_body_mode_of_content_type(content_type: &str) -> String {
+    let media_type = parse_media_type(content_type);
+    let body_mode = match media_type.mime.as_str() {
+        "" => "",
+        "application/json" => "json",
+        "multipart/form-data" => "form-data",
+        "application/x-www-form-urlencoded" => "form-urlencoded",
+        "text/plain" => "text",
+        "text/html" => "html",
+        "application/xml" | "text/xml" => "xml",
+        "application/javascript" | "text/javascript" => "javascript",
+        v if v.ends_with("+json") => "json",
+        v if v.ends_with("+xml") => "xml",
+        v if v.starts_with("text/") => "text",
+        _ => "binary"
+    };
+    body_mode.to_string()
+}
+
+
+/// 按照charset把body转换为字符串，没有设置charset的按utf-8处理
+fn decode_text(body: &[u8], charset: Option<&str>) -> Result<String, String> {
+    match charset {
+        None => String::from_utf8(body.to_vec()).map_err(|e| format!("body is not valid utf-8: {}", e)),
+        Some(label) => {
+            let encoding = match encoding_rs::Encoding::for_label(label.as_bytes()) {
+                Some(v) => v,
+                None => return Err(format!("unsupported charset {}", label))
+            };
+            let (text, _, had_errors) = encoding.decode(body);
+            if had_errors {
+                return Err(format!("body is not valid {}", label));
+            }
+            Ok(text.into_owned())
+        }
+    }
+}
+
+
+/// 把请求的body按照body_mode转换为json，无法解码时返回错误原因
+/// form-urlencoded 的结果作为form-data，其它的作为body
+pub fn parse_request_body(body_mode: &str, charset: Option<&str>, body: &[u8]) -> Result<Value, String> {
+    if body.is_empty() {
+        return Ok(Value::Null);
+    }
+    match body_mode {
+        "json" => {
+            let text = decode_text(body, charset)?;
+            serde_json::from_str(&text).map_err(|e| format!("invalid json: {}", e))
+        }
+        "form-urlencoded" | "form-data" => parse_urlencoded(body, charset),
+        "xml" => xml_to_value(&decode_text(body, charset)?).map_err(|e| format!("invalid xml: {}", e)),
+        "text" | "html" | "javascript" => Ok(Value::String(decode_text(body, charset)?)),
+        "binary" => Ok(Value::String(base64::encode(body))),
+        _ => Ok(Value::Null)
+    }
+}
+
+
+fn percent_decode(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut result = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        match data[i] {
+            b'+' => result.push(b' '),
+            b'%' => {
+                let hex = data.get(i + 1..i + 3)
+                    .and_then(|x| std::str::from_utf8(x).ok())
+                    .and_then(|x| u8::from_str_radix(x, 16).ok());
+                match hex {
+                    Some(v) => result.push(v),
+                    None => return Err(format!("invalid percent encoding at position {}", i))
+                }
+                i += 2;
+            }
+            b => result.push(b)
+        }
+        i += 1;
+    }
+    Ok(result)
+}
+
+
+/// 解析 a=1&b=2&b=3，同名的字段转换为数组，百分号编码的内容按照charset解码
+/// 用于 urlencoded 的body和query string
+pub fn parse_urlencoded(data: &[u8], charset: Option<&str>) -> Result<Value, String> {
+    let mut result = Map::new();
+    for pair in data.split(|x| *x == b'&') {
+        if pair.is_empty() {
+            continue;
+        }
+        let mut kv = pair.splitn(2, |x| *x == b'=');
+        let k = decode_text(&percent_decode(kv.next().unwrap_or(&[]))?, charset)?;
+        let v = Value::String(decode_text(&percent_decode(kv.next().unwrap_or(&[]))?, charset)?);
+        match result.get_mut(&k) {
+            Some(Value::Array(items)) => items.push(v),
+            Some(old) => {
+                let first = old.clone();
+                *old = json!([first, v]);
+            }
+            None => {
+                result.insert(k, v);
+            }
+        }
+    }
+    Ok(Value::Object(result))
+}
+
+
+/// 把xml转换为json: {根元素名: 元素}
+/// 元素的属性为 @属性名，文本为 #text，同名的子元素为数组，只有文本的元素直接为字符串
+pub fn xml_to_value(text: &str) -> Result<Value, String> {
+    let doc = roxmltree::Document::parse(text).map_err(|e| e.to_string())?;
+    let root = doc.root_element();
+    let mut result = Map::new();
+    result.insert(root.tag_name().name().to_string(), xml_element_to_value(&root));
+    Ok(Value::Object(result))
+}
+
+
+fn xml_element_to_value(node: &roxmltree::Node) -> Value {
+    let mut obj = Map::new();
+    for attr in node.attributes() {
+        obj.insert(format!("@{}", attr.name()), Value::String(attr.value().to_string()));
+    }
+    let mut text = String::new();
+    for child in node.children() {
+        if child.is_element() {
+            let name = child.tag_name().name().to_string();
+            let v = xml_element_to_value(&child);
+            match obj.get_mut(&name) {
+                Some(Value::Array(items)) => items.push(v),
+                Some(old) => {
+                    let first = old.clone();
+                    *old = json!([first, v]);
+                }
+                None => {
+                    obj.insert(name, v);
+                }
+            }
+        } else if let Some(t) = child.text() {
+            text.push_str(t);
+        }
+    }
+    let text = text.trim();
+    if obj.is_empty() {
+        return Value::String(text.to_string());
+    }
+    if text != "" {
+        obj.insert("#text".to_string(), Value::String(text.to_string()));
+    }
+    Value::Object(obj)
+}
+
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+
+fn write_xml_element(name: &str, value: &Value, out: &mut String) {
+    match value {
+        Value::Array(items) => {
+            for item in items {
+                write_xml_element(name, item, out);
+            }
+        }
+        Value::Object(obj) => {
+            out.push_str(&format!("<{}", name));
+            for (k, v) in obj {
+                if k.starts_with('@') {
+                    let v = match v {
+                        Value::String(s) => s.to_string(),
+                        v => v.to_string()
+                    };
+                    out.push_str(&format!(" {}=\"{}\"", &k[1..], xml_escape(&v)));
+                }
+            }
+            out.push('>');
+            for (k, v) in obj {
+                if k == "#text" {
+                    match v {
+                        Value::String(s) => out.push_str(&xml_escape(s)),
+                        v => out.push_str(&xml_escape(&v.to_string()))
+                    }
+                } else if !k.starts_with('@') {
+                    write_xml_element(k, v, out);
+                }
+            }
+            out.push_str(&format!("</{}>", name));
+        }
+        Value::Null => out.push_str(&format!("<{}/>", name)),
+        Value::String(s) => out.push_str(&format!("<{}>{}</{}>", name, xml_escape(s), name)),
+        v => out.push_str(&format!("<{}>{}</{}>", name, v, name))
+    }
+}
+
+
+/// 把json转换为xml，只有一个字段的对象以字段名为根元素，否则根元素为 root
+pub fn value_to_xml(value: &Value) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+    match value.as_object() {
+        Some(obj) if obj.len() == 1 && !obj.keys().any(|x| x.starts_with('@') || x == "#text") => {
+            for (k, v) in obj {
+                write_xml_element(k, v, &mut out);
+            }
+        }
+        _ => write_xml_element("root", value, &mut out)
+    }
+    out
+}
+
+
+fn guess_content_type(filename: &str) -> &'static str {
+    let ext = filename.rsplit('.').next().unwrap_or("").to_lowercase();
+    match ext.as_str() {
+        "pdf" => "application/pdf",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "zip" => "application/zip",
+        "csv" => "text/csv",
+        "txt" => "text/plain",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        "xls" => "application/vnd.ms-excel",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "doc" => "application/msword",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        _ => "application/octet-stream"
+    }
+}
+
+
+/// 返回 _data/ 下的文件
+/// value 为文件路径，或者 {file: "_data/report.pdf", filename: "report.pdf", content_type: "application/pdf", inline: false}
+fn create_file_response(value: &Value) -> HttpResponse {
+    let file = match value {
+        Value::String(v) => v.as_str(),
+        v => v.get("file").and_then(|x| x.as_str()).unwrap_or("")
+    };
+    let file = file.trim_start_matches("./");
+    if !file.starts_with("_data/") || file.split('/').any(|x| x == "..") {
+        return HttpResponse::Ok().json(json!({"code": -1, "msg": format!("binary response file {} must be in _data/", file)}));
+    }
+    let data = match fs::read(file) {
+        Ok(v) => v,
+        Err(e) => return HttpResponse::Ok().json(json!({"code": -1, "msg": format!("read binary response file {} error: {}", file, e)}))
+    };
+
+    let default_filename = Path::new(file).file_name().map(|x| x.to_string_lossy().to_string()).unwrap_or_default();
+    let filename = value.get("filename").and_then(|x| x.as_str()).unwrap_or(&default_filename);
+    let content_type = value.get("content_type").and_then(|x| x.as_str()).unwrap_or(guess_content_type(filename));
+    let disposition = if value.get("inline").and_then(|x| x.as_bool()).unwrap_or(false) { "inline" } else { "attachment" };
+    HttpResponse::Ok()
+        .content_type(content_type)
+        .header("Content-Disposition", format!("{}; filename=\"{}\"", disposition, filename.replace('"', "")))
+        .body(data)
+}
+
+
+/// 按照response_mode返回数据，文本类的返回中字符串原样返回，其它的转换为json文本
+pub fn create_response(response_mode: &str, value: &Value) -> HttpResponse {
+    let text = || -> String {
+        match value {
+            Value::String(v) => v.to_string(),
+            v => v.to_string()
+        }
+    };
+    match response_mode.to_lowercase().as_str() {
+        "xml" => {
+            let body = match value {
+                Value::String(v) => v.to_string(),
+                v => value_to_xml(v)
+            };
+            HttpResponse::Ok().content_type("application/xml; charset=utf-8").body(body)
+        }
+        "text" => HttpResponse::Ok().content_type("text/plain; charset=utf-8").body(text()),
+        "html" => HttpResponse::Ok().content_type("text/html; charset=utf-8").body(text()),
+        "javascript" => HttpResponse::Ok().content_type("application/javascript; charset=utf-8").body(text()),
+        "binary" | "file" => create_file_response(value),
+        _ => HttpResponse::Ok().json(value)
+    }
+}
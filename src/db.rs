@@ -8,6 +8,11 @@ use regex::Regex;
 use walkdir::WalkDir;
 use std::path::Path;
 
+use crate::mock;
+use crate::store;
+use crate::graphql;
+use crate::grpc;
+
 #[derive(Debug)]
 pub struct Database {
     pub basic_data: BasicData,
@@ -15,12 +20,22 @@ pub struct Database {
     pub api_data: HashMap<String, HashMap<String, Arc<Mutex<ApiData>>>>, // {url:{"GET":a_api_doc, "POST":a_api_data}}
     pub fileindex_data: HashMap<String, HashSet<String>>,
     // ref和相关文件的索引，当文件更新后，要找到所有ref他的地方，然后进行更新
-    pub websocket_api: Arc<Mutex<ApiData>>,
     pub auth_doc: Option<AuthDoc>,
     pub settings: Option<Value>,
+    // 自定义的字段类型
+    pub custom_types: CustomTypes,
+    // 设置了store的接口保存的数据
+    pub stores: store::Stores,
+    // 接口文档中所有的proto文件，加载失败时为错误信息
+    pub proto_schema: Result<Arc<grpc::ProtoSchema>, String>,
 }
 
 
+/// 自定义的字段类型 {小写类型名: (类型名, 类型定义)}
+/// 在_settings.json5的types 或者 _data/_types.json5 中定义
+pub type CustomTypes = HashMap<String, (String, Value)>;
+
+
 #[derive(Debug)]
 pub struct BasicData {
     pub read_me: String,
@@ -36,6 +51,8 @@ pub struct ApiDoc {
     pub order: i64,
     pub filename: String,
     pub apis: Vec<Arc<Mutex<ApiData>>>,
+    // 文档中设置的 .proto 文件，其中的服务作为gRPC接口
+    pub proto: Vec<String>,
 }
 
 
@@ -48,10 +65,35 @@ pub struct ApiData {
     pub method: Vec<String>,
     pub auth: bool,
     pub body_mode: String,
+    // 返回的格式: json、xml、text、html、javascript、binary
+    pub response_mode: String,
     pub body: Value,
     pub query: Value,
     pub response: Value,
     pub test_data: Value,
+    pub enums: Value,
+    pub pagination: Value,
+    pub tags: Vec<String>,
+    // 接口所在的文档文件
+    pub doc_file: String,
+    // 接口和接口文档上的限流设置
+    pub rate_limit: Value,
+    pub doc_rate_limit: Value,
+    // websocket接口的定时推送、连接后推送设置
+    pub push: Value,
+    // SSE接口的事件设置
+    pub sse: Value,
+    // websocket接口的聊天室设置
+    pub chat: Value,
+    // GraphQL接口的schema文件和字段设置
+    pub graphql: Value,
+    // 加载时解析好的GraphQL schema
+    #[serde(skip)]
+    pub graphql_schema: Option<Arc<graphql::Schema>>,
+    // gRPC接口流式返回的设置
+    pub grpc: Value,
+    // 有状态的数据设置，按用户保存新增、修改、删除的数据
+    pub store: Value,
 }
 
 
@@ -65,11 +107,156 @@ pub struct AuthDoc {
     pub auth_type: String,
     // auth 类型
     pub auth_place: String,
-    // auth 放在什么地方：headers 或者是 url上
+    // auth 放在什么地方：headers, query(url), cookie, form
+    pub auth_key: String,
+    // 放token的header名称、query参数名称、cookie名称或者form字段名称
+    pub auth_scheme: String,
+    // token前面的前缀，如 Bearer, Basic，为空表示没有前缀
     pub filename: String,
     // 文件名称
     pub groups: Vec<AuthData>,
     pub no_perm_response: Value,
+    pub login: Option<LoginSetting>,
+    pub jwt: Option<JwtSetting>,
+    pub oauth: Option<OAuthSetting>,
+    pub session: Option<SessionSetting>,
+    // 已经退出登录的token，重新登录后移除
+    #[serde(skip)]
+    pub revoked_tokens: HashSet<String>,
+    // OAuth2 授权码、access_token、refresh_token
+    #[serde(skip)]
+    pub oauth_codes: HashMap<String, OAuthGrant>,
+    #[serde(skip)]
+    pub oauth_access_tokens: HashMap<String, OAuthGrant>,
+    #[serde(skip)]
+    pub oauth_refresh_tokens: HashMap<String, OAuthGrant>,
+    // Session 模式下的服务端会话，key为session id
+    #[serde(skip)]
+    pub sessions: HashMap<String, Session>,
+}
+
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+/// auth_type 为 Session 时的设置，登录后设置session cookie
+pub struct SessionSetting {
+    pub cookie_name: String,
+    pub cookie_path: String,
+    // 会话有效时间，单位秒
+    pub expires_in: u64,
+    // 每次访问后重新计算过期时间
+    pub sliding: bool,
+    // 开启后，非 GET/HEAD/OPTIONS 请求需要在header或者表单中带上csrf token
+    pub csrf: bool,
+    pub csrf_cookie_name: String,
+    pub csrf_header: String,
+    pub csrf_field: String,
+    pub expired_response: Value,
+    pub csrf_response: Value,
+}
+
+
+#[derive(Debug, Clone, Default)]
+/// 服务端保存的会话数据
+pub struct Session {
+    // 用户在 users 中的 token
+    pub user_token: String,
+    pub group: String,
+    pub csrf_token: String,
+    pub expires_at: u64,
+}
+
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+/// 本地的 OAuth2 / OpenID Connect 服务设置
+pub struct OAuthSetting {
+    // 为空时使用请求的地址
+    pub issuer: String,
+    // 为空时不限制client
+    pub clients: Vec<OAuthClient>,
+    // scope 对应的接口权限
+    pub scopes: HashMap<String, OAuthScope>,
+    pub code_expires_in: u64,
+    pub access_token_expires_in: u64,
+    pub refresh_token_expires_in: u64,
+    // id_token 的签名设置
+    pub jwt: JwtSetting,
+}
+
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct OAuthClient {
+    pub client_id: String,
+    #[serde(skip_serializing)]
+    pub client_secret: String,
+    pub redirect_uris: Vec<String>,
+}
+
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct OAuthScope {
+    pub has_perms: HashMap<String, HashSet<String>>,
+    pub no_perms: HashMap<String, HashSet<String>>,
+}
+
+
+#[derive(Debug, Clone, Default)]
+/// OAuth2 授权后的数据，授权码、access_token、refresh_token 共用
+pub struct OAuthGrant {
+    pub client_id: String,
+    pub redirect_uri: String,
+    pub scope: String,
+    pub nonce: String,
+    pub code_challenge: String,
+    pub code_challenge_method: String,
+    // 用户在 users 中的 token
+    pub user_token: String,
+    pub group: String,
+    pub expires_at: u64,
+}
+
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+/// auth_type 为 JWT 时的签名设置
+pub struct JwtSetting {
+    // HS256, HS384, HS512, RS256, RS384, RS512, ES256, ES384
+    pub algorithm: String,
+    // HMAC 的密钥
+    #[serde(skip_serializing)]
+    pub secret: String,
+    // RSA/EC 的私钥、公钥 pem 文件路径
+    pub private_key: String,
+    pub public_key: String,
+    #[serde(skip)]
+    pub private_key_data: Vec<u8>,
+    #[serde(skip)]
+    pub public_key_data: Vec<u8>,
+    // token 有效时间，单位秒
+    pub expires_in: u64,
+    pub issuer: String,
+    pub audience: String,
+    // token 过期、token 被篡改或者无效时的返回
+    pub expired_response: Value,
+    pub invalid_response: Value,
+}
+
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+/// _auth.json5中的login设置，根据users生成登录、退出、获取当前用户接口
+pub struct LoginSetting {
+    pub url: String,
+    pub method: String,
+    // 登录时提交的用户名、密码字段，对应到users中的同名字段
+    pub username_field: String,
+    pub password_field: String,
+    // 返回模板，可以使用 {{token}} {{user}} {{user.id}} {{group}}
+    pub response: Value,
+    pub fail_response: Value,
+    pub logout_url: String,
+    pub logout_response: Value,
+    pub user_url: String,
+    pub user_response: Value,
+    // JWT 模式下用旧token换取新token的接口
+    pub refresh_url: String,
 }
 
 
@@ -81,6 +268,8 @@ pub struct AuthData {
     pub has_perms: HashMap<String, HashSet<String>>,
     pub no_perms: HashMap<String, HashSet<String>>,
     pub no_perm_response: Value,
+    // 权限组的限流设置
+    pub rate_limit: Value,
 }
 
 
@@ -101,6 +290,182 @@ fn fix_json(org_string: String) -> String {
 }
 
 
+/// 加载自定义字段类型
+/// 先加载_settings中的types，再加载_data/_types.json5，同名的以_data/_types.json5为准
+pub fn load_custom_types(settings: &Option<Value>) -> CustomTypes {
+    let mut types: CustomTypes = HashMap::new();
+
+    let mut add_types = |value: &Value| {
+        if let Some(value) = value.as_object() {
+            for (type_name, type_def) in value {
+                types.insert(type_name.to_lowercase(), (type_name.to_string(), type_def.clone()));
+            }
+        }
+    };
+
+    if let Some(settings) = settings {
+        if let Some(v) = settings.get("types") {
+            add_types(v);
+        }
+    }
+
+    for types_file in ["_data/_types.json5", "_data/_types.json"].iter() {
+        if let Ok(v) = fs::read_to_string(types_file) {
+            let v = fix_json(v);
+            match json5::from_str::<Value>(&v) {
+                Ok(v) => {
+                    add_types(&v);
+                    break;
+                }
+                Err(e) => {
+                    println!("Parse json file {} error : {:?}", types_file, e);
+                }
+            }
+        }
+    }
+
+    types
+}
+
+
+/// 获取所有自定义字段类型，给文档前端展示
+pub fn get_custom_types(types: &CustomTypes) -> Map<String, Value> {
+    let mut result = Map::new();
+    for (_, (type_name, type_def)) in types.iter() {
+        result.insert(type_name.to_string(), type_def.clone());
+    }
+    result
+}
+
+
+/// 替换类型定义中的参数 {{currency}}
+fn replace_type_params(value: &Value, params: &Map<String, Value>) -> Value {
+    match value {
+        Value::String(s) => {
+            for (k, v) in params {
+                let placeholder = format!("{{{{{}}}}}", k);
+                if s.trim() == placeholder {
+                    // 整个值就是一个参数，保留参数的类型
+                    return v.clone();
+                }
+            }
+            let mut s = s.to_string();
+            for (k, v) in params {
+                let placeholder = format!("{{{{{}}}}}", k);
+                let v = match v.as_str() {
+                    Some(v) => v.to_string(),
+                    None => format!("{}", v)
+                };
+                s = s.replace(&placeholder, &v);
+            }
+            Value::String(s)
+        }
+        Value::Object(obj) => {
+            let mut new_obj = Map::new();
+            for (k, v) in obj {
+                new_obj.insert(k.to_string(), replace_type_params(v, params));
+            }
+            Value::Object(new_obj)
+        }
+        Value::Array(items) => {
+            Value::Array(items.iter().map(|x| replace_type_params(x, params)).collect())
+        }
+        _ => value.clone()
+    }
+}
+
+
+/// 用字段定义展开一次自定义类型
+/// 类型定义中有fields的，展开为对象；没有的，类型定义和字段定义合并，字段上的设置优先
+fn expand_custom_type(type_def: &Value, field_attr: &Map<String, Value>) -> Value {
+    let mut params = match type_def.get("params").and_then(|x| x.as_object()) {
+        Some(v) => v.clone(),
+        None => Map::new()
+    };
+    let param_keys: Vec<String> = params.keys().cloned().collect();
+    for k in &param_keys {
+        if let Some(v) = field_attr.get(k) {
+            params.insert(k.to_string(), v.clone());
+        }
+    }
+
+    if let Some(fields) = type_def.get("fields") {
+        let mut result = match replace_type_params(fields, &params) {
+            Value::Object(v) => v,
+            _ => Map::new()
+        };
+        // 保留数组元素上 -length 这样的设置
+        for (k, v) in field_attr {
+            if k.starts_with("-") {
+                result.insert(k.to_string(), v.clone());
+            }
+        }
+        return Value::Object(result);
+    }
+
+    let mut result = match type_def.as_object() {
+        Some(v) => v.clone(),
+        None => Map::new()
+    };
+    result.remove("params");
+    result.remove("name");
+    result.remove("desc");
+    let mut result = match replace_type_params(&Value::Object(result), &params) {
+        Value::Object(v) => v,
+        _ => Map::new()
+    };
+    for (k, v) in field_attr {
+        if k == "type" || param_keys.contains(k) {
+            continue;
+        }
+        result.insert(k.to_string(), v.clone());
+    }
+    Value::Object(result)
+}
+
+
+/// 如果字段的type是自定义类型，返回展开后的字段定义，类型可以嵌套引用其它自定义类型
+pub fn resolve_custom_type(field_attr: &Value, types: &CustomTypes) -> Option<Value> {
+    if types.is_empty() {
+        return None;
+    }
+
+    let mut attr = field_attr.clone();
+    let mut is_resolved = false;
+    for _ in 0..16 {
+        let type_name = match attr.get("type").and_then(|x| x.as_str()) {
+            Some(v) => v.to_lowercase(),
+            None => break
+        };
+        let type_def = match types.get(&type_name) {
+            Some((_, type_def)) => type_def,
+            None => break
+        };
+        attr = match attr.as_object() {
+            Some(attr_obj) => expand_custom_type(type_def, attr_obj),
+            None => break
+        };
+        is_resolved = true;
+        if attr.get("type").and_then(|x| x.as_str()).map(|x| x.to_lowercase()) == Some(type_name.clone()) {
+            break;
+        }
+    }
+
+    if let Some(type_name) = attr.get("type").and_then(|x| x.as_str()) {
+        if is_resolved && types.contains_key(&type_name.to_lowercase()) {
+            println!("custom type {} is nested too deep or refers to itself", type_name);
+            return None;
+        }
+    }
+
+    if is_resolved {
+        Some(attr)
+    } else {
+        None
+    }
+}
+
+
 /// 加载auth认证的相关数据
 pub fn load_auth_data(api_docs: &HashMap<String, ApiDoc>) -> Option<AuthDoc> {
     let auth_files = ["_auth.json5", "_auth.json"];
@@ -148,12 +513,78 @@ pub fn load_auth_data(api_docs: &HashMap<String, ApiDoc>) -> Option<AuthDoc> {
         Some(v) => v.as_str().unwrap(),
         None => "headers"
     };
+    let auth_place = match auth_place.to_lowercase().as_str() {
+        "url" | "query" => "query",
+        "cookie" | "cookies" => "cookie",
+        "form" | "body" | "form-data" => "form",
+        _ => "headers"
+    };
+
+    let auth_type_lower = auth_type.to_lowercase();
+    let is_api_key = auth_type_lower.contains("api");
+
+    // Session 模式下，session id 固定放在cookie中
+    let session = if auth_type_lower == "session" {
+        Some(parse_session_setting(obj.get("session")))
+    } else {
+        None
+    };
+    let auth_place = if session.is_some() { "cookie" } else { auth_place };
+
+    // 没有设置auth_key的时候，根据auth_place和auth_type使用默认的名称
+    let auth_key = match obj.get("auth_key").and_then(|x| x.as_str()) {
+        _ if session.is_some() => session.as_ref().unwrap().cookie_name.clone(),
+        Some(v) => v.to_string(),
+        None => {
+            let key = match auth_place {
+                "headers" => if is_api_key { "x-api-key" } else { "authorization" },
+                "query" => if is_api_key { "api_key" } else { "token" },
+                _ => "token"
+            };
+            key.to_string()
+        }
+    };
+
+    let auth_scheme = match obj.get("auth_scheme").and_then(|x| x.as_str()) {
+        _ if session.is_some() => "".to_string(),
+        Some(v) => v.trim().to_string(),
+        None => {
+            if auth_place == "headers" && auth_key.to_lowercase() == "authorization" {
+                if auth_type_lower.contains("basic") {
+                    "Basic".to_string()
+                } else {
+                    "Bearer".to_string()
+                }
+            } else {
+                "".to_string()
+            }
+        }
+    };
 
     let no_perm_response = match obj.get("no_perm_response") {
         Some(v) => v.clone(),
         None => json!({"code":-1, "error":"no perm to visit"})
     };
 
+    let login = parse_login_setting(obj.get("login"));
+
+    let jwt = if auth_type.to_uppercase() == "JWT" {
+        Some(parse_jwt_setting(obj.get("jwt"), &no_perm_response))
+    } else {
+        None
+    };
+
+    let oauth = match obj.get("oauth") {
+        Some(v) => {
+            if v.as_bool() == Some(false) {
+                None
+            } else {
+                Some(parse_oauth_setting(v, &jwt, &no_perm_response, api_docs))
+            }
+        }
+        None => None
+    };
+
     let mut groups: Vec<AuthData> = Vec::new();
 
     if let Some(test_data_value) = obj.get("groups") {
@@ -190,12 +621,192 @@ pub fn load_auth_data(api_docs: &HashMap<String, ApiDoc>) -> Option<AuthDoc> {
                     None => no_perm_response.clone(),
                 };
 
-                groups.push(AuthData { name: test_data_name.to_string(), desc: test_data_desc.to_string(), users: users, has_perms: has_perms, no_perms: no_perms, no_perm_response: test_data_no_perm_response })
+                groups.push(AuthData { name: test_data_name.to_string(), desc: test_data_desc.to_string(), users: users, has_perms: has_perms, no_perms: no_perms, no_perm_response: test_data_no_perm_response, rate_limit: data.get("rate_limit").cloned().unwrap_or(Value::Null) })
             }
         }
     }
 
-    Some(AuthDoc { name: name.to_string(), desc: desc.to_string(), auth_type: auth_type.to_string(), auth_place: auth_place.to_string(), filename: filename.to_string(), groups: groups, no_perm_response: no_perm_response })
+    Some(AuthDoc { name: name.to_string(), desc: desc.to_string(), auth_type: auth_type.to_string(), auth_place: auth_place.to_string(), auth_key, auth_scheme, filename: filename.to_string(), groups: groups, no_perm_response: no_perm_response, login: login, jwt: jwt, oauth: oauth, session: session, ..Default::default() })
+}
+
+
+/// 解析Session模式的设置
+fn parse_session_setting(session_value: Option<&Value>) -> SessionSetting {
+    let session_value = match session_value {
+        Some(v) => v.clone(),
+        None => json!({})
+    };
+
+    let get_str = |key: &str, default_value: &str| -> String {
+        match session_value.get(key).and_then(|x| x.as_str()) {
+            Some(v) => v.to_string(),
+            None => default_value.to_string()
+        }
+    };
+
+    SessionSetting {
+        cookie_name: get_str("cookie_name", "sessionid"),
+        cookie_path: get_str("cookie_path", "/"),
+        expires_in: session_value.get("expires_in").and_then(|x| x.as_u64()).unwrap_or(1800),
+        sliding: session_value.get("sliding").and_then(|x| x.as_bool()).unwrap_or(true),
+        csrf: session_value.get("csrf").and_then(|x| x.as_bool()).unwrap_or(false),
+        csrf_cookie_name: get_str("csrf_cookie_name", "csrftoken"),
+        csrf_header: get_str("csrf_header", "X-CSRF-Token"),
+        csrf_field: get_str("csrf_field", "csrf_token"),
+        expired_response: match session_value.get("expired_response") {
+            Some(v) => v.clone(),
+            None => json!({"code": -2, "msg": "session expired"})
+        },
+        csrf_response: match session_value.get("csrf_response") {
+            Some(v) => v.clone(),
+            None => json!({"code": -1, "msg": "csrf token missing or incorrect"})
+        },
+    }
+}
+
+
+/// 解析JWT签名设置，RSA/EC的密钥从本地文件中加载
+fn parse_jwt_setting(jwt_value: Option<&Value>, no_perm_response: &Value) -> JwtSetting {
+    let jwt_value = match jwt_value {
+        Some(v) => v.clone(),
+        None => json!({})
+    };
+
+    let get_str = |key: &str, default_value: &str| -> String {
+        match jwt_value.get(key).and_then(|x| x.as_str()) {
+            Some(v) => v.to_string(),
+            None => default_value.to_string()
+        }
+    };
+
+    let read_key_file = |filename: &str| -> Vec<u8> {
+        if filename == "" {
+            return Vec::new();
+        }
+        match fs::read(filename) {
+            Ok(v) => v,
+            Err(e) => {
+                println!("read jwt key file {} error : {:?}", filename, e);
+                Vec::new()
+            }
+        }
+    };
+
+    let private_key = get_str("private_key", "");
+    let public_key = get_str("public_key", "");
+    let private_key_data = read_key_file(&private_key);
+    let public_key_data = read_key_file(&public_key);
+
+    JwtSetting {
+        algorithm: get_str("algorithm", "HS256").to_uppercase(),
+        secret: get_str("secret", "panda-api"),
+        private_key,
+        public_key,
+        private_key_data,
+        public_key_data,
+        expires_in: jwt_value.get("expires_in").and_then(|x| x.as_u64()).unwrap_or(7200),
+        issuer: get_str("issuer", ""),
+        audience: get_str("audience", ""),
+        expired_response: match jwt_value.get("expired_response") {
+            Some(v) => v.clone(),
+            None => json!({"code": -2, "msg": "token expired"})
+        },
+        invalid_response: match jwt_value.get("invalid_response") {
+            Some(v) => v.clone(),
+            None => no_perm_response.clone()
+        },
+    }
+}
+
+
+/// 解析OAuth2设置，id_token的签名没有单独设置时，使用auth的jwt设置
+fn parse_oauth_setting(oauth_value: &Value, jwt: &Option<JwtSetting>, no_perm_response: &Value, api_docs: &HashMap<String, ApiDoc>) -> OAuthSetting {
+    let mut clients = Vec::new();
+    if let Some(items) = oauth_value.get("clients").and_then(|x| x.as_array()) {
+        for item in items {
+            let client_id = match item.get("client_id").and_then(|x| x.as_str()) {
+                Some(v) => v.to_string(),
+                None => continue
+            };
+            let client_secret = item.get("client_secret").and_then(|x| x.as_str()).unwrap_or("").to_string();
+            let redirect_uris = get_array_from_value("redirect_uris", item).unwrap_or_default();
+            clients.push(OAuthClient { client_id, client_secret, redirect_uris });
+        }
+    }
+
+    // scope 可以直接是权限列表(has_perms)，也可以是 {has_perms, no_perms}
+    let mut scopes = HashMap::new();
+    if let Some(items) = oauth_value.get("scopes").and_then(|x| x.as_object()) {
+        for (scope, perms) in items {
+            let oauth_scope = if perms.is_object() {
+                OAuthScope { has_perms: parse_auth_perms(perms.get("has_perms"), api_docs), no_perms: parse_auth_perms(perms.get("no_perms"), api_docs) }
+            } else {
+                OAuthScope { has_perms: parse_auth_perms(Some(perms), api_docs), no_perms: HashMap::new() }
+            };
+            scopes.insert(scope.to_string(), oauth_scope);
+        }
+    }
+
+    let jwt = match oauth_value.get("jwt") {
+        Some(v) => parse_jwt_setting(Some(v), no_perm_response),
+        None => match jwt {
+            Some(v) => v.clone(),
+            None => parse_jwt_setting(None, no_perm_response)
+        }
+    };
+
+    let get_u64 = |key: &str, default_value: u64| -> u64 {
+        oauth_value.get(key).and_then(|x| x.as_u64()).unwrap_or(default_value)
+    };
+
+    OAuthSetting {
+        issuer: oauth_value.get("issuer").and_then(|x| x.as_str()).unwrap_or("").trim_end_matches("/").to_string(),
+        clients,
+        scopes,
+        code_expires_in: get_u64("code_expires_in", 600),
+        access_token_expires_in: get_u64("access_token_expires_in", 3600),
+        refresh_token_expires_in: get_u64("refresh_token_expires_in", 86400 * 30),
+        jwt,
+    }
+}
+
+
+/// 解析_auth.json5中的login设置，没有设置的就不生成登录接口
+fn parse_login_setting(login_value: Option<&Value>) -> Option<LoginSetting> {
+    let login_value = login_value?;
+    if login_value.is_boolean() && login_value.as_bool() == Some(false) {
+        return None;
+    }
+
+    let get_str = |key: &str, default_value: &str| -> String {
+        match login_value.get(key) {
+            Some(v) => match v.as_str() {
+                Some(v) => v.to_string(),
+                None => default_value.to_string()
+            },
+            None => default_value.to_string()
+        }
+    };
+    let get_value = |key: &str, default_value: Value| -> Value {
+        match login_value.get(key) {
+            Some(v) => v.clone(),
+            None => default_value
+        }
+    };
+
+    Some(LoginSetting {
+        url: get_str("url", "/__auth/login/"),
+        method: get_str("method", "POST").to_uppercase(),
+        username_field: get_str("username_field", "username"),
+        password_field: get_str("password_field", "password"),
+        response: get_value("response", json!({"code": 1, "msg": "login success", "token": "{{token}}", "user": "{{user}}"})),
+        fail_response: get_value("fail_response", json!({"code": -1, "msg": "username or password incorrect"})),
+        logout_url: get_str("logout_url", "/__auth/logout/"),
+        logout_response: get_value("logout_response", json!({"code": 1, "msg": "logout success"})),
+        user_url: get_str("user_url", "/__auth/user/"),
+        user_response: get_value("user_response", json!({"code": 1, "data": "{{user}}"})),
+        refresh_url: get_str("refresh_url", "/__auth/refresh/"),
+    })
 }
 
 
@@ -259,27 +870,27 @@ impl Database {
     /// 加载api docs 接口的json数据、配置、相关文档
     pub fn load() -> Database {
         let (basic_data, settings) = load_basic_data();
+        let custom_types = load_custom_types(&settings);
 
         let mut api_docs = HashMap::new();
         let mut api_data: HashMap<String, HashMap<String, Arc<Mutex<ApiData>>>> = HashMap::new();
         let mut fileindex_data: HashMap<String, HashSet<String>> = HashMap::new();
 
-        let mut websocket_api = Arc::new(Mutex::new(ApiData::default()));
-
         for entry in WalkDir::new("./") {
             let e = entry.unwrap();
             let doc_file = e.path().to_str().unwrap().trim_start_matches("./");
-            Self::load_a_api_json_file(doc_file, &basic_data, &mut api_data, &mut api_docs, websocket_api.clone(), &mut fileindex_data);
+            Self::load_a_api_json_file(doc_file, &basic_data, &mut api_data, &mut api_docs, &mut fileindex_data);
         }
 
         let auth_doc = load_auth_data(&api_docs);
-        Database { basic_data, api_data, api_docs, fileindex_data, websocket_api, auth_doc, settings}
+        let proto_schema = grpc::load_proto_schema(&api_docs);
+        Database { basic_data, api_data, api_docs, fileindex_data, auth_doc, settings, custom_types, stores: store::Stores::new(), proto_schema }
     }
 
 
     /// 只加载一个api_doc文件的数据
     ///
-    pub fn load_a_api_json_file(doc_file: &str, basic_data: &BasicData, api_data: &mut HashMap<String, HashMap<String, Arc<Mutex<ApiData>>>>, api_docs: &mut HashMap<String, ApiDoc>, websocket_api: Arc<Mutex<ApiData>>, fileindex_data: &mut HashMap<String, HashSet<String>>) -> i32 {
+    pub fn load_a_api_json_file(doc_file: &str, basic_data: &BasicData, api_data: &mut HashMap<String, HashMap<String, Arc<Mutex<ApiData>>>>, api_docs: &mut HashMap<String, ApiDoc>, fileindex_data: &mut HashMap<String, HashSet<String>>) -> i32 {
         if !(doc_file.ends_with(".json") || doc_file.ends_with(".json5")) || doc_file == "_settings.json" || doc_file == "_settings.json5" || doc_file == "_auth.json" || doc_file == "_auth.json5" || doc_file.contains("_data/") || doc_file.starts_with(".") || doc_file.contains("/.") {
             return -1;
         }
@@ -332,6 +943,19 @@ impl Database {
             None => 0
         };
 
+        let doc_rate_limit = doc_file_obj.get("rate_limit").cloned().unwrap_or(Value::Null);
+
+        // proto: "_data/user.proto" 或者 ["_data/user.proto", "_data/order.proto"]
+        let proto = match doc_file_obj.get("proto") {
+            Some(Value::String(v)) => vec![v.to_string()],
+            Some(Value::Array(v)) => v.iter().filter_map(|x| x.as_str()).map(|x| x.to_string()).collect(),
+            _ => Vec::new()
+        };
+        // proto文件修改后重新加载这个接口文档
+        for proto_file in &proto {
+            fileindex_data.entry(proto_file.trim_start_matches("./").to_string()).or_insert_with(HashSet::new).insert(doc_file.to_string());
+        }
+
         let apis = match doc_file_obj.get("apis") {
             Some(api) => api.clone(),
             None => { json!([]) }
@@ -378,6 +1002,7 @@ impl Database {
                 }
 
                 let body_mode = get_api_field_string_value("body_mode", "json".to_string(), api, &ref_data, &basic_data.global_value);
+                let response_mode = get_api_field_string_value("response_mode", "json".to_string(), api, &ref_data, &basic_data.global_value);
                 let auth = get_api_field_bool_value("auth", false, api, &ref_data, &basic_data.global_value);
 
                 let url_param = match api.get("url_param") {
@@ -443,6 +1068,14 @@ impl Database {
                 // 处理response中的$ref
                 let (mut ref_files2, response) = parse_attribute_ref_value(Value::Object(response), doc_file_obj, doc_file);
 
+                // 检查response字段之间的引用是否有循环依赖
+                let mut cycle_errors = Vec::new();
+                mock::expr::check_cycles(&response, "response", &mut cycle_errors);
+                // 有循环依赖的接口不能生成mock数据，请求时返回错误
+                for e in &cycle_errors {
+                    println!("Load api doc {} error: api {} field dependency cycle: {}", doc_file, url, e);
+                }
+
                 ref_files.append(&mut ref_files2);
                 for ref_file in ref_files {
                     if &ref_file != "" {
@@ -471,13 +1104,85 @@ impl Database {
                     }
                 };
 
-                let o_api_data = ApiData { name, desc, body_mode, body, query, response, test_data, url_param, auth: auth, url: url.clone(), method: method.clone() };
+                // 列表接口的分页设置，根据query中的page、page_size生成对应的数据
+                let pagination = match api.get("pagination") {
+                    Some(v) => v.clone(),
+                    None => {
+                        match ref_data.get("pagination") {
+                            Some(v) => v.clone(),
+                            None => Value::Null
+                        }
+                    }
+                };
+
+                // 接口标签，可以在_auth.json5中按 #标签 授权
+                let tags = match api.get("tags") {
+                    Some(Value::Array(v)) => v.iter().filter_map(|x| x.as_str()).map(|x| x.to_string()).collect(),
+                    Some(Value::String(v)) => vec![v.to_string()],
+                    _ => Vec::new()
+                };
+
+                // 限流设置，如 {limit: 10, window: 60, key: "token"}
+                let rate_limit = match api.get("rate_limit") {
+                    Some(v) => v.clone(),
+                    None => ref_data.get("rate_limit").cloned().unwrap_or(Value::Null)
+                };
+
+                let sse = match api.get("sse") {
+                    Some(v) => v.clone(),
+                    None => ref_data.get("sse").cloned().unwrap_or(Value::Null)
+                };
+
+                let chat = match api.get("chat") {
+                    Some(v) => v.clone(),
+                    None => ref_data.get("chat").cloned().unwrap_or(Value::Null)
+                };
+
+                let push = match api.get("push") {
+                    Some(v) => v.clone(),
+                    None => ref_data.get("push").cloned().unwrap_or(Value::Null)
+                };
+
+                let graphql = match api.get("graphql") {
+                    Some(v) => v.clone(),
+                    None => ref_data.get("graphql").cloned().unwrap_or(Value::Null)
+                };
+                let graphql_schema = if graphql.is_null() {
+                    None
+                } else {
+                    // schema文件修改后重新加载这个接口文档
+                    if let Some(schema_file) = graphql::get_schema_file(&graphql) {
+                        fileindex_data.entry(schema_file.trim_start_matches("./").to_string()).or_insert_with(HashSet::new).insert(doc_file.to_string());
+                    }
+                    match graphql::load_schema(&graphql) {
+                        Ok(v) => Some(Arc::new(v)),
+                        Err(e) => {
+                            println!("Load api doc {} error: api {} {}", doc_file, url, e);
+                            None
+                        }
+                    }
+                };
+
+                let grpc = match api.get("grpc") {
+                    Some(v) => v.clone(),
+                    None => ref_data.get("grpc").cloned().unwrap_or(Value::Null)
+                };
+
+                let store = match api.get("store") {
+                    Some(v) => v.clone(),
+                    None => ref_data.get("store").cloned().unwrap_or(Value::Null)
+                };
+
+                // 收集body、query、response中的枚举值说明，给文档前端展示
+                let mut enums = Map::new();
+                collect_enum_tables(&body, "body", &mut enums);
+                collect_enum_tables(&query, "query", &mut enums);
+                collect_enum_tables(&response, "response", &mut enums);
+                let enums = Value::Object(enums);
+
+                let o_api_data = ApiData { name, desc, body_mode, response_mode, body, query, response, test_data, url_param, enums, pagination, tags, doc_file: doc_file.to_string(), rate_limit, doc_rate_limit: doc_rate_limit.clone(), push, sse, chat, graphql, graphql_schema, grpc, store, auth: auth, url: url.clone(), method: method.clone() };
                 let a_api_data = Arc::new(Mutex::new(o_api_data.clone()));
 
-                if method.contains(&"WEBSOCKET".to_string()) {
-                    let mut websocket_api = websocket_api.lock().unwrap();
-                    *websocket_api = o_api_data.clone();
-                }
                 // 形成 { url: {method:api} }
                 match api_data.get_mut(&url) {
                     Some(data) => {
@@ -498,7 +1203,7 @@ impl Database {
             }
         }
 
-        let api_doc = ApiDoc { name: doc_name, desc: doc_desc, order: doc_order, filename: doc_file.to_string(), apis: api_vec };
+        let api_doc = ApiDoc { name: doc_name, desc: doc_desc, order: doc_order, filename: doc_file.to_string(), apis: api_vec, proto };
         api_docs.insert(doc_file.to_string(), api_doc);
 
         1
@@ -795,19 +1500,166 @@ fn parse_attribute_ref_value(value: Value, doc_file_obj: &Map<String, Value>, do
 }
 
 
-/// auth文件里面，可能是按文件加载接口地址
+/// 解析字段中enum的每一项，返回 (value, desc, weight)
+/// 支持的写法:
+/// 普通值: "成功"
+/// 对象: {value: -1, desc: "不正常", weight: 3}，也兼容 -value, -desc, -weight
+/// 元组: [-1, "不正常"] 或者 [-1, "不正常", 3]，第三个元素为权重
+/// 没有设置weight的，权重默认为1
+pub fn parse_enum_items(enum_data: &Value) -> Vec<(Value, String, u64)> {
+    let mut items = Vec::new();
+    let list = match enum_data.as_array() {
+        Some(list) => list,
+        None => return items
+    };
+
+    for item in list {
+        match item {
+            Value::Object(item_obj) => {
+                let value = match item_obj.get("value") {
+                    Some(v) => v.clone(),
+                    None => {
+                        match item_obj.get("-value") {
+                            Some(v) => v.clone(),
+                            None => item.clone()
+                        }
+                    }
+                };
+                let desc = match item_obj.get("desc").or(item_obj.get("-desc")) {
+                    Some(v) => match v.as_str() {
+                        Some(v) => v.to_string(),
+                        None => format!("{}", v)
+                    },
+                    None => "".to_string()
+                };
+                let weight = match item_obj.get("weight").or(item_obj.get("-weight")) {
+                    Some(v) => v.as_u64().unwrap_or(1),
+                    None => 1
+                };
+                items.push((value, desc, weight));
+            }
+            Value::Array(item_array) if item_array.len() >= 2 && !item_array[0].is_array() && !item_array[0].is_object() => {
+                let desc = match item_array[1].as_str() {
+                    Some(v) => v.to_string(),
+                    None => format!("{}", item_array[1])
+                };
+                let weight = match item_array.get(2) {
+                    Some(v) => v.as_u64().unwrap_or(1),
+                    None => 1
+                };
+                items.push((item_array[0].clone(), desc, weight));
+            }
+            _ => {
+                items.push((item.clone(), "".to_string(), 1));
+            }
+        }
+    }
+    items
+}
+
+
+/// 递归收集字段定义中的enum，形成 {"response.code": [{value, desc, weight}]} 的对照表
+/// 数组里面的字段用 items[].status 这样的路径表示
+fn collect_enum_tables(model: &Value, prefix: &str, result: &mut Map<String, Value>) {
+    match model {
+        Value::Object(model_obj) => {
+            if let Some(enum_data) = model_obj.get("enum") {
+                let mut table = Vec::new();
+                for (value, desc, weight) in parse_enum_items(enum_data) {
+                    table.push(json!({"value": value, "desc": desc, "weight": weight}));
+                }
+                result.insert(prefix.to_string(), Value::Array(table));
+            }
+
+            for (k, v) in model_obj {
+                if k == "enum" || k.starts_with("-") {
+                    continue;
+                }
+                if v.is_object() || v.is_array() {
+                    collect_enum_tables(v, &format!("{}.{}", prefix, k), result);
+                }
+            }
+        }
+        Value::Array(model_array) => {
+            if model_array.len() == 1 {
+                collect_enum_tables(&model_array[0], &format!("{}[]", prefix), result);
+            }
+        }
+        _ => ()
+    }
+}
+
+
+/// 添加一条权限，同一个地址出现多次的时候合并允许的方法，没有设置方法表示所有方法
+fn insert_perm(result: &mut HashMap<String, HashSet<String>>, url: &str, methods: &HashSet<String>) {
+    let methods: HashSet<String> = if methods.is_empty() || methods.contains("*") {
+        vec!["*".to_string()].into_iter().collect()
+    } else {
+        methods.iter().map(|x| x.to_uppercase()).collect()
+    };
+    match result.get_mut(url) {
+        Some(old_methods) => {
+            if old_methods.contains("*") || methods.contains("*") {
+                old_methods.clear();
+                old_methods.insert("*".to_string());
+            } else {
+                old_methods.extend(methods);
+            }
+        }
+        None => {
+            result.insert(url.to_string(), methods);
+        }
+    }
+}
+
+
+/// auth文件里面，可能是按文件($文件名)或者按标签(#标签)加载接口地址
 fn load_all_api_docs_url(result: &mut HashMap<String, HashSet<String>>, doc_file: &str, methods: HashSet<String>, api_docs: &HashMap<String, ApiDoc>) {
+    if doc_file.starts_with("#") {
+        let tag = doc_file.trim_start_matches("#");
+        for (_, api_doc) in api_docs {
+            for a in &api_doc.apis {
+                let api = a.lock().unwrap();
+                if api.tags.iter().any(|x| x == tag) {
+                    insert_perm(result, &api.url, &methods);
+                }
+            }
+        }
+        return;
+    }
     let doc_file = doc_file.trim_start_matches("$");
-    if let Some(api_doc) = api_docs.get(doc_file) {
-        for a in &api_doc.apis {
-            let api = a.lock().unwrap();
-            result.insert(api.url.clone(), methods.clone());
+    match api_docs.get(doc_file) {
+        Some(api_doc) => {
+            for a in &api_doc.apis {
+                let api = a.lock().unwrap();
+                insert_perm(result, &api.url, &methods);
+            }
         }
+        None => println!("auth perms doc file {} not found", doc_file)
     }
 }
 
 
+/// 添加一条权限设置中的地址，$文件名、#标签 会展开为对应的接口地址
+fn add_perm_url(result: &mut HashMap<String, HashSet<String>>, url: &str, methods: HashSet<String>, api_docs: &HashMap<String, ApiDoc>) {
+    let url = url.trim();
+    if url.starts_with("$") || url.starts_with("#") {
+        load_all_api_docs_url(result, url, methods, api_docs);
+        return;
+    }
+    if url.starts_with("re:") {
+        if let Err(e) = Regex::new(url.trim_start_matches("re:")) {
+            println!("auth perms regex {} error: {}", url, e);
+            return;
+        }
+    }
+    insert_perm(result, url, &methods);
+}
+
+
 /// 把权限解析为一个map
+/// 地址可以是具体的接口地址、* 、/admin/* 这样的通配符、/user/{id}/ 这样的路由、re: 开头的正则，
+/// 以及 $文件名、#标签
 fn parse_auth_perms(perms_data: Option<&Value>, api_docs: &HashMap<String, ApiDoc>) -> HashMap<String, HashSet<String>> {
     let mut result: HashMap<String, HashSet<String>> = HashMap::new();
     if let Some(perms) = perms_data {
@@ -816,36 +1668,28 @@ fn parse_auth_perms(perms_data: Option<&Value>, api_docs: &HashMap<String, ApiDo
                 if perm.is_string() {
                     let mut methods = HashSet::new();
                     methods.insert("*".to_string());
-                    let url = perm.as_str().unwrap().trim();
-                    if url.starts_with("$") {
-                        // 按接口文件加载urls
-                        load_all_api_docs_url(&mut result, url, methods, api_docs);
-                    } else {
-                        result.insert(url.to_string(), methods);
-                    }
+                    add_perm_url(&mut result, perm.as_str().unwrap(), methods, api_docs);
                 } else if perm.is_array() {
                     let perms = perm.as_array().unwrap();
                     let mut url = "";
                     let mut methods = HashSet::new();
                     for (i, perm) in perms.iter().enumerate() {
+                        let perm = match perm.as_str() {
+                            Some(v) => v,
+                            None => continue
+                        };
                         if i == 0 {
-                            url = perm.as_str().unwrap();
+                            url = perm;
                         } else {
-                            let perm = perm.as_str().unwrap();
                             methods.insert(perm.to_string());
                         }
                     }
                     // 如果没有设置methods，默认就是所有方法
-                    if url.starts_with("$") {
-                        // 按接口文件加载urls
-                        load_all_api_docs_url(&mut result, url, methods, api_docs);
-                    } else {
-                        result.insert(url.to_string(), methods);
-                    }
+                    add_perm_url(&mut result, url, methods, api_docs);
                 } else if perm.is_object() {
                     let perm = perm.as_object().unwrap();
-                    let url = match perm.get("url") {
-                        Some(url) => url.as_str().unwrap(),
+                    let url = match perm.get("url").and_then(|x| x.as_str()) {
+                        Some(url) => url,
                         None => continue
                     };
 
@@ -858,30 +1702,20 @@ fn parse_auth_perms(perms_data: Option<&Value>, api_docs: &HashMap<String, ApiDo
                         } else if m.is_array() {
                             let m = m.as_array().unwrap();
                             for i in m {
-                                let i = i.as_str().unwrap();
-                                methods.insert(i.to_string());
+                                if let Some(i) = i.as_str() {
+                                    methods.insert(i.to_string());
+                                }
                             }
                         }
                     }
                     // 如果没有设置methods，默认就是所有方法
-                    if url.starts_with("$") {
-                        // 按接口文件加载urls
-                        load_all_api_docs_url(&mut result, url, methods, api_docs);
-                    } else {
-                        result.insert(url.to_string(), methods);
-                    }
+                    add_perm_url(&mut result, url, methods, api_docs);
                 }
             }
         } else if perms.is_string() {
-            let url = perms.as_str().unwrap();
             let mut methods = HashSet::new();
             methods.insert("*".to_string());
-            if url.starts_with("$") {
-                // 按接口文件加载urls
-                load_all_api_docs_url(&mut result, url, methods, api_docs);
-            } else {
-                result.insert(url.to_string(), methods);
-            }
+            add_perm_url(&mut result, perms.as_str().unwrap(), methods, api_docs);
         }
     };
     result
@@ -0,0 +1,1162 @@
+//! GraphQL 接口的mock
+//! 解析 _data/ 下的 SDL schema 文件，执行 query、mutation 和内省查询，
+//! 返回的数据使用和 create_mock_response 一样的字段类型生成
+
+use serde_json::{json, Value, Map};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fs;
+use rand::{thread_rng, Rng};
+
+use crate::api;
+use crate::db;
+
+
+/// 类型引用，如 [User!]!
+#[derive(Debug, Clone)]
+enum TypeRef {
+    Named(String),
+    List(Box<TypeRef>),
+    NonNull(Box<TypeRef>),
+}
+
+
+#[derive(Debug, Clone)]
+struct InputValueDef {
+    name: String,
+    description: String,
+    ty: TypeRef,
+    default_value: Option<String>,
+}
+
+
+#[derive(Debug, Clone)]
+struct FieldDef {
+    name: String,
+    description: String,
+    args: Vec<InputValueDef>,
+    ty: TypeRef,
+}
+
+
+#[derive(Debug, Clone)]
+struct TypeDef {
+    // OBJECT, INPUT_OBJECT, ENUM, SCALAR, INTERFACE, UNION
+    kind: &'static str,
+    name: String,
+    description: String,
+    fields: Vec<FieldDef>,
+    input_fields: Vec<InputValueDef>,
+    enum_values: Vec<String>,
+    interfaces: Vec<String>,
+    possible_types: Vec<String>,
+}
+
+
+/// 加载接口文档时解析好的 schema
+#[derive(Debug, Clone)]
+pub struct Schema {
+    types: HashMap<String, TypeDef>,
+    query_type: String,
+    mutation_type: Option<String>,
+}
+
+
+const BUILTIN_SCALARS: [&str; 5] = ["Int", "Float", "String", "Boolean", "ID"];
+
+// 查询中选择集、值的最大嵌套层数，展开片段后计算
+const MAX_DEPTH: usize = 64;
+// 一次查询最多生成的字段和列表元素数量，片段和别名每次使用都会重新展开
+const MAX_NODES: usize = 100_000;
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Punct(char),
+    Spread,
+    Name(String),
+    Number(String),
+    Str(String),
+}
+
+
+/// 词法分析，SDL 和 query 共用
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() || c == ',' || c == '\u{feff}' {
+            i += 1;
+        } else if c == '#' {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '.' {
+            if i + 2 < chars.len() && chars[i + 1] == '.' && chars[i + 2] == '.' {
+                tokens.push(Token::Spread);
+                i += 3;
+            } else {
+                return Err(format!("unexpected character . at {}", i));
+            }
+        } else if "{}()[]:!=@$|&".contains(c) {
+            tokens.push(Token::Punct(c));
+            i += 1;
+        } else if c == '"' {
+            if i + 2 < chars.len() && chars[i + 1] == '"' && chars[i + 2] == '"' {
+                // """ 块字符串
+                let start = i + 3;
+                let mut end = start;
+                while end + 2 < chars.len() && !(chars[end] == '"' && chars[end + 1] == '"' && chars[end + 2] == '"') {
+                    end += 1;
+                }
+                if end + 2 >= chars.len() {
+                    return Err("unterminated block string".to_string());
+                }
+                let s: String = chars[start..end].iter().collect();
+                tokens.push(Token::Str(s.trim().to_string()));
+                i = end + 3;
+            } else {
+                let mut s = String::new();
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    if chars[i] == '\\' && i + 1 < chars.len() {
+                        i += 1;
+                        s.push(match chars[i] {
+                            'n' => '\n',
+                            't' => '\t',
+                            other => other
+                        });
+                    } else {
+                        s.push(chars[i]);
+                    }
+                    i += 1;
+                }
+                if i >= chars.len() {
+                    return Err("unterminated string".to_string());
+                }
+                tokens.push(Token::Str(s));
+                i += 1;
+            }
+        } else if c == '-' || c.is_ascii_digit() {
+            let start = i;
+            i += 1;
+            while i < chars.len() && (chars[i].is_ascii_digit() || ".eE+-".contains(chars[i])) {
+                i += 1;
+            }
+            tokens.push(Token::Number(chars[start..i].iter().collect()));
+        } else if c == '_' || c.is_ascii_alphabetic() {
+            let start = i;
+            while i < chars.len() && (chars[i] == '_' || chars[i].is_ascii_alphanumeric()) {
+                i += 1;
+            }
+            tokens.push(Token::Name(chars[start..i].iter().collect()));
+        } else {
+            return Err(format!("unexpected character {} at {}", c, i));
+        }
+    }
+    Ok(tokens)
+}
+
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    // 当前的嵌套层数
+    depth: usize,
+}
+
+
+impl Parser {
+    fn new(source: &str) -> Result<Parser, String> {
+        Ok(Parser { tokens: tokenize(source)?, pos: 0, depth: 0 })
+    }
+
+    fn enter(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(format!("nested more than {} levels", MAX_DEPTH));
+        }
+        Ok(())
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn is_end(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    fn is_punct(&self, c: char) -> bool {
+        self.peek() == Some(&Token::Punct(c))
+    }
+
+    fn is_name(&self, name: &str) -> bool {
+        match self.peek() {
+            Some(Token::Name(n)) => n == name,
+            _ => false
+        }
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect_punct(&mut self, c: char) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Punct(p)) if p == c => Ok(()),
+            other => Err(format!("expected {} but found {:?}", c, other))
+        }
+    }
+
+    fn skip_punct(&mut self, c: char) -> bool {
+        if self.is_punct(c) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect_name(&mut self) -> Result<String, String> {
+        match self.next() {
+            Some(Token::Name(n)) => Ok(n),
+            other => Err(format!("expected name but found {:?}", other))
+        }
+    }
+
+    fn description(&mut self) -> String {
+        if let Some(Token::Str(s)) = self.peek() {
+            let s = s.to_string();
+            self.pos += 1;
+            return s;
+        }
+        "".to_string()
+    }
+
+    fn type_ref(&mut self) -> Result<TypeRef, String> {
+        let ty = if self.skip_punct('[') {
+            let inner = self.type_ref()?;
+            self.expect_punct(']')?;
+            TypeRef::List(Box::new(inner))
+        } else {
+            TypeRef::Named(self.expect_name()?)
+        };
+        if self.skip_punct('!') {
+            return Ok(TypeRef::NonNull(Box::new(ty)));
+        }
+        Ok(ty)
+    }
+
+    /// 跳过 @deprecated(reason: "") 这样的指令
+    fn skip_directives(&mut self) -> Result<(), String> {
+        while self.skip_punct('@') {
+            self.expect_name()?;
+            if self.is_punct('(') {
+                self.arguments()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 值的原始文本，用于 defaultValue
+    fn value(&mut self) -> Result<AstValue, String> {
+        match self.next() {
+            Some(Token::Punct('$')) => Ok(AstValue::Variable(self.expect_name()?)),
+            Some(Token::Number(n)) => {
+                if let Ok(v) = n.parse::<i64>() {
+                    Ok(AstValue::Const(json!(v)))
+                } else {
+                    Ok(AstValue::Const(json!(n.parse::<f64>().map_err(|_| format!("invalid number {}", n))?)))
+                }
+            }
+            Some(Token::Str(s)) => Ok(AstValue::Const(json!(s))),
+            Some(Token::Name(n)) => {
+                match n.as_str() {
+                    "true" => Ok(AstValue::Const(json!(true))),
+                    "false" => Ok(AstValue::Const(json!(false))),
+                    "null" => Ok(AstValue::Const(Value::Null)),
+                    _ => Ok(AstValue::Const(json!(n)))
+                }
+            }
+            Some(Token::Punct('[')) => {
+                self.enter()?;
+                let mut items = Vec::new();
+                while !self.skip_punct(']') {
+                    if self.is_end() {
+                        return Err("unterminated list".to_string());
+                    }
+                    items.push(self.value()?);
+                }
+                self.depth -= 1;
+                Ok(AstValue::List(items))
+            }
+            Some(Token::Punct('{')) => {
+                self.enter()?;
+                let mut items = Vec::new();
+                while !self.skip_punct('}') {
+                    let name = self.expect_name()?;
+                    self.expect_punct(':')?;
+                    items.push((name, self.value()?));
+                }
+                self.depth -= 1;
+                Ok(AstValue::Object(items))
+            }
+            other => Err(format!("unexpected value {:?}", other))
+        }
+    }
+
+    fn arguments(&mut self) -> Result<Vec<(String, AstValue)>, String> {
+        let mut args = Vec::new();
+        self.expect_punct('(')?;
+        while !self.skip_punct(')') {
+            let name = self.expect_name()?;
+            self.expect_punct(':')?;
+            args.push((name, self.value()?));
+        }
+        Ok(args)
+    }
+
+    fn input_values(&mut self, end: char) -> Result<Vec<InputValueDef>, String> {
+        let mut values = Vec::new();
+        while !self.skip_punct(end) {
+            if self.is_end() {
+                return Err(format!("expected {}", end));
+            }
+            let description = self.description();
+            let name = self.expect_name()?;
+            self.expect_punct(':')?;
+            let ty = self.type_ref()?;
+            let default_value = if self.skip_punct('=') {
+                Some(self.value()?.to_value(&Map::new()).to_string())
+            } else {
+                None
+            };
+            self.skip_directives()?;
+            values.push(InputValueDef { name, description, ty, default_value });
+        }
+        Ok(values)
+    }
+}
+
+
+/// 解析 SDL
+fn parse_schema(source: &str) -> Result<Schema, String> {
+    let mut p = Parser::new(source)?;
+    let mut types: HashMap<String, TypeDef> = HashMap::new();
+    let mut query_type = "Query".to_string();
+    let mut mutation_type = None;
+
+    while !p.is_end() {
+        let description = p.description();
+        let keyword = p.expect_name()?;
+        let keyword = if keyword == "extend" { p.expect_name()? } else { keyword };
+        match keyword.as_str() {
+            "schema" => {
+                p.skip_directives()?;
+                p.expect_punct('{')?;
+                while !p.skip_punct('}') {
+                    let op = p.expect_name()?;
+                    p.expect_punct(':')?;
+                    let name = p.expect_name()?;
+                    match op.as_str() {
+                        "query" => query_type = name,
+                        "mutation" => mutation_type = Some(name),
+                        _ => ()
+                    }
+                }
+            }
+            "type" | "interface" | "input" => {
+                let name = p.expect_name()?;
+                let mut interfaces = Vec::new();
+                if p.is_name("implements") {
+                    p.next();
+                    p.skip_punct('&');
+                    while let Some(Token::Name(n)) = p.peek() {
+                        interfaces.push(n.to_string());
+                        p.next();
+                        if !p.skip_punct('&') {
+                            break;
+                        }
+                    }
+                }
+                p.skip_directives()?;
+                let kind = match keyword.as_str() {
+                    "type" => "OBJECT",
+                    "interface" => "INTERFACE",
+                    _ => "INPUT_OBJECT"
+                };
+                let mut fields = Vec::new();
+                let mut input_fields = Vec::new();
+                if p.skip_punct('{') {
+                    if kind == "INPUT_OBJECT" {
+                        input_fields = p.input_values('}')?;
+                    } else {
+                        while !p.skip_punct('}') {
+                            if p.is_end() {
+                                return Err(format!("type {} is not closed", name));
+                            }
+                            let description = p.description();
+                            let field_name = p.expect_name()?;
+                            let args = if p.skip_punct('(') { p.input_values(')')? } else { Vec::new() };
+                            p.expect_punct(':')?;
+                            let ty = p.type_ref()?;
+                            p.skip_directives()?;
+                            fields.push(FieldDef { name: field_name, description, args, ty });
+                        }
+                    }
+                }
+                // extend type 合并字段
+                let t = types.entry(name.clone()).or_insert(TypeDef { kind, name, description, fields: Vec::new(), input_fields: Vec::new(), enum_values: Vec::new(), interfaces: Vec::new(), possible_types: Vec::new() });
+                t.fields.extend(fields);
+                t.input_fields.extend(input_fields);
+                t.interfaces.extend(interfaces);
+            }
+            "enum" => {
+                let name = p.expect_name()?;
+                p.skip_directives()?;
+                let mut enum_values = Vec::new();
+                p.expect_punct('{')?;
+                while !p.skip_punct('}') {
+                    p.description();
+                    enum_values.push(p.expect_name()?);
+                    p.skip_directives()?;
+                }
+                types.insert(name.clone(), TypeDef { kind: "ENUM", name, description, fields: Vec::new(), input_fields: Vec::new(), enum_values, interfaces: Vec::new(), possible_types: Vec::new() });
+            }
+            "union" => {
+                let name = p.expect_name()?;
+                p.skip_directives()?;
+                p.expect_punct('=')?;
+                p.skip_punct('|');
+                let mut possible_types = vec![p.expect_name()?];
+                while p.skip_punct('|') {
+                    possible_types.push(p.expect_name()?);
+                }
+                types.insert(name.clone(), TypeDef { kind: "UNION", name, description, fields: Vec::new(), input_fields: Vec::new(), enum_values: Vec::new(), interfaces: Vec::new(), possible_types });
+            }
+            "scalar" => {
+                let name = p.expect_name()?;
+                p.skip_directives()?;
+                types.insert(name.clone(), TypeDef { kind: "SCALAR", name, description, fields: Vec::new(), input_fields: Vec::new(), enum_values: Vec::new(), interfaces: Vec::new(), possible_types: Vec::new() });
+            }
+            "directive" => {
+                // directive @name(args) on LOCATION | LOCATION
+                p.expect_punct('@')?;
+                p.expect_name()?;
+                if p.skip_punct('(') {
+                    p.input_values(')')?;
+                }
+                p.expect_name()?;
+                p.skip_punct('|');
+                while let Some(Token::Name(_)) = p.peek() {
+                    p.next();
+                    if !p.skip_punct('|') {
+                        break;
+                    }
+                }
+            }
+            other => return Err(format!("unknown schema definition {}", other))
+        }
+    }
+
+    for name in BUILTIN_SCALARS.iter() {
+        types.entry(name.to_string()).or_insert(TypeDef { kind: "SCALAR", name: name.to_string(), description: "".to_string(), fields: Vec::new(), input_fields: Vec::new(), enum_values: Vec::new(), interfaces: Vec::new(), possible_types: Vec::new() });
+    }
+
+    // 接口的实现类型
+    let implementations: Vec<(String, String)> = types.values()
+        .flat_map(|t| t.interfaces.iter().map(move |i| (i.to_string(), t.name.to_string())))
+        .collect();
+    for (interface, name) in implementations {
+        if let Some(t) = types.get_mut(&interface) {
+            t.possible_types.push(name);
+        }
+    }
+
+    if mutation_type.is_none() && types.contains_key("Mutation") {
+        mutation_type = Some("Mutation".to_string());
+    }
+    if !types.contains_key(&query_type) {
+        return Err(format!("schema has no query type {}", query_type));
+    }
+    Ok(Schema { types, query_type, mutation_type })
+}
+
+
+#[derive(Debug, Clone)]
+enum AstValue {
+    Variable(String),
+    Const(Value),
+    List(Vec<AstValue>),
+    Object(Vec<(String, AstValue)>),
+}
+
+
+impl AstValue {
+    fn to_value(&self, variables: &Map<String, Value>) -> Value {
+        match self {
+            AstValue::Variable(name) => variables.get(name).cloned().unwrap_or(Value::Null),
+            AstValue::Const(v) => v.clone(),
+            AstValue::List(items) => Value::Array(items.iter().map(|x| x.to_value(variables)).collect()),
+            AstValue::Object(items) => {
+                let mut obj = Map::new();
+                for (k, v) in items {
+                    obj.insert(k.to_string(), v.to_value(variables));
+                }
+                Value::Object(obj)
+            }
+        }
+    }
+}
+
+
+#[derive(Debug, Clone)]
+struct Directive {
+    name: String,
+    args: Vec<(String, AstValue)>,
+}
+
+
+#[derive(Debug, Clone)]
+enum Selection {
+    Field { alias: Option<String>, name: String, args: Vec<(String, AstValue)>, directives: Vec<Directive>, selections: Vec<Selection> },
+    FragmentSpread { name: String, directives: Vec<Directive> },
+    InlineFragment { type_condition: Option<String>, directives: Vec<Directive>, selections: Vec<Selection> },
+}
+
+
+#[derive(Debug, Clone)]
+struct Operation {
+    kind: String,
+    name: Option<String>,
+    variable_defaults: Map<String, Value>,
+    selections: Vec<Selection>,
+}
+
+
+#[derive(Debug, Clone)]
+struct Fragment {
+    type_condition: String,
+    selections: Vec<Selection>,
+}
+
+
+fn parse_directives(p: &mut Parser) -> Result<Vec<Directive>, String> {
+    let mut directives = Vec::new();
+    while p.skip_punct('@') {
+        let name = p.expect_name()?;
+        let args = if p.is_punct('(') { p.arguments()? } else { Vec::new() };
+        directives.push(Directive { name, args });
+    }
+    Ok(directives)
+}
+
+
+fn parse_selections(p: &mut Parser) -> Result<Vec<Selection>, String> {
+    let mut selections = Vec::new();
+    p.expect_punct('{')?;
+    p.enter()?;
+    while !p.skip_punct('}') {
+        if p.is_end() {
+            return Err("selection set is not closed".to_string());
+        }
+        if p.peek() == Some(&Token::Spread) {
+            p.next();
+            if p.is_name("on") {
+                p.next();
+                let type_condition = Some(p.expect_name()?);
+                let directives = parse_directives(p)?;
+                selections.push(Selection::InlineFragment { type_condition, directives, selections: parse_selections(p)? });
+            } else if p.is_punct('{') || p.is_punct('@') {
+                let directives = parse_directives(p)?;
+                selections.push(Selection::InlineFragment { type_condition: None, directives, selections: parse_selections(p)? });
+            } else {
+                let name = p.expect_name()?;
+                selections.push(Selection::FragmentSpread { name, directives: parse_directives(p)? });
+            }
+            continue;
+        }
+        let mut name = p.expect_name()?;
+        let mut alias = None;
+        if p.skip_punct(':') {
+            alias = Some(name);
+            name = p.expect_name()?;
+        }
+        let args = if p.is_punct('(') { p.arguments()? } else { Vec::new() };
+        let directives = parse_directives(p)?;
+        let selections_ = if p.is_punct('{') { parse_selections(p)? } else { Vec::new() };
+        selections.push(Selection::Field { alias, name, args, directives, selections: selections_ });
+    }
+    p.depth -= 1;
+    Ok(selections)
+}
+
+
+/// 展开片段后选择集的层数，片段引用自己时报错
+/// depths 中保存已经计算过的片段层数，visiting 为正在展开的片段
+fn selection_depth(selections: &Vec<Selection>, fragments: &HashMap<String, Fragment>, depths: &mut HashMap<String, usize>, visiting: &mut Vec<String>) -> Result<usize, String> {
+    let mut max = 0;
+    for selection in selections {
+        let depth = match selection {
+            Selection::Field { selections, .. } => selection_depth(selections, fragments, depths, visiting)? + 1,
+            Selection::InlineFragment { selections, .. } => selection_depth(selections, fragments, depths, visiting)?,
+            Selection::FragmentSpread { name, .. } => {
+                if let Some(v) = depths.get(name) {
+                    *v
+                } else if visiting.contains(name) {
+                    visiting.push(name.to_string());
+                    return Err(format!("fragment cycle: {}", visiting.join(" -> ")));
+                } else {
+                    let fragment = match fragments.get(name) {
+                        Some(v) => v,
+                        None => return Err(format!("unknown fragment {}", name))
+                    };
+                    visiting.push(name.to_string());
+                    let v = selection_depth(&fragment.selections, fragments, depths, visiting)?;
+                    visiting.pop();
+                    depths.insert(name.to_string(), v);
+                    v
+                }
+            }
+        };
+        if depth > MAX_DEPTH {
+            return Err(format!("query nested more than {} levels", MAX_DEPTH));
+        }
+        max = max.max(depth);
+    }
+    Ok(max)
+}
+
+
+/// 解析请求中的 query 文档
+fn parse_document(source: &str) -> Result<(Vec<Operation>, HashMap<String, Fragment>), String> {
+    let mut p = Parser::new(source)?;
+    let mut operations = Vec::new();
+    let mut fragments = HashMap::new();
+    while !p.is_end() {
+        if p.is_punct('{') {
+            operations.push(Operation { kind: "query".to_string(), name: None, variable_defaults: Map::new(), selections: parse_selections(&mut p)? });
+            continue;
+        }
+        let keyword = p.expect_name()?;
+        match keyword.as_str() {
+            "query" | "mutation" | "subscription" => {
+                let has_name = match p.peek() {
+                    Some(Token::Name(_)) => true,
+                    _ => false
+                };
+                let name = if has_name { Some(p.expect_name()?) } else { None };
+                let mut variable_defaults = Map::new();
+                if p.skip_punct('(') {
+                    while !p.skip_punct(')') {
+                        p.expect_punct('$')?;
+                        let var_name = p.expect_name()?;
+                        p.expect_punct(':')?;
+                        p.type_ref()?;
+                        if p.skip_punct('=') {
+                            variable_defaults.insert(var_name, p.value()?.to_value(&Map::new()));
+                        }
+                        parse_directives(&mut p)?;
+                    }
+                }
+                parse_directives(&mut p)?;
+                operations.push(Operation { kind: keyword, name, variable_defaults, selections: parse_selections(&mut p)? });
+            }
+            "fragment" => {
+                let name = p.expect_name()?;
+                if !p.is_name("on") {
+                    return Err(format!("fragment {} has no type condition", name));
+                }
+                p.next();
+                let type_condition = p.expect_name()?;
+                parse_directives(&mut p)?;
+                fragments.insert(name, Fragment { type_condition, selections: parse_selections(&mut p)? });
+            }
+            other => return Err(format!("unexpected {}", other))
+        }
+    }
+
+    // 片段不能循环引用，展开后的层数也不能太多
+    let mut depths = HashMap::new();
+    for fragment in fragments.values() {
+        selection_depth(&fragment.selections, &fragments, &mut depths, &mut Vec::new())?;
+    }
+    for operation in &operations {
+        selection_depth(&operation.selections, &fragments, &mut depths, &mut Vec::new())?;
+    }
+    Ok((operations, fragments))
+}
+
+
+/// 执行查询时的上下文
+struct Executor<'a> {
+    schema: &'a Schema,
+    fragments: &'a HashMap<String, Fragment>,
+    variables: Map<String, Value>,
+    // 字段的覆盖设置，key 为 类型.字段，如 User.name: {type: "cname"}，Query.users: {length: 5}
+    fields: &'a Map<String, Value>,
+    // 自定义的字段类型
+    types: &'a db::CustomTypes,
+    errors: Vec<String>,
+    // 剩余可以生成的字段数量，用完后不再生成
+    nodes: Cell<usize>,
+}
+
+
+impl<'a> Executor<'a> {
+    /// 使用一个字段的额度，额度用完时返回false
+    fn take_node(&self) -> bool {
+        let nodes = self.nodes.get();
+        if nodes == 0 {
+            return false;
+        }
+        self.nodes.set(nodes - 1);
+        true
+    }
+
+    /// @include(if:) @skip(if:)
+    fn is_included(&self, directives: &Vec<Directive>) -> bool {
+        for d in directives {
+            let cond = d.args.iter().find(|(k, _)| k == "if").map(|(_, v)| v.to_value(&self.variables)).unwrap_or(Value::Null);
+            if d.name == "include" && cond != json!(true) {
+                return false;
+            }
+            if d.name == "skip" && cond == json!(true) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// 展开片段，返回 (返回的key, 字段名, 参数, 子查询)
+    fn collect_fields(&self, type_name: &str, selections: &Vec<Selection>, result: &mut Vec<(String, String, Vec<(String, AstValue)>, Vec<Selection>)>) {
+        for selection in selections {
+            if !self.take_node() {
+                return;
+            }
+            match selection {
+                Selection::Field { alias, name, args, directives, selections } => {
+                    if !self.is_included(directives) {
+                        continue;
+                    }
+                    let key = alias.clone().unwrap_or(name.clone());
+                    // 相同key的字段合并子查询
+                    match result.iter_mut().find(|x| x.0 == key) {
+                        Some(x) => x.3.extend(selections.iter().cloned()),
+                        None => result.push((key, name.clone(), args.clone(), selections.clone()))
+                    }
+                }
+                Selection::FragmentSpread { name, directives } => {
+                    if !self.is_included(directives) {
+                        continue;
+                    }
+                    if let Some(fragment) = self.fragments.get(name) {
+                        if self.is_type_match(type_name, &fragment.type_condition) {
+                            self.collect_fields(type_name, &fragment.selections, result);
+                        }
+                    }
+                }
+                Selection::InlineFragment { type_condition, directives, selections } => {
+                    if !self.is_included(directives) {
+                        continue;
+                    }
+                    let is_match = match type_condition {
+                        Some(t) => self.is_type_match(type_name, t),
+                        None => true
+                    };
+                    if is_match {
+                        self.collect_fields(type_name, selections, result);
+                    }
+                }
+            }
+        }
+    }
+
+    fn is_type_match(&self, type_name: &str, condition: &str) -> bool {
+        if type_name == condition {
+            return true;
+        }
+        match self.schema.types.get(condition) {
+            Some(t) => t.possible_types.iter().any(|x| x == type_name),
+            None => false
+        }
+    }
+
+    /// 按照类型生成一个对象
+    fn resolve_object(&mut self, type_name: &str, selections: &Vec<Selection>) -> Value {
+        let mut fields = Vec::new();
+        self.collect_fields(type_name, selections, &mut fields);
+
+        let type_def = match self.schema.types.get(type_name) {
+            Some(t) => t.clone(),
+            None => return Value::Null
+        };
+        let mut result = Map::new();
+        for (key, name, args, sub_selections) in fields {
+            if name == "__typename" {
+                result.insert(key, json!(type_name));
+                continue;
+            }
+            if type_name == self.schema.query_type && name == "__schema" {
+                let v = introspection_schema(self.schema);
+                result.insert(key, self.select_json(&v, &sub_selections));
+                continue;
+            }
+            if type_name == self.schema.query_type && name == "__type" {
+                let type_arg = args.iter().find(|(k, _)| k == "name").map(|(_, v)| v.to_value(&self.variables));
+                let v = match type_arg.as_ref().and_then(|x| x.as_str()).and_then(|x| self.schema.types.get(x)) {
+                    Some(t) => introspection_type(self.schema, t),
+                    None => Value::Null
+                };
+                result.insert(key, self.select_json(&v, &sub_selections));
+                continue;
+            }
+            let field_def = match type_def.fields.iter().find(|x| x.name == name) {
+                Some(v) => v.clone(),
+                None => {
+                    self.errors.push(format!("Cannot query field \"{}\" on type \"{}\"", name, type_name));
+                    continue;
+                }
+            };
+            let path = format!("{}.{}", type_name, name);
+            let value = self.resolve_type(&field_def.ty, &path, &sub_selections);
+            result.insert(key, value);
+        }
+        Value::Object(result)
+    }
+
+    fn resolve_type(&mut self, ty: &TypeRef, path: &str, selections: &Vec<Selection>) -> Value {
+        if !self.take_node() {
+            return Value::Null;
+        }
+        let setting = self.fields.get(path).cloned().unwrap_or(Value::Null);
+        if let Some(v) = setting.get("value") {
+            return v.clone();
+        }
+        match ty {
+            TypeRef::NonNull(inner) => self.resolve_type(inner, path, selections),
+            TypeRef::List(inner) => {
+                let length = match setting.get("length").or(setting.get("-length")).and_then(|x| x.as_u64()) {
+                    Some(v) => v,
+                    None => {
+                        let min = setting.get("min_length").and_then(|x| x.as_u64()).unwrap_or(1);
+                        let max = setting.get("max_length").and_then(|x| x.as_u64()).unwrap_or(5).max(min);
+                        thread_rng().gen_range(min, max + 1)
+                    }
+                };
+                let mut items = Vec::new();
+                for _ in 0..length {
+                    if self.nodes.get() == 0 {
+                        break;
+                    }
+                    items.push(self.resolve_type(inner, path, selections));
+                }
+                Value::Array(items)
+            }
+            TypeRef::Named(name) => {
+                let type_def = match self.schema.types.get(name) {
+                    Some(t) => t.clone(),
+                    None => return Value::Null
+                };
+                match type_def.kind {
+                    "SCALAR" => mock_scalar(name, &setting, self.types),
+                    "ENUM" => {
+                        if type_def.enum_values.is_empty() {
+                            return Value::Null;
+                        }
+                        let i = thread_rng().gen_range(0, type_def.enum_values.len());
+                        json!(type_def.enum_values[i])
+                    }
+                    "INTERFACE" | "UNION" => {
+                        // 随机使用一个实现类型
+                        if type_def.possible_types.is_empty() {
+                            return Value::Null;
+                        }
+                        let i = thread_rng().gen_range(0, type_def.possible_types.len());
+                        let concrete = type_def.possible_types[i].to_string();
+                        self.resolve_object(&concrete, selections)
+                    }
+                    _ => self.resolve_object(name, selections)
+                }
+            }
+        }
+    }
+
+    /// 按照查询从json中选择字段，用于内省查询
+    fn select_json(&self, value: &Value, selections: &Vec<Selection>) -> Value {
+        match value {
+            Value::Array(items) => Value::Array(items.iter().map(|x| self.select_json(x, selections)).collect()),
+            Value::Object(obj) => {
+                let type_name = obj.get("__typename").and_then(|x| x.as_str()).unwrap_or("").to_string();
+                let mut fields = Vec::new();
+                self.collect_fields(&type_name, selections, &mut fields);
+                let mut result = Map::new();
+                for (key, name, _, sub_selections) in fields {
+                    let v = obj.get(&name).unwrap_or(&Value::Null);
+                    result.insert(key, self.select_json(v, &sub_selections));
+                }
+                Value::Object(result)
+            }
+            _ => value.clone()
+        }
+    }
+}
+
+
+/// 按照GraphQL的类型生成mock数据，使用和response字段一样的类型，字段设置可以覆盖
+fn mock_scalar(type_name: &str, setting: &Value, types: &db::CustomTypes) -> Value {
+    let field_type = match type_name {
+        "Int" => "posint",
+        "Float" => "posfloat",
+        "Boolean" => "bool",
+        "ID" => "uuid",
+        "String" => "string",
+        _ => {
+            let lower = type_name.to_lowercase();
+            if lower.contains("datetime") || lower == "time" {
+                "datetime"
+            } else if lower.contains("date") {
+                "date"
+            } else if lower.contains("url") || lower.contains("uri") {
+                "url"
+            } else if lower.contains("email") {
+                "email"
+            } else {
+                "string"
+            }
+        }
+    };
+    let mut attr = match setting.as_object() {
+        Some(v) => v.clone(),
+        None => Map::new()
+    };
+    if !attr.contains_key("type") {
+        attr.insert("type".to_string(), json!(field_type));
+    }
+    match api::try_create_mock_response(&json!({"v": attr}), types) {
+        Ok(mut v) => v.remove("v").unwrap_or(Value::Null),
+        Err(_) => Value::Null
+    }
+}
+
+
+fn introspection_type_ref(schema: &Schema, ty: &TypeRef) -> Value {
+    match ty {
+        TypeRef::NonNull(inner) => json!({"__typename": "__Type", "kind": "NON_NULL", "name": null, "ofType": introspection_type_ref(schema, inner)}),
+        TypeRef::List(inner) => json!({"__typename": "__Type", "kind": "LIST", "name": null, "ofType": introspection_type_ref(schema, inner)}),
+        TypeRef::Named(name) => {
+            let kind = schema.types.get(name).map(|x| x.kind).unwrap_or("SCALAR");
+            json!({"__typename": "__Type", "kind": kind, "name": name, "ofType": null})
+        }
+    }
+}
+
+
+fn introspection_input_values(schema: &Schema, values: &Vec<InputValueDef>) -> Value {
+    Value::Array(values.iter().map(|x| json!({
+        "__typename": "__InputValue",
+        "name": x.name,
+        "description": description_value(&x.description),
+        "type": introspection_type_ref(schema, &x.ty),
+        "defaultValue": x.default_value,
+    })).collect())
+}
+
+
+fn description_value(description: &str) -> Value {
+    if description == "" { Value::Null } else { json!(description) }
+}
+
+
+fn introspection_type(schema: &Schema, t: &TypeDef) -> Value {
+    let named_types = |names: &Vec<String>| -> Value {
+        Value::Array(names.iter().map(|x| introspection_type_ref(schema, &TypeRef::Named(x.to_string()))).collect())
+    };
+    let mut fields = Value::Null;
+    let mut input_fields = Value::Null;
+    let mut interfaces = Value::Null;
+    let mut enum_values = Value::Null;
+    let mut possible_types = Value::Null;
+    match t.kind {
+        "OBJECT" | "INTERFACE" => {
+            fields = Value::Array(t.fields.iter().map(|f| json!({
+                "__typename": "__Field",
+                "name": f.name,
+                "description": description_value(&f.description),
+                "args": introspection_input_values(schema, &f.args),
+                "type": introspection_type_ref(schema, &f.ty),
+                "isDeprecated": false,
+                "deprecationReason": null,
+            })).collect());
+            if t.kind == "OBJECT" {
+                interfaces = named_types(&t.interfaces);
+            } else {
+                possible_types = named_types(&t.possible_types);
+            }
+        }
+        "UNION" => possible_types = named_types(&t.possible_types),
+        "INPUT_OBJECT" => input_fields = introspection_input_values(schema, &t.input_fields),
+        "ENUM" => {
+            enum_values = Value::Array(t.enum_values.iter().map(|x| json!({
+                "__typename": "__EnumValue", "name": x, "description": null, "isDeprecated": false, "deprecationReason": null
+            })).collect());
+        }
+        _ => ()
+    }
+    json!({
+        "__typename": "__Type",
+        "kind": t.kind,
+        "name": t.name,
+        "description": description_value(&t.description),
+        "fields": fields,
+        "inputFields": input_fields,
+        "interfaces": interfaces,
+        "enumValues": enum_values,
+        "possibleTypes": possible_types,
+        "ofType": null,
+    })
+}
+
+
+/// 生成内省查询的 __schema 数据
+fn introspection_schema(schema: &Schema) -> Value {
+    let mut names: Vec<&String> = schema.types.keys().collect();
+    names.sort();
+    let types: Vec<Value> = names.iter().map(|x| introspection_type(schema, &schema.types[*x])).collect();
+    let type_name = |name: &Option<String>| -> Value {
+        match name {
+            Some(n) => json!({"__typename": "__Type", "kind": "OBJECT", "name": n}),
+            None => Value::Null
+        }
+    };
+    let bool_arg = json!({"__typename": "__InputValue", "name": "if", "description": null, "defaultValue": null,
+        "type": {"__typename": "__Type", "kind": "NON_NULL", "name": null, "ofType": {"__typename": "__Type", "kind": "SCALAR", "name": "Boolean", "ofType": null}}});
+    json!({
+        "__typename": "__Schema",
+        "description": null,
+        "queryType": type_name(&Some(schema.query_type.clone())),
+        "mutationType": type_name(&schema.mutation_type),
+        "subscriptionType": null,
+        "types": types,
+        "directives": [
+            {"__typename": "__Directive", "name": "include", "description": null, "locations": ["FIELD", "FRAGMENT_SPREAD", "INLINE_FRAGMENT"], "args": [bool_arg.clone()]},
+            {"__typename": "__Directive", "name": "skip", "description": null, "locations": ["FIELD", "FRAGMENT_SPREAD", "INLINE_FRAGMENT"], "args": [bool_arg]}
+        ]
+    })
+}
+
+
+fn graphql_error(msg: &str) -> Value {
+    json!({"data": null, "errors": [{"message": msg}]})
+}
+
+
+/// 接口的 graphql 设置中的 schema 文件
+pub fn get_schema_file(setting: &Value) -> Option<&str> {
+    setting.get("schema").and_then(|x| x.as_str())
+}
+
+
+/// 读取并解析接口的 schema 文件，在加载接口文档的时候调用
+pub fn load_schema(setting: &Value) -> Result<Schema, String> {
+    let schema_file = match get_schema_file(setting) {
+        Some(v) => v,
+        None => return Err("graphql schema file is not set".to_string())
+    };
+    let source = match fs::read_to_string(schema_file) {
+        Ok(v) => v,
+        Err(e) => return Err(format!("read graphql schema file {} error: {}", schema_file, e))
+    };
+    parse_schema(&source).map_err(|e| format!("parse graphql schema file {} error: {}", schema_file, e))
+}
+
+
+/// 执行一个GraphQL请求，返回结果和匹配的test_data序号
+/// setting 为接口的 graphql 设置: {schema: "_data/schema.graphql", fields: {"User.name": {type: "cname"}}}
+/// schema 为加载接口文档时解析好的 schema，加载失败时为None
+/// request 为 {query, operationName, variables}
+pub fn execute(setting: &Value, schema: Option<&Schema>, test_data: &Value, request: &Value, types: &db::CustomTypes) -> (Value, Option<usize>) {
+    let schema = match schema {
+        Some(v) => v,
+        None => return (graphql_error(&format!("graphql schema file {} is not loaded", get_schema_file(setting).unwrap_or(""))), None)
+    };
+
+    let query = match request.get("query").and_then(|x| x.as_str()) {
+        Some(v) => v,
+        None => return (graphql_error("Must provide query string."), None)
+    };
+    let (operations, fragments) = match parse_document(query) {
+        Ok(v) => v,
+        Err(e) => return (graphql_error(&format!("Syntax Error: {}", e)), None)
+    };
+    let operation_name = request.get("operationName").and_then(|x| x.as_str());
+    let operation = match operation_name {
+        Some(name) => operations.iter().find(|x| x.name.as_ref().map(|n| n.as_str()) == Some(name)),
+        None => if operations.len() == 1 { operations.get(0) } else { None }
+    };
+    let operation = match operation {
+        Some(v) => v,
+        None => return (graphql_error("Must provide a valid operation name."), None)
+    };
+
+    // GET 请求的 variables 是字符串
+    let mut variables = match request.get("variables") {
+        Some(Value::Object(v)) => v.clone(),
+        Some(Value::String(s)) => serde_json::from_str(s).unwrap_or(Map::new()),
+        _ => Map::new()
+    };
+    for (k, v) in &operation.variable_defaults {
+        if !variables.contains_key(k) {
+            variables.insert(k.to_string(), v.clone());
+        }
+    }
+
+    // test_data 按照 operation 和 variables 匹配
+    if let Some(test_data) = test_data.as_array() {
+        let op_name = operation.name.clone().unwrap_or_default();
+        for (i, test_case_data) in test_data.iter().enumerate() {
+            if let Some(v) = test_case_data.get("operation").and_then(|x| x.as_str()) {
+                if v != op_name {
+                    continue;
+                }
+            }
+            if let Some(v) = test_case_data.get("variables") {
+                if !api::is_value_equal(&Value::Object(variables.clone()), v) {
+                    continue;
+                }
+            }
+            let response = test_case_data.get("response").cloned().unwrap_or(Value::Null);
+            if response.get("data").is_some() || response.get("errors").is_some() {
+                return (response, Some(i));
+            }
+            return (json!({"data": response}), Some(i));
+        }
+    }
+
+    let root_type = match operation.kind.as_str() {
+        "mutation" => match &schema.mutation_type {
+            Some(v) => v.clone(),
+            None => return (graphql_error("Schema is not configured for mutations."), None)
+        },
+        "subscription" => return (graphql_error("Subscriptions are not supported."), None),
+        _ => schema.query_type.clone()
+    };
+
+    let empty = Map::new();
+    let fields = setting.get("fields").and_then(|x| x.as_object()).unwrap_or(&empty);
+    let mut executor = Executor { schema, fragments: &fragments, variables, fields, types, errors: Vec::new(), nodes: Cell::new(MAX_NODES) };
+    let data = executor.resolve_object(&root_type, &operation.selections);
+    if executor.nodes.get() == 0 {
+        return (graphql_error(&format!("query resolves more than {} fields", MAX_NODES)), None);
+    }
+    if executor.errors.is_empty() {
+        (json!({"data": data}), None)
+    } else {
+        let errors: Vec<Value> = executor.errors.iter().map(|x| json!({"message": x})).collect();
+        (json!({"data": data, "errors": errors}), None)
+    }
+}
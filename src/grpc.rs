@@ -0,0 +1,1213 @@
+//! gRPC 接口的mock
+//! 接口文档中 proto 设置的 .proto 文件定义服务，每个rpc的接口地址为 /包名.服务名/方法名，文档中用 method: "GRPC" 的接口设置test_data
+//! HTTP/2 在单独的端口上提供服务，浏览器使用的 gRPC-Web 和普通接口共用端口
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::net::ToSocketAddrs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix_web::{web, HttpRequest, HttpResponse};
+use bytes::Bytes;
+use rand::{thread_rng, Rng};
+use serde_json::{json, Map, Value};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::api;
+use crate::db;
+
+
+const SCALAR_TYPES: [&str; 15] = ["double", "float", "int32", "int64", "uint32", "uint64", "sint32", "sint64", "fixed32", "fixed64", "sfixed32", "sfixed64", "bool", "string", "bytes"];
+
+/// 生成mock消息时嵌套消息的最大层数，避免递归的消息无限生成
+const MAX_MOCK_DEPTH: usize = 3;
+
+/// 解码请求消息时嵌套消息的最大层数
+const MAX_DECODE_DEPTH: usize = 100;
+
+
+#[derive(Debug, Clone)]
+struct ProtoField {
+    name: String,
+    number: u64,
+    // 标量类型名，或者消息、枚举的完整名称，map字段为value的类型
+    ty: String,
+    repeated: bool,
+    map_key: Option<String>,
+    oneof: Option<String>,
+}
+
+
+#[derive(Debug, Clone)]
+struct ProtoMethod {
+    input: String,
+    output: String,
+    client_streaming: bool,
+    server_streaming: bool,
+    // 所在的包名，用于查找消息类型
+    scope: String,
+}
+
+
+/// 所有proto文件中的消息、枚举和服务方法，加载接口文档时解析
+#[derive(Debug, Default)]
+pub struct ProtoSchema {
+    messages: HashMap<String, Vec<ProtoField>>,
+    enums: HashMap<String, Vec<(String, i64)>>,
+    // {"/包名.服务名/方法名": method}
+    methods: HashMap<String, ProtoMethod>,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Word(String),
+    Str(String),
+    Punct(char),
+}
+
+
+fn tokenize(source: &str) -> Vec<Tok> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && i + 1 < chars.len() && chars[i + 1] == '/' {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && i + 1 < chars.len() && chars[i + 1] == '*' {
+            i += 2;
+            while i + 1 < chars.len() && !(chars[i] == '*' && chars[i + 1] == '/') {
+                i += 1;
+            }
+            i += 2;
+        } else if c == '"' || c == '\'' {
+            let mut s = String::new();
+            i += 1;
+            while i < chars.len() && chars[i] != c {
+                if chars[i] == '\\' && i + 1 < chars.len() {
+                    i += 1;
+                }
+                s.push(chars[i]);
+                i += 1;
+            }
+            tokens.push(Tok::Str(s));
+            i += 1;
+        } else if c.is_alphanumeric() || "_.-+".contains(c) {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || "_.-+".contains(chars[i])) {
+                i += 1;
+            }
+            tokens.push(Tok::Word(chars[start..i].iter().collect()));
+        } else {
+            tokens.push(Tok::Punct(c));
+            i += 1;
+        }
+    }
+    tokens
+}
+
+
+struct ProtoParser {
+    tokens: Vec<Tok>,
+    pos: usize,
+}
+
+
+impl ProtoParser {
+    fn next(&mut self) -> Option<Tok> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn is_punct(&self, c: char) -> bool {
+        self.tokens.get(self.pos) == Some(&Tok::Punct(c))
+    }
+
+    fn skip_punct(&mut self, c: char) -> bool {
+        if self.is_punct(c) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect_punct(&mut self, c: char) -> Result<(), String> {
+        match self.next() {
+            Some(Tok::Punct(p)) if p == c => Ok(()),
+            other => Err(format!("expected {} but found {:?}", c, other))
+        }
+    }
+
+    fn word(&mut self) -> Result<String, String> {
+        match self.next() {
+            Some(Tok::Word(w)) => Ok(w),
+            other => Err(format!("expected name but found {:?}", other))
+        }
+    }
+
+    fn number(&mut self) -> Result<i64, String> {
+        let w = self.word()?;
+        let n = if w.starts_with("0x") || w.starts_with("0X") {
+            i64::from_str_radix(&w[2..], 16).ok()
+        } else {
+            w.parse::<i64>().ok()
+        };
+        n.ok_or(format!("invalid number {}", w))
+    }
+
+    /// 跳过到分号，如 option、reserved 和字段后面的 [packed = true]
+    fn skip_statement(&mut self) {
+        let mut depth = 0;
+        while let Some(token) = self.next() {
+            match token {
+                Tok::Punct('{') | Tok::Punct('[') | Tok::Punct('(') => depth += 1,
+                Tok::Punct('}') | Tok::Punct(']') | Tok::Punct(')') => depth -= 1,
+                Tok::Punct(';') if depth <= 0 => break,
+                _ => ()
+            }
+        }
+    }
+
+    fn skip_block(&mut self) -> Result<(), String> {
+        self.expect_punct('{')?;
+        let mut depth = 1;
+        while depth > 0 {
+            match self.next() {
+                Some(Tok::Punct('{')) => depth += 1,
+                Some(Tok::Punct('}')) => depth -= 1,
+                Some(_) => (),
+                None => return Err("block is not closed".to_string())
+            }
+        }
+        Ok(())
+    }
+}
+
+
+fn full_name(scope: &str, name: &str) -> String {
+    if scope == "" { name.to_string() } else { format!("{}.{}", scope, name) }
+}
+
+
+fn parse_enum(p: &mut ProtoParser, scope: &str, schema: &mut ProtoSchema) -> Result<(), String> {
+    let name = full_name(scope, &p.word()?);
+    p.expect_punct('{')?;
+    let mut values = Vec::new();
+    loop {
+        match p.next() {
+            Some(Tok::Punct('}')) => break,
+            Some(Tok::Punct(';')) => (),
+            Some(Tok::Word(w)) => {
+                if w == "option" || w == "reserved" {
+                    p.skip_statement();
+                    continue;
+                }
+                p.expect_punct('=')?;
+                values.push((w, p.number()?));
+                p.skip_statement();
+            }
+            other => return Err(format!("unexpected {:?} in enum {}", other, name))
+        }
+    }
+    schema.enums.insert(name, values);
+    Ok(())
+}
+
+
+fn parse_message_body(p: &mut ProtoParser, name: &str, oneof: Option<String>, fields: &mut Vec<ProtoField>, schema: &mut ProtoSchema) -> Result<(), String> {
+    loop {
+        let word = match p.next() {
+            Some(Tok::Punct('}')) => return Ok(()),
+            Some(Tok::Punct(';')) => continue,
+            Some(Tok::Word(w)) => w,
+            other => return Err(format!("unexpected {:?} in message {}", other, name))
+        };
+        match word.as_str() {
+            "message" => parse_message(p, name, schema)?,
+            "enum" => parse_enum(p, name, schema)?,
+            "option" | "reserved" | "extensions" => p.skip_statement(),
+            "extend" => {
+                p.word()?;
+                p.skip_block()?;
+            }
+            "oneof" => {
+                let oneof_name = p.word()?;
+                p.expect_punct('{')?;
+                parse_message_body(p, name, Some(oneof_name), fields, schema)?;
+            }
+            "map" => {
+                p.expect_punct('<')?;
+                let key = p.word()?;
+                p.expect_punct(',')?;
+                let value = p.word()?;
+                p.expect_punct('>')?;
+                let field_name = p.word()?;
+                p.expect_punct('=')?;
+                let number = p.number()? as u64;
+                p.skip_statement();
+                fields.push(ProtoField { name: field_name, number, ty: value, repeated: true, map_key: Some(key), oneof: oneof.clone() });
+            }
+            _ => {
+                let (repeated, ty) = match word.as_str() {
+                    "repeated" => (true, p.word()?),
+                    "optional" | "required" => (false, p.word()?),
+                    _ => (false, word.clone())
+                };
+                let field_name = p.word()?;
+                p.expect_punct('=')?;
+                let number = p.number()? as u64;
+                p.skip_statement();
+                fields.push(ProtoField { name: field_name, number, ty, repeated, map_key: None, oneof: oneof.clone() });
+            }
+        }
+    }
+}
+
+
+fn parse_message(p: &mut ProtoParser, scope: &str, schema: &mut ProtoSchema) -> Result<(), String> {
+    let name = full_name(scope, &p.word()?);
+    p.expect_punct('{')?;
+    let mut fields = Vec::new();
+    parse_message_body(p, &name, None, &mut fields, schema)?;
+    schema.messages.insert(name, fields);
+    Ok(())
+}
+
+
+fn parse_service(p: &mut ProtoParser, package: &str, schema: &mut ProtoSchema) -> Result<(), String> {
+    let service = full_name(package, &p.word()?);
+    p.expect_punct('{')?;
+    loop {
+        match p.next() {
+            Some(Tok::Punct('}')) => break,
+            Some(Tok::Punct(';')) => (),
+            Some(Tok::Word(w)) if w == "option" => p.skip_statement(),
+            Some(Tok::Word(w)) if w == "rpc" => {
+                let method = p.word()?;
+                p.expect_punct('(')?;
+                let mut input = p.word()?;
+                let client_streaming = input == "stream";
+                if client_streaming {
+                    input = p.word()?;
+                }
+                p.expect_punct(')')?;
+                if p.word()? != "returns" {
+                    return Err(format!("rpc {} has no returns", method));
+                }
+                p.expect_punct('(')?;
+                let mut output = p.word()?;
+                let server_streaming = output == "stream";
+                if server_streaming {
+                    output = p.word()?;
+                }
+                p.expect_punct(')')?;
+                if p.is_punct('{') {
+                    p.skip_block()?;
+                } else {
+                    p.skip_punct(';');
+                }
+                schema.methods.insert(format!("/{}/{}", service, method), ProtoMethod { input, output, client_streaming, server_streaming, scope: package.to_string() });
+            }
+            other => return Err(format!("unexpected {:?} in service {}", other, service))
+        }
+    }
+    Ok(())
+}
+
+
+/// 解析一个proto文件，返回其中import的文件
+fn parse_proto_file(source: &str, schema: &mut ProtoSchema) -> Result<Vec<String>, String> {
+    let mut p = ProtoParser { tokens: tokenize(source), pos: 0 };
+    let mut package = String::new();
+    let mut imports = Vec::new();
+    while let Some(token) = p.next() {
+        let word = match token {
+            Tok::Punct(';') => continue,
+            Tok::Word(w) => w,
+            other => return Err(format!("unexpected {:?}", other))
+        };
+        match word.as_str() {
+            "syntax" | "edition" | "option" => p.skip_statement(),
+            "package" => {
+                package = p.word()?;
+                p.skip_statement();
+            }
+            "import" => {
+                // import public "x.proto"; import weak "x.proto";
+                let mut token = p.next();
+                if let Some(Tok::Word(_)) = token {
+                    token = p.next();
+                }
+                if let Some(Tok::Str(s)) = token {
+                    imports.push(s);
+                }
+                p.skip_statement();
+            }
+            "message" => parse_message(&mut p, &package, schema)?,
+            "enum" => parse_enum(&mut p, &package, schema)?,
+            "service" => parse_service(&mut p, &package, schema)?,
+            "extend" => {
+                p.word()?;
+                p.skip_block()?;
+            }
+            other => return Err(format!("unexpected {}", other))
+        }
+    }
+    Ok(imports)
+}
+
+
+impl ProtoSchema {
+    /// 按照protobuf的作用域规则找到类型的完整名称，从内层往外层查找
+    fn resolve_type(&self, name: &str, scope: &str) -> String {
+        if SCALAR_TYPES.contains(&name) {
+            return name.to_string();
+        }
+        if name.starts_with('.') {
+            return name.trim_start_matches('.').to_string();
+        }
+        let mut scope = scope.to_string();
+        loop {
+            let candidate = full_name(&scope, name);
+            if self.messages.contains_key(&candidate) || self.enums.contains_key(&candidate) {
+                return candidate;
+            }
+            if scope == "" {
+                return name.to_string();
+            }
+            scope = match scope.rfind('.') {
+                Some(i) => scope[..i].to_string(),
+                None => "".to_string()
+            };
+        }
+    }
+
+    fn resolve_all_types(&mut self) {
+        let mut resolved_fields = Vec::new();
+        for (name, fields) in &self.messages {
+            let fields: Vec<String> = fields.iter().map(|f| self.resolve_type(&f.ty, name)).collect();
+            resolved_fields.push((name.to_string(), fields));
+        }
+        for (name, types) in resolved_fields {
+            if let Some(fields) = self.messages.get_mut(&name) {
+                for (field, ty) in fields.iter_mut().zip(types) {
+                    field.ty = ty;
+                }
+            }
+        }
+
+        let mut resolved_methods = Vec::new();
+        for (path, method) in &self.methods {
+            resolved_methods.push((path.to_string(), self.resolve_type(&method.input, &method.scope), self.resolve_type(&method.output, &method.scope)));
+        }
+        for (path, input, output) in resolved_methods {
+            if let Some(method) = self.methods.get_mut(&path) {
+                method.input = input;
+                method.output = output;
+            }
+        }
+    }
+}
+
+
+/// 加载接口文档中设置的proto文件，以及它们import的文件
+/// import 的文件先按照当前proto文件所在目录查找，然后是当前目录和 _data/
+/// 找不到的import (如 google/protobuf/*.proto) 会跳过，其中的类型按空消息处理
+fn load_schema(proto_files: &[String]) -> Result<ProtoSchema, String> {
+    let mut schema = ProtoSchema::default();
+    let mut loaded = HashSet::new();
+    let mut queue: Vec<String> = proto_files.to_vec();
+    while let Some(proto_file) = queue.pop() {
+        if !loaded.insert(proto_file.clone()) {
+            continue;
+        }
+        let source = match fs::read_to_string(&proto_file) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("read proto file {} error: {}", proto_file, e))
+        };
+        let imports = parse_proto_file(&source, &mut schema).map_err(|e| format!("parse proto file {} error: {}", proto_file, e))?;
+        let dir = Path::new(&proto_file).parent().map(|x| x.to_path_buf()).unwrap_or_default();
+        for import in imports {
+            let candidates = [dir.join(&import), Path::new(&import).to_path_buf(), Path::new("_data").join(&import)];
+            match candidates.iter().find(|x| x.is_file()) {
+                Some(v) => queue.push(v.to_string_lossy().to_string()),
+                None => println!("proto import {} of {} is not found", import, proto_file)
+            }
+        }
+    }
+    schema.resolve_all_types();
+    Ok(schema)
+}
+
+
+/// 加载所有接口文档中的proto文件，在加载、重新加载接口文档后调用
+pub fn load_proto_schema(api_docs: &HashMap<String, db::ApiDoc>) -> Result<Arc<ProtoSchema>, String> {
+    let proto_files: Vec<String> = api_docs.values().flat_map(|x| x.proto.iter().cloned()).collect();
+    match load_schema(&proto_files) {
+        Ok(v) => Ok(Arc::new(v)),
+        Err(e) => {
+            println!("Load proto files error: {}", e);
+            Err(e)
+        }
+    }
+}
+
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Result<u64, String> {
+    let mut result = 0u64;
+    let mut shift = 0;
+    loop {
+        if *pos >= data.len() {
+            return Err("truncated varint".to_string());
+        }
+        let b = data[*pos];
+        *pos += 1;
+        if shift < 64 {
+            result |= ((b & 0x7f) as u64) << shift;
+        }
+        if b & 0x80 == 0 {
+            return Ok(result);
+        }
+        shift += 7;
+        if shift >= 70 {
+            return Err("varint is too long".to_string());
+        }
+    }
+}
+
+
+fn value_i64(v: &Value) -> i64 {
+    match v {
+        Value::Number(n) => n.as_i64().or(n.as_f64().map(|x| x as i64)).unwrap_or(0),
+        Value::String(s) => s.parse().unwrap_or(0),
+        Value::Bool(b) => *b as i64,
+        _ => 0
+    }
+}
+
+
+fn value_u64(v: &Value) -> u64 {
+    match v {
+        Value::Number(n) => n.as_u64().unwrap_or(value_i64(v) as u64),
+        Value::String(s) => s.parse().unwrap_or(0),
+        _ => value_i64(v) as u64
+    }
+}
+
+
+fn value_f64(v: &Value) -> f64 {
+    match v {
+        Value::Number(n) => n.as_f64().unwrap_or(0.0),
+        Value::String(s) => s.parse().unwrap_or(0.0),
+        _ => 0.0
+    }
+}
+
+
+fn scalar_wire_type(ty: &str) -> u8 {
+    match ty {
+        "double" | "fixed64" | "sfixed64" => 1,
+        "float" | "fixed32" | "sfixed32" => 5,
+        "string" | "bytes" => 2,
+        _ => 0
+    }
+}
+
+
+impl ProtoSchema {
+    fn wire_type(&self, ty: &str) -> u8 {
+        if SCALAR_TYPES.contains(&ty) {
+            scalar_wire_type(ty)
+        } else if self.enums.contains_key(ty) {
+            0
+        } else {
+            2
+        }
+    }
+
+    /// 数字类型和枚举的repeated字段使用packed编码
+    fn is_packable(&self, ty: &str) -> bool {
+        self.enums.contains_key(ty) || (SCALAR_TYPES.contains(&ty) && ty != "string" && ty != "bytes")
+    }
+
+    /// 写入一个值，不包括tag
+    fn write_value(&self, ty: &str, v: &Value, out: &mut Vec<u8>) {
+        match ty {
+            "double" => out.extend_from_slice(&value_f64(v).to_le_bytes()),
+            "float" => out.extend_from_slice(&(value_f64(v) as f32).to_le_bytes()),
+            "int32" | "int64" => write_varint(out, value_i64(v) as u64),
+            "uint32" | "uint64" => write_varint(out, value_u64(v)),
+            "sint32" | "sint64" => {
+                let n = value_i64(v);
+                write_varint(out, ((n << 1) ^ (n >> 63)) as u64);
+            }
+            "fixed32" => out.extend_from_slice(&(value_u64(v) as u32).to_le_bytes()),
+            "sfixed32" => out.extend_from_slice(&(value_i64(v) as i32).to_le_bytes()),
+            "fixed64" => out.extend_from_slice(&value_u64(v).to_le_bytes()),
+            "sfixed64" => out.extend_from_slice(&value_i64(v).to_le_bytes()),
+            "bool" => write_varint(out, match v {
+                Value::Bool(b) => *b as u64,
+                _ => (value_i64(v) != 0) as u64
+            }),
+            "string" | "bytes" => {
+                let s = match v {
+                    Value::String(s) => s.to_string(),
+                    _ => v.to_string()
+                };
+                // bytes 在json中是base64字符串
+                let data = if ty == "bytes" { base64::decode(&s).unwrap_or(s.into_bytes()) } else { s.into_bytes() };
+                write_varint(out, data.len() as u64);
+                out.extend(data);
+            }
+            _ => {
+                if let Some(values) = self.enums.get(ty) {
+                    let number = match v.as_str() {
+                        Some(name) => values.iter().find(|x| x.0 == name).map(|x| x.1).unwrap_or(0),
+                        None => value_i64(v)
+                    };
+                    write_varint(out, number as u64);
+                } else {
+                    let mut data = Vec::new();
+                    self.encode_message(ty, v, &mut data);
+                    write_varint(out, data.len() as u64);
+                    out.extend(data);
+                }
+            }
+        }
+    }
+
+    fn write_field(&self, ty: &str, number: u64, v: &Value, out: &mut Vec<u8>) {
+        write_varint(out, (number << 3) | self.wire_type(ty) as u64);
+        self.write_value(ty, v, out);
+    }
+
+    /// 把json编码为protobuf消息，字段名可以是proto中的名称或者驼峰名称
+    fn encode_message(&self, name: &str, value: &Value, out: &mut Vec<u8>) {
+        let (fields, obj) = match (self.messages.get(name), value.as_object()) {
+            (Some(fields), Some(obj)) => (fields, obj),
+            _ => return
+        };
+        for field in fields {
+            let v = match obj.get(&field.name).or_else(|| obj.get(&camel_case(&field.name))) {
+                Some(v) if !v.is_null() => v,
+                _ => continue
+            };
+            if let Some(key_type) = &field.map_key {
+                if let Some(entries) = v.as_object() {
+                    for (k, item) in entries {
+                        let mut entry = Vec::new();
+                        self.write_field(key_type, 1, &json!(k), &mut entry);
+                        self.write_field(&field.ty, 2, item, &mut entry);
+                        write_varint(out, (field.number << 3) | 2);
+                        write_varint(out, entry.len() as u64);
+                        out.extend(entry);
+                    }
+                }
+            } else if field.repeated {
+                let items = match v.as_array() {
+                    Some(v) => v.clone(),
+                    None => vec![v.clone()]
+                };
+                if self.is_packable(&field.ty) {
+                    let mut packed = Vec::new();
+                    for item in &items {
+                        self.write_value(&field.ty, item, &mut packed);
+                    }
+                    write_varint(out, (field.number << 3) | 2);
+                    write_varint(out, packed.len() as u64);
+                    out.extend(packed);
+                } else {
+                    for item in &items {
+                        self.write_field(&field.ty, field.number, item, out);
+                    }
+                }
+            } else {
+                self.write_field(&field.ty, field.number, v, out);
+            }
+        }
+    }
+}
+
+
+fn camel_case(name: &str) -> String {
+    let mut result = String::new();
+    let mut upper = false;
+    for c in name.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            result.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+
+/// 消息中一个字段的原始值
+enum Raw<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Fixed32(u32),
+    Bytes(&'a [u8]),
+}
+
+
+fn read_raw<'a>(data: &'a [u8], pos: &mut usize, wire_type: u8) -> Result<Raw<'a>, String> {
+    match wire_type {
+        0 => Ok(Raw::Varint(read_varint(data, pos)?)),
+        1 => {
+            let b = match data.get(*pos..).and_then(|x| x.get(..8)) {
+                Some(v) => v,
+                None => return Err("truncated fixed64".to_string())
+            };
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(b);
+            *pos += 8;
+            Ok(Raw::Fixed64(u64::from_le_bytes(bytes)))
+        }
+        5 => {
+            let b = match data.get(*pos..).and_then(|x| x.get(..4)) {
+                Some(v) => v,
+                None => return Err("truncated fixed32".to_string())
+            };
+            let mut bytes = [0u8; 4];
+            bytes.copy_from_slice(b);
+            *pos += 4;
+            Ok(Raw::Fixed32(u32::from_le_bytes(bytes)))
+        }
+        2 => {
+            let len = read_varint(data, pos)?;
+            let end = match pos.checked_add(len as usize) {
+                Some(v) if v <= data.len() => v,
+                _ => return Err("truncated length delimited field".to_string())
+            };
+            let b = &data[*pos..end];
+            *pos = end;
+            Ok(Raw::Bytes(b))
+        }
+        _ => Err(format!("unsupported wire type {}", wire_type))
+    }
+}
+
+
+impl ProtoSchema {
+    fn decode_value(&self, ty: &str, raw: &Raw, depth: usize) -> Result<Value, String> {
+        let v = match (ty, raw) {
+            ("double", Raw::Fixed64(v)) => json!(f64::from_bits(*v)),
+            ("float", Raw::Fixed32(v)) => json!(f32::from_bits(*v) as f64),
+            ("int32", Raw::Varint(v)) => json!(*v as i64 as i32),
+            ("int64", Raw::Varint(v)) => json!(*v as i64),
+            ("uint32", Raw::Varint(v)) => json!(*v as u32),
+            ("uint64", Raw::Varint(v)) => json!(*v),
+            ("sint32", Raw::Varint(v)) | ("sint64", Raw::Varint(v)) => json!(((*v >> 1) as i64) ^ -((*v & 1) as i64)),
+            ("fixed32", Raw::Fixed32(v)) => json!(*v),
+            ("sfixed32", Raw::Fixed32(v)) => json!(*v as i32),
+            ("fixed64", Raw::Fixed64(v)) => json!(*v),
+            ("sfixed64", Raw::Fixed64(v)) => json!(*v as i64),
+            ("bool", Raw::Varint(v)) => json!(*v != 0),
+            ("string", Raw::Bytes(b)) => json!(String::from_utf8_lossy(b)),
+            ("bytes", Raw::Bytes(b)) => json!(base64::encode(b)),
+            (_, Raw::Varint(v)) if self.enums.contains_key(ty) => {
+                match self.enums[ty].iter().find(|x| x.1 == *v as i64) {
+                    Some(x) => json!(x.0),
+                    None => json!(*v as i64)
+                }
+            }
+            (_, Raw::Bytes(b)) if !SCALAR_TYPES.contains(&ty) => self.decode_message(ty, b, depth + 1)?,
+            _ => return Err(format!("field type {} does not match the wire type", ty))
+        };
+        Ok(v)
+    }
+
+    fn default_value(&self, field: &ProtoField) -> Value {
+        if field.map_key.is_some() {
+            return json!({});
+        }
+        if field.repeated {
+            return json!([]);
+        }
+        match field.ty.as_str() {
+            "double" | "float" => json!(0.0),
+            "bool" => json!(false),
+            "string" | "bytes" => json!(""),
+            ty if SCALAR_TYPES.contains(&ty) => json!(0),
+            ty => match self.enums.get(ty).and_then(|x| x.first()) {
+                Some(x) => json!(x.0),
+                None => Value::Null
+            }
+        }
+    }
+
+    /// 把protobuf消息解码为json，没有传的标量字段使用默认值，方便和test_data匹配
+    fn decode_message(&self, name: &str, data: &[u8], depth: usize) -> Result<Value, String> {
+        if depth > MAX_DECODE_DEPTH {
+            return Err(format!("message nested more than {} levels", MAX_DECODE_DEPTH));
+        }
+        let fields = match self.messages.get(name) {
+            Some(v) => v,
+            None => return Ok(json!({}))
+        };
+        let mut obj = Map::new();
+        for field in fields {
+            let v = self.default_value(field);
+            if field.oneof.is_none() && !v.is_null() {
+                obj.insert(field.name.clone(), v);
+            }
+        }
+
+        let mut pos = 0;
+        while pos < data.len() {
+            let tag = read_varint(data, &mut pos)?;
+            let raw = read_raw(data, &mut pos, (tag & 7) as u8)?;
+            let field = match fields.iter().find(|x| x.number == tag >> 3) {
+                Some(v) => v,
+                // 未知字段直接跳过
+                None => continue
+            };
+
+            if let Some(key_type) = &field.map_key {
+                let entry = match raw {
+                    Raw::Bytes(b) => b,
+                    _ => return Err(format!("map field {} is not length delimited", field.name))
+                };
+                let mut key = Value::Null;
+                let mut value = Value::Null;
+                let mut entry_pos = 0;
+                while entry_pos < entry.len() {
+                    let entry_tag = read_varint(entry, &mut entry_pos)?;
+                    let entry_raw = read_raw(entry, &mut entry_pos, (entry_tag & 7) as u8)?;
+                    match entry_tag >> 3 {
+                        1 => key = self.decode_value(key_type, &entry_raw, depth)?,
+                        2 => value = self.decode_value(&field.ty, &entry_raw, depth)?,
+                        _ => ()
+                    }
+                }
+                let key = match key {
+                    Value::String(s) => s,
+                    Value::Null => "".to_string(),
+                    v => v.to_string()
+                };
+                if let Some(map) = obj.entry(field.name.clone()).or_insert(json!({})).as_object_mut() {
+                    map.insert(key, value);
+                }
+            } else if field.repeated {
+                let mut items = Vec::new();
+                match raw {
+                    Raw::Bytes(b) if self.is_packable(&field.ty) => {
+                        let mut packed_pos = 0;
+                        while packed_pos < b.len() {
+                            let item = read_raw(b, &mut packed_pos, self.wire_type(&field.ty))?;
+                            items.push(self.decode_value(&field.ty, &item, depth)?);
+                        }
+                    }
+                    raw => items.push(self.decode_value(&field.ty, &raw, depth)?)
+                }
+                if let Some(list) = obj.entry(field.name.clone()).or_insert(json!([])).as_array_mut() {
+                    list.extend(items);
+                }
+            } else {
+                obj.insert(field.name.clone(), self.decode_value(&field.ty, &raw, depth)?);
+            }
+        }
+        Ok(Value::Object(obj))
+    }
+}
+
+
+/// 使用和response字段一样的mock类型生成标量的值
+fn mock_scalar(ty: &str) -> Value {
+    let mock_type = match ty {
+        "int32" | "int64" | "uint32" | "uint64" | "fixed32" | "fixed64" => "posint",
+        "sint32" | "sint64" | "sfixed32" | "sfixed64" => "int",
+        "double" | "float" => "posfloat",
+        "bool" => "bool",
+        _ => "string"
+    };
+    let v = match api::try_create_mock_response(&json!({"v": {"type": mock_type}}), &db::CustomTypes::new()) {
+        Ok(mut v) => v.remove("v").unwrap_or(Value::Null),
+        Err(_) => Value::Null
+    };
+    match (ty, v.as_str()) {
+        ("bytes", Some(s)) => json!(base64::encode(s)),
+        _ => v
+    }
+}
+
+
+impl ProtoSchema {
+    fn mock_value(&self, ty: &str, depth: usize) -> Value {
+        if let Some(values) = self.enums.get(ty) {
+            if values.is_empty() {
+                return json!(0);
+            }
+            return json!(values[thread_rng().gen_range(0, values.len())].0);
+        }
+        if SCALAR_TYPES.contains(&ty) {
+            return mock_scalar(ty);
+        }
+        if depth >= MAX_MOCK_DEPTH {
+            return Value::Null;
+        }
+        self.mock_message(ty, depth + 1)
+    }
+
+    /// 按照字段类型生成一个消息，oneof只生成第一个字段，repeated和map生成1到3个
+    fn mock_message(&self, name: &str, depth: usize) -> Value {
+        let fields = match self.messages.get(name) {
+            Some(v) => v,
+            None => return json!({})
+        };
+        let mut obj = Map::new();
+        let mut oneofs = HashSet::new();
+        for field in fields {
+            if let Some(oneof) = &field.oneof {
+                if !oneofs.insert(oneof.to_string()) {
+                    continue;
+                }
+            }
+            let count = thread_rng().gen_range(1, 4);
+            let v = if let Some(key_type) = &field.map_key {
+                let mut map = Map::new();
+                for _ in 0..count {
+                    let key = match self.mock_value(key_type, depth) {
+                        Value::String(s) => s,
+                        v => v.to_string()
+                    };
+                    map.insert(key, self.mock_value(&field.ty, depth));
+                }
+                Value::Object(map)
+            } else if field.repeated {
+                let items: Vec<Value> = (0..count).map(|_| self.mock_value(&field.ty, depth)).filter(|x| !x.is_null()).collect();
+                Value::Array(items)
+            } else {
+                self.mock_value(&field.ty, depth)
+            };
+            if !v.is_null() {
+                obj.insert(field.name.clone(), v);
+            }
+        }
+        Value::Object(obj)
+    }
+}
+
+
+/// 一次调用的结果
+pub struct GrpcReply {
+    // (发送前等待的毫秒数, 编码后的消息)
+    messages: Vec<(u64, Vec<u8>)>,
+    status: u64,
+    message: String,
+}
+
+
+fn grpc_error(status: u64, message: &str) -> GrpcReply {
+    GrpcReply { messages: Vec::new(), status, message: message.to_string() }
+}
+
+
+/// 拆分请求中带长度前缀的消息
+fn parse_frames(body: &[u8]) -> Result<Vec<Vec<u8>>, GrpcReply> {
+    let mut frames = Vec::new();
+    let mut pos = 0;
+    while pos + 5 <= body.len() {
+        if body[pos] & 1 == 1 {
+            return Err(grpc_error(12, "compressed messages are not supported"));
+        }
+        let len = u32::from_be_bytes([body[pos + 1], body[pos + 2], body[pos + 3], body[pos + 4]]) as usize;
+        pos += 5;
+        let end = match pos.checked_add(len) {
+            Some(v) if v <= body.len() => v,
+            _ => return Err(grpc_error(3, "truncated message"))
+        };
+        frames.push(body[pos..end].to_vec());
+        pos = end;
+    }
+    Ok(frames)
+}
+
+
+fn frame(message: &[u8]) -> Vec<u8> {
+    let mut result = vec![0u8];
+    result.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    result.extend_from_slice(message);
+    result
+}
+
+
+/// grpc-message 中的非可见字符需要百分号编码
+fn encode_grpc_message(message: &str) -> String {
+    let mut result = String::new();
+    for b in message.bytes() {
+        if b < 0x20 || b > 0x7e || b == b'%' {
+            result.push_str(&format!("%{:02X}", b));
+        } else {
+            result.push(b as char);
+        }
+    }
+    result
+}
+
+
+/// 处理一次调用: 解码请求消息，按照test_data中的body匹配，返回response/responses，
+/// 没有匹配的按照proto中的字段类型生成，接口的response设置可以覆盖生成的字段
+fn handle_call(path: &str, body: &[u8], db: &web::Data<Mutex<db::Database>>) -> GrpcReply {
+    // 解码、编码消息的时候不占用数据库的锁
+    let (schema, a_api_data) = {
+        let db_data = db.lock().unwrap();
+        let schema = match &db_data.proto_schema {
+            Ok(v) => v.clone(),
+            Err(e) => return grpc_error(13, e)
+        };
+        let a_api_data = db_data.api_data.get(path)
+            .and_then(|x| x.get("GRPC").or(x.get("*")))
+            .map(|x| x.lock().unwrap().clone());
+        (schema, a_api_data)
+    };
+    let method = match schema.methods.get(path) {
+        Some(v) => v,
+        None => return grpc_error(12, &format!("method {} is not defined in proto files", path))
+    };
+
+    let frames = match parse_frames(body) {
+        Ok(v) => v,
+        Err(reply) => return reply
+    };
+    let mut requests = Vec::new();
+    for data in &frames {
+        match schema.decode_message(&method.input, data, 0) {
+            Ok(v) => requests.push(v),
+            Err(e) => return grpc_error(3, &format!("decode request message error: {}", e))
+        }
+    }
+    // 客户端流的请求是消息列表
+    let request = if method.client_streaming {
+        Value::Array(requests)
+    } else {
+        match requests.into_iter().next() {
+            Some(v) => v,
+            None => schema.decode_message(&method.input, &[], 0).unwrap_or(json!({}))
+        }
+    };
+
+    let mut responses = None;
+    let mut interval = 0;
+    if let Some(a_api_data) = &a_api_data {
+        interval = a_api_data.grpc.get("interval").and_then(|x| x.as_u64()).unwrap_or(0);
+        if let Some(test_data) = a_api_data.test_data.as_array() {
+            for test_case_data in test_data {
+                if let Some(pattern) = test_case_data.get("body") {
+                    if !api::is_value_contains(&request, pattern) {
+                        continue;
+                    }
+                }
+                let status = test_case_data.get("status").and_then(|x| x.as_u64()).unwrap_or(0);
+                if status != 0 {
+                    let message = test_case_data.get("error").and_then(|x| x.as_str()).unwrap_or("");
+                    return grpc_error(status, message);
+                }
+                interval = test_case_data.get("interval").and_then(|x| x.as_u64()).unwrap_or(interval);
+                responses = match test_case_data.get("responses").and_then(|x| x.as_array()) {
+                    Some(v) => Some(v.clone()),
+                    None => Some(test_case_data.get("response").into_iter().cloned().collect())
+                };
+                break;
+            }
+        }
+    }
+
+    let mut responses = match responses {
+        Some(v) => v,
+        None => {
+            let count = match (&a_api_data, method.server_streaming) {
+                (Some(a_api_data), true) => a_api_data.grpc.get("count").and_then(|x| x.as_u64()).unwrap_or(3),
+                (None, true) => 3,
+                _ => 1
+            };
+            (0..count).map(|_| {
+                let mut v = schema.mock_message(&method.output, 0);
+                if let Some(a_api_data) = &a_api_data {
+                    if a_api_data.response.is_object() {
+                        if let (Some(obj), Ok(fields)) = (v.as_object_mut(), api::try_create_mock_response(&a_api_data.response, &db.lock().unwrap().custom_types)) {
+                            obj.extend(fields);
+                        }
+                    }
+                }
+                v
+            }).collect()
+        }
+    };
+    // 一元调用只返回一个消息
+    if !method.server_streaming {
+        responses.truncate(1);
+        if responses.is_empty() {
+            responses.push(json!({}));
+        }
+    }
+
+    let messages = responses.iter().enumerate().map(|(i, v)| {
+        let mut data = Vec::new();
+        schema.encode_message(&method.output, v, &mut data);
+        (if i == 0 { 0 } else { interval }, data)
+    }).collect();
+    GrpcReply { messages, status: 0, message: "".to_string() }
+}
+
+
+/// 在单独的端口上提供 HTTP/2 的gRPC服务
+pub async fn serve(addr: String, db: web::Data<Mutex<db::Database>>) {
+    // 启动时解析一次地址，host 可以是 localhost 这样的名称
+    let socket_addr = match addr.to_socket_addrs().ok().and_then(|mut x| x.next()) {
+        Some(v) => v,
+        None => {
+            println!("grpc service address {} is invalid", addr);
+            return;
+        }
+    };
+    let mut listener = match TcpListener::bind(&socket_addr).await {
+        Ok(v) => v,
+        Err(e) => {
+            println!("grpc service bind {} error: {}", addr, e);
+            return;
+        }
+    };
+    loop {
+        let socket = match listener.accept().await {
+            Ok((socket, _)) => socket,
+            Err(e) => {
+                println!("grpc accept error: {}", e);
+                continue;
+            }
+        };
+        let db = db.clone();
+        actix_rt::spawn(async move {
+            if let Err(e) = serve_connection(socket, db).await {
+                println!("grpc connection error: {}", e);
+            }
+        });
+    }
+}
+
+
+async fn serve_connection(socket: TcpStream, db: web::Data<Mutex<db::Database>>) -> Result<(), h2::Error> {
+    let mut connection = h2::server::handshake(socket).await?;
+    while let Some(result) = connection.accept().await {
+        let (request, respond) = result?;
+        let db = db.clone();
+        actix_rt::spawn(async move {
+            if let Err(e) = handle_h2_request(request, respond, db).await {
+                println!("grpc stream error: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+
+async fn handle_h2_request(request: http::Request<h2::RecvStream>, mut respond: h2::server::SendResponse<Bytes>, db: web::Data<Mutex<db::Database>>) -> Result<(), h2::Error> {
+    let path = request.uri().path().to_string();
+    let max_body_size = api::get_max_body_size(&db.lock().unwrap().settings);
+    let mut body = request.into_body();
+    let mut data = Vec::new();
+    let mut is_too_large = false;
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        let _ = body.flow_control().release_capacity(chunk.len());
+        // 和http接口一样限制请求body的大小，超过后丢弃剩余的数据，读完后再返回错误
+        if is_too_large || data.len() + chunk.len() > max_body_size {
+            is_too_large = true;
+            continue;
+        }
+        data.extend_from_slice(&chunk);
+    }
+
+    let reply = if is_too_large {
+        grpc_error(8, &format!("request body is larger than {} bytes", max_body_size))
+    } else {
+        handle_call(&path, &data, &db)
+    };
+    let response = http::Response::builder()
+        .status(200)
+        .header("content-type", "application/grpc")
+        .body(())
+        .unwrap();
+    let mut stream = respond.send_response(response, false)?;
+    for (delay, message) in reply.messages {
+        if delay > 0 {
+            actix_rt::time::delay_for(Duration::from_millis(delay)).await;
+        }
+        stream.send_data(Bytes::from(frame(&message)), false)?;
+    }
+
+    let mut trailers = http::HeaderMap::new();
+    trailers.insert("grpc-status", http::HeaderValue::from(reply.status));
+    if reply.message != "" {
+        if let Ok(v) = http::HeaderValue::from_str(&encode_grpc_message(&reply.message)) {
+            trailers.insert("grpc-message", v);
+        }
+    }
+    stream.send_trailers(trailers)
+}
+
+
+/// 判断是否是gRPC-Web请求
+pub fn is_grpc_web_request(head: &actix_web::dev::RequestHead) -> bool {
+    match head.headers().get("content-type").and_then(|x| x.to_str().ok()) {
+        Some(v) => v.starts_with("application/grpc-web"),
+        None => false
+    }
+}
+
+
+/// gRPC-Web 请求，trailer 作为最后一个消息放在body中，流式返回的消息一次返回
+pub async fn grpc_web_handle(req: HttpRequest, body: web::Bytes, db: web::Data<Mutex<db::Database>>) -> HttpResponse {
+    let content_type = req.headers().get("content-type").and_then(|x| x.to_str().ok()).unwrap_or("");
+    let is_text = content_type.starts_with("application/grpc-web-text");
+
+    let reply = if is_text {
+        match base64::decode(&body[..]) {
+            Ok(v) => handle_call(req.path(), &v, &db),
+            Err(e) => grpc_error(3, &format!("decode grpc-web-text body error: {}", e))
+        }
+    } else {
+        handle_call(req.path(), &body, &db)
+    };
+
+    let mut payload = Vec::new();
+    for (_, message) in &reply.messages {
+        payload.extend(frame(message));
+    }
+    let trailers = format!("grpc-status:{}\r\ngrpc-message:{}\r\n", reply.status, encode_grpc_message(&reply.message));
+    payload.push(0x80);
+    payload.extend_from_slice(&(trailers.len() as u32).to_be_bytes());
+    payload.extend_from_slice(trailers.as_bytes());
+
+    let (content_type, payload) = if is_text {
+        ("application/grpc-web-text+proto", base64::encode(&payload).into_bytes())
+    } else {
+        ("application/grpc-web+proto", payload)
+    };
+    HttpResponse::Ok()
+        .content_type(content_type)
+        .header("Access-Control-Expose-Headers", "grpc-status, grpc-message")
+        .body(payload)
+}
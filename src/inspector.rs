@@ -0,0 +1,466 @@
+//! 请求记录，保存最近的mock请求，用于查看前端发送了什么、匹配了哪个接口和测试数据
+//! GET /__api_docs/requests/ 按条件查询，/__api_docs/requests/ws/ 实时推送新的请求
+//! _settings.json5 中 inspector: {max_requests: 200} 设置最多保存的条数
+//! 前端测试中可以用 /__api_docs/requests/find/、count/、wait/ 验证调用了哪些接口，DELETE /__api_docs/requests/ 在测试之间清空记录
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use actix::*;
+use actix_web::dev::{Body, ResponseBody};
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use actix_web_actors::ws;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::api;
+use crate::db;
+use crate::server;
+
+
+const DEFAULT_MAX_REQUESTS: usize = 200;
+/// 超过这个长度的返回不保存内容
+const MAX_RESPONSE_SIZE: usize = 64 * 1024;
+
+/// wait 默认最多等待的毫秒数
+const DEFAULT_WAIT_TIMEOUT: u64 = 5000;
+const WAIT_INTERVAL: Duration = Duration::from_millis(50);
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 记录中替换认证信息的值
+const REDACTED: &str = "***";
+/// 名称中包含这些内容的header、参数、字段是认证信息，不保存原始的值
+const SECRET_KEYS: [&str; 8] = ["authorization", "cookie", "password", "token", "secret", "csrf", "api-key", "api_key"];
+
+
+/// 一次请求的记录
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct RequestRecord {
+    pub id: u64,
+    // 请求的毫秒时间戳
+    pub time: u64,
+    pub method: String,
+    pub path: String,
+    pub query_string: String,
+    pub headers: Map<String, Value>,
+    pub query: Value,
+    pub body: Value,
+    pub form_data: Value,
+    pub files: Value,
+    // 请求token对应的用户，不包含密码
+    pub user: Value,
+    pub group: String,
+    // 匹配到的接口地址和名称，没有匹配到为空
+    pub api: String,
+    pub api_name: String,
+    // 匹配到的test_data序号，没有匹配到的为mock数据
+    pub test_case: Option<usize>,
+    pub status: u16,
+    pub response: Value,
+    // 处理用的毫秒数
+    pub duration: f64,
+}
+
+
+impl RequestRecord {
+    pub fn new(req: &HttpRequest) -> Self {
+        let mut headers = Map::new();
+        for (k, v) in req.headers() {
+            headers.insert(k.as_str().to_string(), Value::String(v.to_str().unwrap_or("").to_string()));
+        }
+        RequestRecord {
+            time: SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_millis() as u64).unwrap_or(0),
+            method: req.method().as_str().to_string(),
+            path: req.path().to_string(),
+            query_string: req.query_string().to_string(),
+            headers,
+            ..Default::default()
+        }
+    }
+
+    /// 把header、query、body、form和返回内容中的token、密码等认证信息替换为 ***
+    /// 除了默认的名称，还有 _auth.json5 中的 auth_key 和登录的密码字段
+    pub fn redact(&mut self, auth_doc: &Option<db::AuthDoc>) {
+        let mut keys: Vec<String> = SECRET_KEYS.iter().map(|x| x.to_string()).collect();
+        if let Some(auth_doc) = auth_doc {
+            keys.push(auth_doc.auth_key.to_lowercase());
+            if let Some(login) = &auth_doc.login {
+                keys.push(login.password_field.to_lowercase());
+            }
+        }
+        keys.retain(|x| x != "");
+        let is_secret = |key: &str| -> bool {
+            let key = key.to_lowercase();
+            keys.iter().any(|x| key.contains(x.as_str()))
+        };
+
+        for (k, v) in self.headers.iter_mut() {
+            if is_secret(k) {
+                *v = json!(REDACTED);
+            }
+        }
+        redact_value(&mut self.query, &is_secret);
+        redact_value(&mut self.body, &is_secret);
+        redact_value(&mut self.form_data, &is_secret);
+        redact_value(&mut self.response, &is_secret);
+        self.query_string = self.query_string.split('&').map(|x| {
+            let mut parts = x.splitn(2, '=');
+            let k = parts.next().unwrap_or("");
+            match parts.next() {
+                Some(_) if is_secret(k) => format!("{}={}", k, REDACTED),
+                _ => x.to_string()
+            }
+        }).collect::<Vec<String>>().join("&");
+    }
+
+    /// 记录返回的状态和内容，流式返回(SSE)的内容不记录
+    pub fn finish(&mut self, response: &HttpResponse, start: Instant) {
+        self.status = response.status().as_u16();
+        self.duration = start.elapsed().as_secs_f64() * 1000.0;
+        let bytes = match response.body() {
+            ResponseBody::Body(Body::Bytes(v)) | ResponseBody::Other(Body::Bytes(v)) => v,
+            ResponseBody::Body(Body::Message(_)) | ResponseBody::Other(Body::Message(_)) => {
+                self.response = Value::String("<stream>".to_string());
+                return;
+            }
+            _ => return
+        };
+        if bytes.len() > MAX_RESPONSE_SIZE {
+            self.response = Value::String(format!("<{} bytes>", bytes.len()));
+            return;
+        }
+        self.response = match serde_json::from_slice(bytes) {
+            Ok(v) => v,
+            Err(_) => match std::str::from_utf8(bytes) {
+                Ok(v) => Value::String(v.to_string()),
+                Err(_) => Value::String(format!("<{} bytes>", bytes.len()))
+            }
+        };
+    }
+}
+
+
+fn redact_value(value: &mut Value, is_secret: &dyn Fn(&str) -> bool) {
+    match value {
+        Value::Object(obj) => {
+            for (k, v) in obj.iter_mut() {
+                if is_secret(k) {
+                    *v = json!(REDACTED);
+                } else {
+                    redact_value(v, is_secret);
+                }
+            }
+        }
+        Value::Array(items) => {
+            for v in items.iter_mut() {
+                redact_value(v, is_secret);
+            }
+        }
+        _ => ()
+    }
+}
+
+
+/// 查询条件，都是可选的
+#[derive(Deserialize, Debug, Default)]
+pub struct RequestFilter {
+    // 请求方法，不区分大小写
+    pub method: Option<String>,
+    // 请求地址包含的内容
+    pub path: Option<String>,
+    // 匹配到的接口地址或者接口名称
+    pub api: Option<String>,
+    // true 只要匹配到test_data的请求，false 只要返回mock数据的请求
+    pub matched: Option<bool>,
+    pub status: Option<u16>,
+    // 只要id大于since的请求，用于轮询
+    pub since: Option<u64>,
+    pub limit: Option<usize>,
+}
+
+
+impl RequestFilter {
+    pub fn is_match(&self, record: &RequestRecord) -> bool {
+        if let Some(method) = &self.method {
+            if !method.eq_ignore_ascii_case(&record.method) {
+                return false;
+            }
+        }
+        if let Some(path) = &self.path {
+            if !record.path.contains(path.as_str()) {
+                return false;
+            }
+        }
+        if let Some(api) = &self.api {
+            if api != &record.api && api != &record.api_name {
+                return false;
+            }
+        }
+        if let Some(matched) = self.matched {
+            if matched != record.test_case.is_some() {
+                return false;
+            }
+        }
+        if let Some(status) = self.status {
+            if status != record.status {
+                return false;
+            }
+        }
+        if let Some(since) = self.since {
+            if record.id <= since {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+
+/// 验证请求的条件，在RequestFilter的条件上匹配请求的内容
+/// body、query、form-data、files、headers、user 只比较设置了的字段
+#[derive(Deserialize, Debug, Default)]
+pub struct RequestPattern {
+    #[serde(flatten)]
+    pub filter: RequestFilter,
+    pub body: Option<Value>,
+    pub query: Option<Value>,
+    #[serde(rename = "form-data", alias = "form_data")]
+    pub form_data: Option<Value>,
+    pub files: Option<Value>,
+    pub headers: Option<Value>,
+    pub user: Option<Value>,
+    // wait 等待的请求数，默认为1
+    pub count: Option<usize>,
+    // wait 最多等待的毫秒数
+    pub timeout: Option<u64>,
+}
+
+
+impl RequestPattern {
+    pub fn is_match(&self, record: &RequestRecord) -> bool {
+        if !self.filter.is_match(record) {
+            return false;
+        }
+        let contents = [
+            (&self.body, &record.body),
+            (&self.query, &record.query),
+            (&self.form_data, &record.form_data),
+            (&self.files, &record.files),
+            (&self.user, &record.user),
+        ];
+        for (pattern, value) in contents.iter() {
+            if let Some(pattern) = pattern {
+                if !api::is_value_contains(value, pattern) {
+                    return false;
+                }
+            }
+        }
+        // header名称不区分大小写
+        if let Some(headers) = self.headers.as_ref().and_then(|x| x.as_object()) {
+            for (k, v) in headers {
+                match record.headers.get(&k.to_lowercase()) {
+                    Some(header) if header == v => (),
+                    _ => return false
+                }
+            }
+        }
+        true
+    }
+}
+
+
+/// 最近请求的环形缓冲，超过max_requests时丢弃最早的记录
+pub struct RequestLog {
+    records: VecDeque<RequestRecord>,
+    max_requests: usize,
+    next_id: u64,
+    // 实时推送的websocket连接
+    subscribers: Vec<Recipient<server::Message>>,
+}
+
+
+impl RequestLog {
+    pub fn new(settings: &Option<Value>) -> Self {
+        let max_requests = settings.as_ref()
+            .and_then(|x| x.pointer("/inspector/max_requests"))
+            .and_then(|x| x.as_u64())
+            .map(|x| x as usize)
+            .unwrap_or(DEFAULT_MAX_REQUESTS);
+        RequestLog {
+            records: VecDeque::new(),
+            max_requests,
+            next_id: 1,
+            subscribers: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, mut record: RequestRecord) {
+        record.id = self.next_id;
+        self.next_id += 1;
+
+        let message = json!({"type": "request", "data": &record}).to_string();
+        self.subscribers.retain(|x| x.do_send(server::Message(message.clone())).is_ok());
+
+        if self.max_requests == 0 {
+            return;
+        }
+        while self.records.len() >= self.max_requests {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+
+    /// 清空记录，id继续增加，since 仍然可以用
+    pub fn clear(&mut self) -> usize {
+        let count = self.records.len();
+        self.records.clear();
+        count
+    }
+
+    /// 匹配的所有请求，最新的在前面
+    pub fn find(&self, pattern: &RequestPattern) -> Vec<&RequestRecord> {
+        self.records.iter().rev().filter(|x| pattern.is_match(x)).collect()
+    }
+
+    /// 按条件查询，最新的在前面
+    pub fn query(&self, filter: &RequestFilter) -> Vec<&RequestRecord> {
+        let records = self.records.iter().rev().filter(|x| filter.is_match(x));
+        match filter.limit {
+            Some(limit) => records.take(limit).collect(),
+            None => records.collect()
+        }
+    }
+}
+
+
+/// 查询最近的请求
+/// GET /__api_docs/requests/?method=POST&path=/login/&api=/login/&matched=true&since=10&limit=20
+pub async fn get_requests(req_get: web::Query<RequestFilter>, log: web::Data<Mutex<RequestLog>>) -> HttpResponse {
+    let log = log.lock().unwrap();
+    let records = log.query(&req_get);
+    HttpResponse::Ok().json(json!({"code": 1, "msg": "", "data": records}))
+}
+
+
+/// 清空请求记录
+/// DELETE /__api_docs/requests/
+pub async fn clear_requests(log: web::Data<Mutex<RequestLog>>) -> HttpResponse {
+    let count = log.lock().unwrap().clear();
+    HttpResponse::Ok().json(json!({"code": 1, "msg": "", "count": count}))
+}
+
+
+/// 按接口和方法统计请求数，没有匹配到接口的按请求地址统计
+/// GET /__api_docs/requests/count/?method=POST
+pub async fn count_requests(req_get: web::Query<RequestFilter>, log: web::Data<Mutex<RequestLog>>) -> HttpResponse {
+    let log = log.lock().unwrap();
+    let mut counts: Vec<Value> = Vec::new();
+    let records = log.query(&RequestFilter { limit: None, ..req_get.into_inner() });
+    for record in &records {
+        let url = if record.api == "" { &record.path } else { &record.api };
+        match counts.iter_mut().find(|x| x["url"] == url.as_str() && x["method"] == record.method.as_str()) {
+            Some(v) => {
+                let count = v["count"].as_u64().unwrap_or(0);
+                v["count"] = json!(count + 1);
+            }
+            None => counts.push(json!({"url": url, "name": record.api_name, "method": record.method, "count": 1}))
+        }
+    }
+    HttpResponse::Ok().json(json!({"code": 1, "msg": "", "count": records.len(), "data": counts}))
+}
+
+
+fn matched_requests_response(code: i64, msg: &str, records: &[&RequestRecord], limit: Option<usize>) -> HttpResponse {
+    let data: Vec<&&RequestRecord> = records.iter().take(limit.unwrap_or(records.len())).collect();
+    HttpResponse::Ok().json(json!({"code": code, "msg": msg, "count": records.len(), "data": data}))
+}
+
+
+/// 查询匹配的请求，返回总数和请求内容
+/// POST /__api_docs/requests/find/ {api: "/login/", method: "POST", body: {username: "edison"}}
+pub async fn find_requests(body: web::Json<RequestPattern>, log: web::Data<Mutex<RequestLog>>) -> HttpResponse {
+    let log = log.lock().unwrap();
+    let records = log.find(&body);
+    matched_requests_response(1, "", &records, body.filter.limit)
+}
+
+
+/// 等待匹配的请求数达到count，超时返回 code: -1 和当前匹配的请求
+/// POST /__api_docs/requests/wait/ {api: "/orders/", method: "POST", count: 1, timeout: 5000}
+pub async fn wait_requests(body: web::Json<RequestPattern>, log: web::Data<Mutex<RequestLog>>) -> HttpResponse {
+    let pattern = body.into_inner();
+    let count = pattern.count.unwrap_or(1);
+    let timeout = Duration::from_millis(pattern.timeout.unwrap_or(DEFAULT_WAIT_TIMEOUT));
+    let start = Instant::now();
+    loop {
+        {
+            let log = log.lock().unwrap();
+            let records = log.find(&pattern);
+            if records.len() >= count {
+                return matched_requests_response(1, "", &records, pattern.filter.limit);
+            }
+            if start.elapsed() >= timeout {
+                let msg = format!("wait {} requests timeout after {}ms, {} matched", count, timeout.as_millis(), records.len());
+                return matched_requests_response(-1, &msg, &records, pattern.filter.limit);
+            }
+        }
+        actix_rt::time::delay_for(WAIT_INTERVAL).await;
+    }
+}
+
+
+/// 文档页面实时查看请求的websocket连接，每个新请求推送 {type: "request", data: {...}}
+pub async fn requests_feed(req: HttpRequest, stream: web::Payload, log: web::Data<Mutex<RequestLog>>) -> Result<HttpResponse, Error> {
+    ws::start(InspectorSession { hb: Instant::now(), log: log.clone() }, &req, stream)
+}
+
+
+pub struct InspectorSession {
+    hb: Instant,
+    log: web::Data<Mutex<RequestLog>>,
+}
+
+
+impl Actor for InspectorSession {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
+            if Instant::now().duration_since(act.hb) > CLIENT_TIMEOUT {
+                ctx.stop();
+                return;
+            }
+            ctx.ping(b"");
+        });
+        self.log.lock().unwrap().subscribers.push(ctx.address().recipient());
+    }
+}
+
+
+impl Handler<server::Message> for InspectorSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: server::Message, ctx: &mut Self::Context) {
+        ctx.text(msg.0);
+    }
+}
+
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for InspectorSession {
+    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        match msg {
+            Ok(ws::Message::Ping(msg)) => {
+                self.hb = Instant::now();
+                ctx.pong(&msg);
+            }
+            Ok(ws::Message::Pong(_)) => {
+                self.hb = Instant::now();
+            }
+            Ok(ws::Message::Text(_)) | Ok(ws::Message::Binary(_)) | Ok(ws::Message::Nop) => (),
+            _ => ctx.stop()
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use actix_web::{middleware, web, App, HttpServer, http};
+use actix_web::{guard, middleware, web, App, HttpServer, http};
 use actix_files::Files;
 
 use dotenv::dotenv;
@@ -6,9 +6,19 @@ use std::sync::Mutex;
 
 mod db;
 mod api;
+mod auth;
+mod body;
+mod graphql;
+mod grpc;
+mod inspector;
+mod oauth;
+mod rate_limit;
+mod runner;
 mod utils;
 mod websocket;
 mod server;
+mod store;
+mod upload;
 
 mod mock;
 
@@ -30,6 +40,18 @@ pub struct Config {
     /// create auth token length
     #[structopt(short, long, env = "PANDA_API_PORT")]
     token_length: Option<usize>,
+
+    /// gRPC HTTP/2 port, used when api docs have proto files
+    #[structopt(long, default_value = "50051", env = "PANDA_API_GRPC_PORT")]
+    grpc_port: usize,
+
+    /// Run websocket and SSE test_data against a backend, ex: --test-realtime http://127.0.0.1:8000
+    #[structopt(long)]
+    test_realtime: Option<String>,
+
+    /// Token sent by the realtime tests to apis need auth
+    #[structopt(long)]
+    test_token: Option<String>,
 }
 
 
@@ -48,6 +70,13 @@ async fn main() -> std::io::Result<()> {
         return Ok(());
     }
 
+    if let Some(target) = &conf.test_realtime {
+        if !runner::run_realtime_tests(target, conf.test_token.clone()).await {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     match dirs::home_dir() {
         Some(path) => {
             let current_dir = std::env::current_dir().expect("Failed to determine current directory");
@@ -61,21 +90,40 @@ async fn main() -> std::io::Result<()> {
 
     let db = db::Database::load();
 
-    let websocket_api = &db.websocket_api.clone();
-    let w = websocket_api.lock().unwrap();
-
-    let websocket_uri = w.url.clone();
     let web_db = web::Data::new(Mutex::new(db));
 
     utils::watch_api_docs_change(web_db.clone());
 
+    let rate_limit = rate_limit::RateLimit::new(web_db.clone());
+
+    let request_log = web::Data::new(Mutex::new(inspector::RequestLog::new(&web_db.lock().unwrap().settings)));
+
+    // 只在启动时有proto文件的时候提供HTTP/2服务，之后添加的proto文件只能用gRPC-Web访问，重启后才能用HTTP/2访问
+    let has_proto = web_db.lock().unwrap().api_docs.values().any(|x| !x.proto.is_empty());
+    if has_proto {
+        let grpc_addr = format!("{}:{}", conf.host, conf.grpc_port);
+        println!("Starting grpc service on http://{}", grpc_addr);
+        actix_rt::spawn(grpc::serve(grpc_addr, web_db.clone()));
+    }
+
+    // 上传目录不存在的时候 Files 会使用当前目录，创建失败的时候不提供上传文件的访问
+    let has_upload_dir = match std::fs::create_dir_all(upload::UPLOAD_DIR) {
+        Ok(_) => true,
+        Err(e) => {
+            println!("create upload dir {} error: {}", upload::UPLOAD_DIR, e);
+            false
+        }
+    };
+
     let server = server::ChatServer::default();
     let server = server.start();
     println!("Starting service on http://{}:{}", conf.host, conf.port);
     HttpServer::new(move || {
-        App::new()
+        let mut app = App::new()
             .data(server.clone())
             .app_data(web_db.clone())
+            .app_data(request_log.clone())
+            .wrap(rate_limit.clone())
             .wrap(middleware::Logger::default())
             .wrap(middleware::Logger::new("%a %{User-Agent}i"))
             .wrap(middleware::DefaultHeaders::new()
@@ -86,12 +134,30 @@ async fn main() -> std::io::Result<()> {
 
             .service(web::resource("/__api_docs/").route(web::get().to(api::get_api_doc_basic)))
             .service(web::resource("/__api_docs/api_data/").route(web::get().to(api::get_api_doc_data)))
+            .service(web::resource("/__api_docs/auth/explain/").route(web::get().to(api::explain_perm)))
+            .service(web::resource("/__api_docs/websocket/send/").route(web::post().to(api::websocket_send)))
+            .service(web::resource("/__api_docs/requests/")
+                .route(web::get().to(inspector::get_requests))
+                .route(web::delete().to(inspector::clear_requests)))
+            .service(web::resource("/__api_docs/requests/count/").route(web::get().to(inspector::count_requests)))
+            .service(web::resource("/__api_docs/requests/find/").route(web::post().to(inspector::find_requests)))
+            .service(web::resource("/__api_docs/requests/wait/").route(web::post().to(inspector::wait_requests)))
+            .service(web::resource("/__api_docs/requests/ws/").route(web::get().to(inspector::requests_feed)))
             .service(web::resource("/__api_docs/_data/").route(web::get().to(api::get_api_doc_schema_data)))
             .service(web::resource("/").route(web::get().to(api::theme_view)))
-            .service(web::resource("/static/*").route(web::get().to(api::theme_view)))
-            .service(Files::new("/_upload", "_data/_upload"))
-
-            .service(web::resource(&websocket_uri).to(api::chat_route))
+            .service(web::resource("/static/*").route(web::get().to(api::theme_view)));
+        if has_upload_dir {
+            app = app.service(Files::new("/_upload", upload::UPLOAD_DIR));
+        }
+        app
+            .service(web::resource("/.well-known/openid-configuration").route(web::get().to(oauth::discovery)))
+            .service(web::resource("/__oauth/authorize").route(web::get().to(oauth::authorize)))
+            .service(web::resource("/__oauth/token").route(web::post().to(oauth::token)))
+            .service(web::resource("/__oauth/userinfo").route(web::get().to(oauth::userinfo)))
+            .service(web::resource("/__oauth/jwks").route(web::get().to(oauth::jwks)))
+
+            .service(web::resource("/*").guard(guard::fn_guard(grpc::is_grpc_web_request)).to(grpc::grpc_web_handle))
+            .service(web::resource("/*").guard(guard::fn_guard(api::is_websocket_request)).to(api::chat_route))
             .service(web::resource("/*").to(api::action_handle))
     })
         .bind(format!("{}:{}", conf.host, conf.port))?
@@ -1,14 +1,32 @@
-use rand::{thread_rng, Rng};
+use rand::Rng;
 use std::time::{Duration, SystemTime};
 use chrono::{TimeZone, Utc};
 use regex::Regex;
-use uuid::Uuid;
+use std::collections::HashMap;
+use std::sync::Mutex;
 use regex_generate::{DEFAULT_MAX_REPEAT, Generator};
+use lazy_static::lazy_static;
 
 const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789)(*&^%$#@!~";
 const CHARSET2: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
 
 
+lazy_static! {
+    /// 跨请求的自增字段计数器 {key: 下一个值}
+    static ref SEQUENCES: Mutex<HashMap<String, i64>> = Mutex::new(HashMap::new());
+}
+
+
+/// 获取自增字段的下一个值，同一个key在多次请求之间持续自增
+pub fn sequence_next(key: &str, start: i64, step: i64) -> i64 {
+    let mut sequences = SEQUENCES.lock().unwrap();
+    let v = sequences.entry(key.to_string()).or_insert(start);
+    let current = *v;
+    *v += step;
+    current
+}
+
+
 /// 根据正则regx生成字符串
 pub fn regex_string(regex_pattern: &str) -> String {
 //    let mut gen = Generator::new(r"(?x) (?P<year>[0-9]{4}) - (?P<month>[0-9]{2}) - (?P<day>[0-9]{2})", rand::thread_rng(), DEFAULT_MAX_REPEAT).unwrap();
@@ -23,13 +41,17 @@ pub fn regex_string(regex_pattern: &str) -> String {
 
 /// 生成uuid
 pub fn uuid() -> String {
-    let uuid = Uuid::new_v4();
+    let bytes: [u8; 16] = super::rng().gen();
+    let uuid = uuid::Builder::from_bytes(bytes)
+        .set_variant(uuid::Variant::RFC4122)
+        .set_version(uuid::Version::Random)
+        .build();
     uuid.to_string()
 }
 
 /// 随机生成一个bool
 pub fn bool() -> bool {
-    let mut rng = thread_rng();
+    let mut rng = super::rng();
     let n = rng.gen_range(1, 10);
 
     if n % 2 == 0 {
@@ -43,21 +65,21 @@ pub fn bool() -> bool {
 macro_rules! int {
     ($min_value:expr, $max_value:expr) => {
     {
-        let mut rng = thread_rng();
+        let mut rng = $crate::mock::rng();
         rng.gen_range($min_value, $max_value)
     }
     };
 
     ($min_value:expr) => {
     {
-        let mut rng = thread_rng();
+        let mut rng = $crate::mock::rng();
         rng.gen_range($min_value, i32::max_value())
     }
     };
 
     () => {
     {
-        let mut rng = thread_rng();
+        let mut rng = $crate::mock::rng();
         rng.gen::<i32>()
     }
     };
@@ -69,7 +91,7 @@ macro_rules! int {
 macro_rules! float {
     ($min_value:expr, $max_value:expr, $min_decimal_places:expr, $max_decimal_places:expr) => {
     {
-        let mut rng = thread_rng();
+        let mut rng = $crate::mock::rng();
         let n = rng.gen_range($min_value as f64, $max_value as f64);
         let l = rng.gen_range($min_decimal_places as u32, $max_decimal_places as u32);
         (n * 10_u64.pow(l) as f64).round() / 10_i64.pow(l) as f64
@@ -78,7 +100,7 @@ macro_rules! float {
 
     ($min_value:expr, $max_value:expr, $min_decimal_places:expr) => {
     {
-        let mut rng = thread_rng();
+        let mut rng = $crate::mock::rng();
         let n = rng.gen_range($min_value as f64, $max_value as f64);
         (n * 10_u64.pow($min_decimal_places) as f64).round() / 10_i64.pow($min_decimal_places) as f64
     }
@@ -162,13 +184,13 @@ pub fn datetime(min_value: &str, max_value: &str, format: &str) -> String {
 
 /// 随机返回一个字符串
 pub fn alphabet() -> char {
-    let mut rng = thread_rng();
+    let mut rng = super::rng();
     let idx = rng.gen_range(0, CHARSET2.len());
     CHARSET2[idx] as char
 }
 
 pub fn char() -> char {
-    let mut rng = thread_rng();
+    let mut rng = super::rng();
     let idx = rng.gen_range(0, CHARSET.len());
     CHARSET[idx] as char
 }
@@ -177,7 +199,7 @@ pub fn char() -> char {
 /// 随机生成英文+符号的字符串
 pub fn string(mut length: u64, mut min_length: u64, mut max_length: u64) -> String {
     let mut s = String::new();
-    let mut rng = thread_rng();
+    let mut rng = super::rng();
 
     if min_length == 0 {
         min_length = 5;
@@ -206,7 +228,7 @@ pub fn image(size: &str, foreground: &str, background: &str, format: &str, text:
         "120x600", "300x600"];
     let color_list = [["fff", "868e96"], ["fff", "212529"], ["fff", "007bff"], ["fff", "28a745"], ["fff", "17a2b8"], ["fff", "ffc107"], ["fff", "dc3545"],
         ["004085", "cce5ff"], ["383d41", "e2e3e5"], ["155724", "d4edda"], ["721c24", "f8d7da"], ["856404", "fff3cd"], ["0c5460", "d1ecf1"]];
-    let mut rng = thread_rng();
+    let mut rng = super::rng();
     let mut size = size;
     let background = background.replace("#", "");
     let mut background = background.trim();
@@ -0,0 +1,389 @@
+use serde_json::{json, Value, Map};
+use std::collections::{HashMap, HashSet};
+use regex::Regex;
+use lazy_static::lazy_static;
+
+
+/// 字段中可以使用表达式的属性
+/// min_value: "$start_date" 引用同级字段的值
+/// value: "=sum(items[].price)" 计算字段
+pub const EXPR_KEYS: [&str; 3] = ["value", "min_value", "max_value"];
+
+
+/// 表达式中对其它字段的引用
+/// up 表示要往上找几级(../)，path表示字段路径，如 items[].price
+#[derive(Debug, Clone)]
+pub struct FieldRef {
+    pub up: usize,
+    pub path: String,
+}
+
+
+impl FieldRef {
+    /// 引用路径中的第一个字段名称，用于计算同级字段的依赖
+    pub fn first_name(&self) -> String {
+        let name = self.path.split('.').next().unwrap_or("");
+        name.trim_end_matches("[]").to_string()
+    }
+}
+
+
+/// 计算字段支持的函数
+const EXPR_FUNCS: [&str; 6] = ["len", "count", "sum", "avg", "min", "max"];
+
+
+lazy_static! {
+    /// 字段路径，如 start_date、../user.name、items[].price
+    static ref FIELD_PATH_RE: Regex = Regex::new(r"^(\.\./)*[A-Za-z_]\w*(\[\])?(\.[A-Za-z_]\w*(\[\])?)*$").unwrap();
+    /// 函数调用，如 sum(items[].price)
+    static ref FUNC_CALL_RE: Regex = Regex::new(r"^(\w+)\s*\((.*)\)$").unwrap();
+}
+
+
+/// 是否是字段路径，如 start_date、../user.name、items[].price
+fn is_field_path(s: &str) -> bool {
+    FIELD_PATH_RE.is_match(s.trim())
+}
+
+
+/// 判断一个值是否是表达式: $字段路径 是字段引用，=函数(字段路径) 是计算字段
+/// 不符合表达式语法的值，如 "$100"、"=="，按普通的值处理
+pub fn is_expr(value: &Value) -> bool {
+    let v = match value.as_str() {
+        Some(v) => v.trim(),
+        None => return false
+    };
+    if v.starts_with("$") {
+        return is_field_path(&v[1..]);
+    }
+    if !v.starts_with("=") {
+        return false;
+    }
+    let expr = v[1..].trim();
+    match FUNC_CALL_RE.captures(expr) {
+        Some(cap) => EXPR_FUNCS.contains(&cap[1].to_lowercase().as_str()) && cap[2].split(",").all(|x| is_field_path(x)),
+        None => is_field_path(expr)
+    }
+}
+
+
+fn parse_ref(s: &str) -> Option<FieldRef> {
+    let mut s = s.trim().trim_start_matches("$");
+    if s == "" || s.parse::<f64>().is_ok() || s.starts_with("\"") || s.starts_with("'") {
+        return None;
+    }
+    let mut up = 0;
+    while s.starts_with("../") {
+        up += 1;
+        s = &s[3..];
+    }
+    Some(FieldRef { up, path: s.to_string() })
+}
+
+
+/// 把表达式拆分为函数名和引用参数
+/// "$start_date" => ("", [start_date])
+/// "=sum(items[].price)" => ("sum", [items[].price])
+/// "=len(items)" => ("len", [items])
+fn parse_expr(expr: &str) -> (String, Vec<FieldRef>) {
+    let expr = expr.trim();
+    if expr.starts_with("$") {
+        return ("".to_string(), parse_ref(expr).into_iter().collect());
+    }
+
+    let expr = expr.trim_start_matches("=").trim();
+    match FUNC_CALL_RE.captures(expr) {
+        Some(cap) => {
+            let func = cap[1].to_lowercase();
+            let refs = cap[2].split(",").filter_map(|x| parse_ref(x)).collect();
+            (func, refs)
+        }
+        None => ("".to_string(), parse_ref(expr).into_iter().collect())
+    }
+}
+
+
+/// 获取表达式中引用的字段
+pub fn get_expr_refs(expr: &str) -> Vec<FieldRef> {
+    parse_expr(expr).1
+}
+
+
+/// 按照路径查找字段值，路径中的 [] 表示展开数组
+fn lookup(scope: &Map<String, Value>, path: &str) -> Vec<Value> {
+    let mut current: Vec<Value> = Vec::new();
+    for (i, segment) in path.split('.').enumerate() {
+        let is_array = segment.ends_with("[]");
+        let name = segment.trim_end_matches("[]");
+        let mut next = Vec::new();
+
+        let mut push = |v: &Value| {
+            if is_array {
+                if let Some(items) = v.as_array() {
+                    for item in items {
+                        next.push(item.clone());
+                    }
+                }
+            } else {
+                next.push(v.clone());
+            }
+        };
+
+        if i == 0 {
+            if let Some(v) = scope.get(name) {
+                push(v);
+            }
+        } else {
+            for v in &current {
+                if let Some(v) = v.get(name) {
+                    push(v);
+                }
+            }
+        }
+        current = next;
+    }
+    current
+}
+
+
+/// 把引用的值展开为一个列表，如果只引用了一个数组，就用数组里面的元素
+fn flatten_values(values: Vec<Value>) -> Vec<Value> {
+    if values.len() == 1 {
+        if let Some(items) = values[0].as_array() {
+            return items.clone();
+        }
+    }
+    values
+}
+
+
+fn number_value(v: f64, all_int: bool) -> Value {
+    if all_int {
+        json!(v as i64)
+    } else {
+        // 避免浮点相加出现 0.30000000000000004 这样的值
+        json!((v * 1e10).round() / 1e10)
+    }
+}
+
+
+/// 计算表达式的值
+/// scopes 最后一个为当前层级的字段数据，往前依次为上一级
+pub fn eval_expr(expr: &str, scopes: &[&Map<String, Value>]) -> Option<Value> {
+    let (func, refs) = parse_expr(expr);
+    let field_ref = refs.get(0)?;
+    if field_ref.up >= scopes.len() {
+        return None;
+    }
+    let scope = scopes[scopes.len() - 1 - field_ref.up];
+    let values = lookup(scope, &field_ref.path);
+
+    match func.as_str() {
+        "" => {
+            if field_ref.path.contains("[]") {
+                Some(Value::Array(values))
+            } else {
+                values.into_iter().next()
+            }
+        }
+        "len" | "count" => {
+            if values.len() == 1 {
+                if let Some(s) = values[0].as_str() {
+                    return Some(json!(s.chars().count()));
+                }
+            }
+            Some(json!(flatten_values(values).len()))
+        }
+        "sum" | "avg" | "min" | "max" => {
+            let values = flatten_values(values);
+            let all_int = values.iter().all(|x| x.is_i64() || x.is_u64());
+            let numbers: Vec<f64> = values.iter().filter_map(|x| x.as_f64()).collect();
+            if numbers.is_empty() {
+                return if func == "sum" { Some(json!(0)) } else { None };
+            }
+            match func.as_str() {
+                "sum" => Some(number_value(numbers.iter().sum(), all_int)),
+                "avg" => Some(number_value(numbers.iter().sum::<f64>() / numbers.len() as f64, false)),
+                "min" => Some(number_value(numbers.iter().cloned().fold(f64::INFINITY, f64::min), all_int)),
+                _ => Some(number_value(numbers.iter().cloned().fold(f64::NEG_INFINITY, f64::max), all_int)),
+            }
+        }
+        _ => None
+    }
+}
+
+
+/// 收集字段定义中，引用到 depth 层级以上同级字段的名称
+fn collect_field_refs(field_attr: &Value, depth: usize, result: &mut HashSet<String>) {
+    match field_attr {
+        Value::Object(attr) => {
+            for key in EXPR_KEYS.iter() {
+                if let Some(v) = attr.get(*key) {
+                    if is_expr(v) {
+                        for field_ref in get_expr_refs(v.as_str().unwrap()) {
+                            if field_ref.up == depth {
+                                result.insert(field_ref.first_name());
+                            }
+                        }
+                    }
+                }
+            }
+            for (k, v) in attr {
+                if k.starts_with("-") || EXPR_KEYS.contains(&k.as_str()) {
+                    continue;
+                }
+                if v.is_object() || v.is_array() {
+                    collect_field_refs(v, depth + 1, result);
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(item) = items.get(0) {
+                // 数组元素如果是对象，元素里面的字段往上一级就是数组所在的对象
+                // 如果是普通值，会多一层包装
+                let is_object_item = match item.as_object() {
+                    Some(item) => item.values().any(|x| x.is_object() || x.is_array()),
+                    None => false
+                };
+                if is_object_item {
+                    collect_field_refs(item, depth, result);
+                } else {
+                    collect_field_refs(item, depth + 1, result);
+                }
+            }
+        }
+        _ => ()
+    }
+}
+
+
+/// 获取一个字段依赖的同级字段
+pub fn field_dependencies(field_attr: &Value) -> HashSet<String> {
+    let mut result = HashSet::new();
+    collect_field_refs(field_attr, 0, &mut result);
+    result
+}
+
+
+/// 按照依赖关系排序对象中的字段，被引用的字段先生成
+/// 如果有循环依赖，返回 Err(循环路径)
+pub fn field_order(model: &Map<String, Value>) -> Result<Vec<String>, Vec<String>> {
+    let mut deps: HashMap<&str, HashSet<String>> = HashMap::new();
+    for (k, v) in model {
+        if k.starts_with("-") {
+            continue;
+        }
+        let mut d = field_dependencies(v);
+        d.retain(|x| x != k && model.contains_key(x));
+        deps.insert(k.as_str(), d);
+    }
+
+    // 0: 未访问 1: 访问中 2: 已完成
+    let mut state: HashMap<&str, u8> = HashMap::new();
+    let mut order: Vec<String> = Vec::new();
+
+    fn visit<'a>(name: &'a str, deps: &HashMap<&'a str, HashSet<String>>, state: &mut HashMap<&'a str, u8>, stack: &mut Vec<String>, order: &mut Vec<String>) -> Result<(), Vec<String>> {
+        match state.get(name).cloned() {
+            Some(2) => return Ok(()),
+            Some(1) => {
+                let start = stack.iter().position(|x| x == name).unwrap_or(0);
+                let mut cycle = stack[start..].to_vec();
+                cycle.push(name.to_string());
+                return Err(cycle);
+            }
+            _ => ()
+        }
+        state.insert(name, 1);
+        stack.push(name.to_string());
+        if let Some(d) = deps.get(name) {
+            let mut d: Vec<&String> = d.iter().collect();
+            d.sort();
+            for dep in d {
+                if let Some((dep_name, _)) = deps.get_key_value(dep.as_str()) {
+                    visit(*dep_name, deps, state, stack, order)?;
+                }
+            }
+        }
+        stack.pop();
+        state.insert(name, 2);
+        order.push(name.to_string());
+        Ok(())
+    }
+
+    for k in model.keys() {
+        if k.starts_with("-") {
+            continue;
+        }
+        let mut stack = Vec::new();
+        visit(k.as_str(), &deps, &mut state, &mut stack, &mut order)?;
+    }
+    Ok(order)
+}
+
+
+/// 检查response定义中是否有循环依赖的字段，返回错误说明
+pub fn check_cycles(model: &Value, prefix: &str, errors: &mut Vec<String>) {
+    match model {
+        Value::Object(model_obj) => {
+            if let Err(cycle) = field_order(model_obj) {
+                errors.push(format!("{}: {}", prefix, cycle.join(" -> ")));
+            }
+            for (k, v) in model_obj {
+                if k.starts_with("-") || EXPR_KEYS.contains(&k.as_str()) {
+                    continue;
+                }
+                if v.is_object() || v.is_array() {
+                    check_cycles(v, &format!("{}.{}", prefix, k), errors);
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(item) = items.get(0) {
+                check_cycles(item, &format!("{}[]", prefix), errors);
+            }
+        }
+        _ => ()
+    }
+}
+
+
+/// 把字段定义中的表达式替换为实际的值，其它属性保持不变
+pub fn resolve_field_attr(field_attr: &Value, scopes: &[&Map<String, Value>]) -> Value {
+    let attr = match field_attr.as_object() {
+        Some(attr) => attr,
+        None => return field_attr.clone()
+    };
+
+    let mut has_expr = false;
+    for key in EXPR_KEYS.iter() {
+        if let Some(v) = attr.get(*key) {
+            if is_expr(v) {
+                has_expr = true;
+            }
+        }
+    }
+    if !has_expr {
+        return field_attr.clone();
+    }
+
+    let mut new_attr = attr.clone();
+    for key in EXPR_KEYS.iter() {
+        if let Some(v) = attr.get(*key) {
+            if is_expr(v) {
+                match eval_expr(v.as_str().unwrap(), scopes) {
+                    Some(v) => {
+                        new_attr.insert(key.to_string(), v);
+                    }
+                    None => {
+                        if *key == "value" {
+                            new_attr.insert(key.to_string(), Value::Null);
+                        } else {
+                            new_attr.remove(*key);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Value::Object(new_attr)
+}
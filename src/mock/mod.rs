@@ -1,4 +1,63 @@
 pub mod basic;
 pub mod text;
 pub mod name;
-pub mod web;
\ No newline at end of file
+pub mod web;
+pub mod expr;
+
+use std::cell::RefCell;
+
+use rand::rngs::StdRng;
+use rand::{thread_rng, RngCore, SeedableRng};
+
+
+thread_local! {
+    /// 设置了seed时使用的随机数生成器，没有设置时使用thread_rng
+    static SEEDED_RNG: RefCell<Option<StdRng>> = RefCell::new(None);
+}
+
+
+/// mock数据使用的随机数生成器，在 with_seed 中生成的数据每次都一样
+pub struct MockRng;
+
+
+impl RngCore for MockRng {
+    fn next_u32(&mut self) -> u32 {
+        SEEDED_RNG.with(|x| match &mut *x.borrow_mut() {
+            Some(rng) => rng.next_u32(),
+            None => thread_rng().next_u32()
+        })
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        SEEDED_RNG.with(|x| match &mut *x.borrow_mut() {
+            Some(rng) => rng.next_u64(),
+            None => thread_rng().next_u64()
+        })
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        SEEDED_RNG.with(|x| match &mut *x.borrow_mut() {
+            Some(rng) => rng.fill_bytes(dest),
+            None => thread_rng().fill_bytes(dest)
+        })
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+
+pub fn rng() -> MockRng {
+    MockRng
+}
+
+
+/// 用固定的seed生成数据，结束后恢复原来的生成器
+pub fn with_seed<T>(seed: u64, f: impl FnOnce() -> T) -> T {
+    let old = SEEDED_RNG.with(|x| x.replace(Some(StdRng::seed_from_u64(seed))));
+    let result = f();
+    SEEDED_RNG.with(|x| x.replace(old));
+    result
+}
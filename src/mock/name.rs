@@ -1,4 +1,4 @@
-use rand::{thread_rng, Rng};
+use rand::Rng;
 
 const EN_FIRAR_NAME: [&str; 66] = ["James", "John", "Robert", "Michael", "William",
     "David", "Richard", "Charles", "Joseph", "Thomas",
@@ -41,7 +41,7 @@ const CN_LAST_NAME: [&str; 30] = ["伟", "芳", "娜", "秀英", "敏", "静", "
 
 
 pub fn name() -> String {
-    let mut rng = thread_rng();
+    let mut rng = super::rng();
     let n = rng.gen_range(0, EN_FIRAR_NAME.len());
     let first_name = EN_FIRAR_NAME[n];
     let middle_name = if n % 2 == 0 {
@@ -57,7 +57,7 @@ pub fn name() -> String {
 }
 
 pub fn cname() -> String {
-    let mut rng = thread_rng();
+    let mut rng = super::rng();
     let n = rng.gen_range(0, CN_FIRST_NAME.len());
     let first_name = CN_FIRST_NAME[n];
     let n = rng.gen_range(0, CN_LAST_NAME.len());
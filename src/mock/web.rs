@@ -1,16 +1,16 @@
-use rand::{thread_rng, Rng};
+use rand::Rng;
 use crate::mock;
 
 const DOMAIN_SUFFIX:[&str;10] = ["com", "net", "org", "edu", "gov", "cc", "cn", "com.cn", "name", "mobi"];
 
 pub fn ip() -> String {
-    let mut rng = thread_rng();
+    let mut rng = super::rng();
     format!("{}.{}.{}.{}", rng.gen_range(0, 255), rng.gen_range(0, 255), rng.gen_range(0, 255), rng.gen_range(0, 255))
 }
 
 
 pub fn domain_suffix() -> String {
-    let mut rng = thread_rng();
+    let mut rng = super::rng();
     let n = rng.gen_range(0, DOMAIN_SUFFIX.len());
     DOMAIN_SUFFIX[n].to_string()
 }
@@ -29,7 +29,7 @@ pub fn email() -> String {
 }
 
 pub fn url() -> String {
-    let mut rng = thread_rng();
+    let mut rng = super::rng();
     let n = rng.gen_range(1, 10);
     let http = if n % 2 == 0 {
         "http"
@@ -0,0 +1,536 @@
+//! 本地的 OAuth2 / OpenID Connect 服务
+//! 使用 _auth.json5 中的用户和权限组，支持 authorization code + PKCE、refresh_token、
+//! userinfo、jwks 和 discovery，不需要联网就可以调试前端的登录流程
+
+use actix_web::{http, web, HttpRequest, HttpResponse};
+use serde_json::{json, Value, Map};
+use sha2::{Sha256, Digest};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::db;
+use crate::auth;
+
+
+/// 获取issuer，没有设置的时候使用请求的地址
+fn get_issuer(req: &HttpRequest, oauth: &db::OAuthSetting) -> String {
+    if oauth.issuer != "" {
+        return oauth.issuer.clone();
+    }
+    let conn = req.connection_info();
+    format!("{}://{}", conn.scheme(), conn.host())
+}
+
+
+fn oauth_error(error: &str, desc: &str) -> HttpResponse {
+    HttpResponse::BadRequest().json(json!({"error": error, "error_description": desc}))
+}
+
+
+/// url参数编码
+fn url_encode(s: &str) -> String {
+    let mut result = String::new();
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => result.push(b as char),
+            _ => result.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    result
+}
+
+
+fn html_escape(s: &str) -> String {
+    s.replace("&", "&amp;").replace("<", "&lt;").replace(">", "&gt;").replace("\"", "&quot;")
+}
+
+
+fn new_random_code() -> String {
+    format!("{}{}", uuid::Uuid::new_v4().to_simple(), uuid::Uuid::new_v4().to_simple())
+}
+
+
+/// 移除已经过期的授权码、access_token、refresh_token，授权的数据都保存在内存中
+pub fn remove_expired_grants(auth_doc: &mut db::AuthDoc) {
+    let now = auth::now_timestamp();
+    auth_doc.oauth_codes.retain(|_, x| x.expires_at >= now);
+    auth_doc.oauth_access_tokens.retain(|_, x| x.expires_at >= now);
+    auth_doc.oauth_refresh_tokens.retain(|_, x| x.expires_at >= now);
+}
+
+
+/// 校验client_id和redirect_uri，没有设置clients的时候不限制client
+/// redirect_uri 只能是 http、https 地址，javascript: 这样的地址会在拒绝链接中执行脚本
+fn check_client(oauth: &db::OAuthSetting, client_id: &str, redirect_uri: &str) -> Result<(), String> {
+    let lower = redirect_uri.trim().to_lowercase();
+    if !lower.starts_with("http://") && !lower.starts_with("https://") {
+        return Err(format!("redirect_uri {} must be a http or https url", redirect_uri));
+    }
+    if oauth.clients.is_empty() {
+        return Ok(());
+    }
+    for client in &oauth.clients {
+        if client.client_id == client_id {
+            if client.redirect_uris.is_empty() || client.redirect_uris.iter().any(|x| x == redirect_uri) {
+                return Ok(());
+            }
+            return Err(format!("redirect_uri {} is not allowed", redirect_uri));
+        }
+    }
+    Err(format!("unknown client_id {}", client_id))
+}
+
+
+/// 校验client_secret，client没有设置secret的时候是公开客户端，使用PKCE
+fn check_client_secret(oauth: &db::OAuthSetting, client_id: &str, client_secret: &str) -> bool {
+    for client in &oauth.clients {
+        if client.client_id == client_id {
+            return client.client_secret == "" || client.client_secret == client_secret;
+        }
+    }
+    true
+}
+
+
+/// 校验PKCE的code_verifier
+fn check_code_verifier(grant: &db::OAuthGrant, code_verifier: &str) -> bool {
+    if grant.code_challenge == "" {
+        return true;
+    }
+    match grant.code_challenge_method.as_str() {
+        "S256" => {
+            let hash = Sha256::digest(code_verifier.as_bytes());
+            base64::encode_config(&hash, base64::URL_SAFE_NO_PAD) == grant.code_challenge
+        }
+        _ => code_verifier == grant.code_challenge
+    }
+}
+
+
+/// 根据subject和权限组找到用户 (用户token, user)
+fn find_user<'a>(auth_doc: &'a db::AuthDoc, group_name: &str, sub: &str) -> Option<(&'a String, &'a Value)> {
+    for group in &auth_doc.groups {
+        if group.name != group_name {
+            continue;
+        }
+        for (token, user) in &group.users {
+            if auth::get_user_subject(user) == sub {
+                return Some((token, user));
+            }
+        }
+    }
+    None
+}
+
+
+/// 根据用户token找到用户
+fn find_user_by_user_token<'a>(auth_doc: &'a db::AuthDoc, group_name: &str, user_token: &str) -> Option<&'a Value> {
+    for group in &auth_doc.groups {
+        if group.name == group_name {
+            return group.users.get(user_token);
+        }
+    }
+    None
+}
+
+
+/// 根据OAuth的access_token找到用户 (user, group name)，过期的access_token找不到
+pub fn find_access_token_user<'a>(auth_doc: &'a db::AuthDoc, token: &str) -> Option<(&'a Value, &'a String)> {
+    let grant = auth_doc.oauth_access_tokens.get(token)?;
+    if grant.expires_at < auth::now_timestamp() {
+        return None;
+    }
+    let group = auth_doc.groups.iter().find(|x| x.name == grant.group)?;
+    group.users.get(&grant.user_token).map(|user| (user, &group.name))
+}
+
+
+/// 用户的claims，去掉password和token
+fn get_user_claims(user: &Value, group: &str) -> Map<String, Value> {
+    let mut claims = Map::new();
+    if let Some(user_obj) = user.as_object() {
+        for (k, v) in user_obj {
+            if k != "password" && k != "token" {
+                claims.insert(k.to_string(), v.clone());
+            }
+        }
+    }
+    claims.insert("sub".to_string(), json!(auth::get_user_subject(user)));
+    claims.insert("group".to_string(), json!(group));
+    claims
+}
+
+
+/// OpenID Connect discovery
+/// GET /.well-known/openid-configuration
+pub async fn discovery(req: HttpRequest, data: web::Data<Mutex<db::Database>>) -> HttpResponse {
+    let data = data.lock().unwrap();
+    let oauth = match data.auth_doc.as_ref().and_then(|x| x.oauth.as_ref()) {
+        Some(v) => v,
+        None => return HttpResponse::NotFound().json(json!({"code": -1, "msg": "oauth is not enabled in _auth.json5"}))
+    };
+    let issuer = get_issuer(&req, oauth);
+    let mut scopes = vec!["openid".to_string(), "profile".to_string(), "email".to_string(), "offline_access".to_string()];
+    for scope in oauth.scopes.keys() {
+        scopes.push(scope.to_string());
+    }
+
+    HttpResponse::Ok().json(json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{}/__oauth/authorize", issuer),
+        "token_endpoint": format!("{}/__oauth/token", issuer),
+        "userinfo_endpoint": format!("{}/__oauth/userinfo", issuer),
+        "jwks_uri": format!("{}/__oauth/jwks", issuer),
+        "response_types_supported": ["code"],
+        "grant_types_supported": ["authorization_code", "refresh_token"],
+        "subject_types_supported": ["public"],
+        "id_token_signing_alg_values_supported": [oauth.jwt.algorithm],
+        "code_challenge_methods_supported": ["S256", "plain"],
+        "token_endpoint_auth_methods_supported": ["none", "client_secret_post", "client_secret_basic"],
+        "scopes_supported": scopes,
+    }))
+}
+
+
+/// 授权页面，没有选择用户的时候展示用户列表，选择后跳转回redirect_uri并带上授权码
+/// GET /__oauth/authorize
+pub async fn authorize(query: web::Query<HashMap<String, String>>, data: web::Data<Mutex<db::Database>>) -> HttpResponse {
+    let mut data = data.lock().unwrap();
+    let auth_doc = match &mut data.auth_doc {
+        Some(v) => v,
+        None => return HttpResponse::NotFound().json(json!({"code": -1, "msg": "oauth is not enabled in _auth.json5"}))
+    };
+    let oauth = match &auth_doc.oauth {
+        Some(v) => v.clone(),
+        None => return HttpResponse::NotFound().json(json!({"code": -1, "msg": "oauth is not enabled in _auth.json5"}))
+    };
+    remove_expired_grants(auth_doc);
+
+    let get = |key: &str| -> String {
+        query.get(key).cloned().unwrap_or_default()
+    };
+    let client_id = get("client_id");
+    let redirect_uri = get("redirect_uri");
+    let state = get("state");
+
+    if get("response_type") != "code" {
+        return oauth_error("unsupported_response_type", "only response_type=code is supported");
+    }
+    if redirect_uri == "" {
+        return oauth_error("invalid_request", "redirect_uri is required");
+    }
+    if let Err(e) = check_client(&oauth, &client_id, &redirect_uri) {
+        return oauth_error("invalid_client", &e);
+    }
+
+    let user = get("user");
+    let group = get("group");
+    if user == "" {
+        // 展示用户选择页面
+        let mut base_query = String::new();
+        for (k, v) in query.iter() {
+            if k != "user" && k != "group" {
+                base_query.push_str(&format!("{}={}&", url_encode(k), url_encode(v)));
+            }
+        }
+        let mut items = String::new();
+        for g in &auth_doc.groups {
+            items.push_str(&format!("<h3>{}</h3><ul>", html_escape(&g.name)));
+            for (_, u) in &g.users {
+                let sub = auth::get_user_subject(u);
+                let label = match u.get("username").and_then(|x| x.as_str()) {
+                    Some(v) => v.to_string(),
+                    None => sub.clone()
+                };
+                items.push_str(&format!(r#"<li><a href="/__oauth/authorize?{}user={}&group={}">{}</a></li>"#,
+                                        html_escape(&base_query), url_encode(&sub), url_encode(&g.name), html_escape(&label)));
+            }
+            items.push_str("</ul>");
+        }
+        let html = format!(r#"<!DOCTYPE html><html><head><meta charset="utf-8"><title>Panda Api OAuth</title></head><body><h2>{} 请求登录，选择一个用户</h2><p>scope: {}</p>{}<p><a href="{}{}error=access_denied&state={}">拒绝</a></p></body></html>"#,
+                           html_escape(&client_id), html_escape(&get("scope")), items,
+                           html_escape(&redirect_uri), if redirect_uri.contains("?") { "&" } else { "?" }, url_encode(&state));
+        return HttpResponse::Ok().content_type("text/html; charset=utf-8").body(html);
+    }
+
+    let user_token = match find_user(auth_doc, &group, &user) {
+        Some((token, _)) => token.to_string(),
+        None => return oauth_error("invalid_request", &format!("user {} not found in group {}", user, group))
+    };
+
+    let code = new_random_code();
+    auth_doc.oauth_codes.insert(code.clone(), db::OAuthGrant {
+        client_id,
+        redirect_uri: redirect_uri.clone(),
+        scope: get("scope"),
+        nonce: get("nonce"),
+        code_challenge: get("code_challenge"),
+        code_challenge_method: get("code_challenge_method"),
+        user_token,
+        group,
+        expires_at: auth::now_timestamp() + oauth.code_expires_in,
+    });
+
+    let mut location = format!("{}{}code={}", redirect_uri, if redirect_uri.contains("?") { "&" } else { "?" }, url_encode(&code));
+    if state != "" {
+        location.push_str(&format!("&state={}", url_encode(&state)));
+    }
+    HttpResponse::Found().header(http::header::LOCATION, location).finish()
+}
+
+
+/// 生成access_token、refresh_token，scope中有openid的时候生成id_token
+fn issue_tokens(req: &HttpRequest, auth_doc: &mut db::AuthDoc, oauth: &db::OAuthSetting, grant: db::OAuthGrant) -> HttpResponse {
+    let user = match find_user_by_user_token(auth_doc, &grant.group, &grant.user_token) {
+        Some(v) => v.clone(),
+        None => return oauth_error("invalid_grant", "user not found")
+    };
+    let now = auth::now_timestamp();
+
+    let mut response = json!({
+        "token_type": "Bearer",
+        "expires_in": oauth.access_token_expires_in,
+        "scope": grant.scope,
+    });
+
+    if grant.scope.split_whitespace().any(|x| x == "openid") {
+        let mut claims = get_user_claims(&user, &grant.group);
+        claims.insert("iss".to_string(), json!(get_issuer(req, oauth)));
+        claims.insert("aud".to_string(), json!(grant.client_id));
+        claims.insert("iat".to_string(), json!(now));
+        claims.insert("exp".to_string(), json!(now + oauth.access_token_expires_in));
+        if grant.nonce != "" {
+            claims.insert("nonce".to_string(), json!(grant.nonce));
+        }
+        match auth::sign_jwt_claims(&oauth.jwt, claims) {
+            Ok(id_token) => {
+                response["id_token"] = json!(id_token);
+            }
+            Err(e) => return oauth_error("server_error", &e)
+        }
+    }
+
+    let access_token = new_random_code();
+    let refresh_token = new_random_code();
+    response["access_token"] = json!(access_token);
+    response["refresh_token"] = json!(refresh_token);
+
+    let mut access_grant = grant.clone();
+    access_grant.expires_at = now + oauth.access_token_expires_in;
+    auth_doc.oauth_access_tokens.insert(access_token, access_grant);
+
+    let mut refresh_grant = grant;
+    refresh_grant.expires_at = now + oauth.refresh_token_expires_in;
+    auth_doc.oauth_refresh_tokens.insert(refresh_token, refresh_grant);
+
+    HttpResponse::Ok().json(response)
+}
+
+
+/// 获取client的认证信息，支持 client_secret_basic 和 client_secret_post
+fn get_client_credentials(req: &HttpRequest, form: &HashMap<String, String>) -> (String, String) {
+    if let Some(v) = req.headers().get("authorization").and_then(|x| x.to_str().ok()) {
+        if v.starts_with("Basic ") {
+            if let Ok(decoded) = base64::decode(v.trim_start_matches("Basic ").trim()) {
+                let decoded = String::from_utf8(decoded).unwrap_or_default();
+                let decoded: Vec<&str> = decoded.splitn(2, ':').collect();
+                if decoded.len() == 2 {
+                    return (decoded[0].to_string(), decoded[1].to_string());
+                }
+            }
+        }
+    }
+    (form.get("client_id").cloned().unwrap_or_default(), form.get("client_secret").cloned().unwrap_or_default())
+}
+
+
+/// 用授权码或者refresh_token换取token
+/// POST /__oauth/token
+pub async fn token(req: HttpRequest, form: web::Form<HashMap<String, String>>, data: web::Data<Mutex<db::Database>>) -> HttpResponse {
+    let mut data = data.lock().unwrap();
+    let auth_doc = match &mut data.auth_doc {
+        Some(v) => v,
+        None => return HttpResponse::NotFound().json(json!({"code": -1, "msg": "oauth is not enabled in _auth.json5"}))
+    };
+    let oauth = match &auth_doc.oauth {
+        Some(v) => v.clone(),
+        None => return HttpResponse::NotFound().json(json!({"code": -1, "msg": "oauth is not enabled in _auth.json5"}))
+    };
+    remove_expired_grants(auth_doc);
+
+    let (client_id, client_secret) = get_client_credentials(&req, &form);
+    if !check_client_secret(&oauth, &client_id, &client_secret) {
+        return oauth_error("invalid_client", "client authentication failed");
+    }
+
+    let now = auth::now_timestamp();
+    let grant_type = form.get("grant_type").cloned().unwrap_or_default();
+    match grant_type.as_str() {
+        "authorization_code" => {
+            let code = form.get("code").cloned().unwrap_or_default();
+            // 授权码只能使用一次
+            let grant = match auth_doc.oauth_codes.remove(&code) {
+                Some(v) => v,
+                None => return oauth_error("invalid_grant", "invalid authorization code")
+            };
+            if grant.expires_at < now {
+                return oauth_error("invalid_grant", "authorization code expired");
+            }
+            if grant.client_id != client_id {
+                return oauth_error("invalid_grant", "client_id does not match");
+            }
+            if let Some(redirect_uri) = form.get("redirect_uri") {
+                if redirect_uri != &grant.redirect_uri {
+                    return oauth_error("invalid_grant", "redirect_uri does not match");
+                }
+            }
+            let code_verifier = form.get("code_verifier").cloned().unwrap_or_default();
+            if !check_code_verifier(&grant, &code_verifier) {
+                return oauth_error("invalid_grant", "code_verifier does not match code_challenge");
+            }
+            issue_tokens(&req, auth_doc, &oauth, grant)
+        }
+        "refresh_token" => {
+            let refresh_token = form.get("refresh_token").cloned().unwrap_or_default();
+            let grant = match auth_doc.oauth_refresh_tokens.remove(&refresh_token) {
+                Some(v) => v,
+                None => return oauth_error("invalid_grant", "invalid refresh_token")
+            };
+            if grant.expires_at < now {
+                return oauth_error("invalid_grant", "refresh_token expired");
+            }
+            if client_id != "" && grant.client_id != client_id {
+                return oauth_error("invalid_grant", "client_id does not match");
+            }
+            issue_tokens(&req, auth_doc, &oauth, grant)
+        }
+        _ => oauth_error("unsupported_grant_type", &format!("grant_type {} is not supported", grant_type))
+    }
+}
+
+
+/// 获取access_token对应的用户信息
+/// GET /__oauth/userinfo
+pub async fn userinfo(req: HttpRequest, data: web::Data<Mutex<db::Database>>) -> HttpResponse {
+    let data = data.lock().unwrap();
+    let auth_doc = match &data.auth_doc {
+        Some(v) => v,
+        None => return HttpResponse::NotFound().json(json!({"code": -1, "msg": "oauth is not enabled in _auth.json5"}))
+    };
+
+    let token = match req.headers().get("authorization").and_then(|x| x.to_str().ok()) {
+        Some(v) => v.trim_start_matches("Bearer ").trim().to_string(),
+        None => "".to_string()
+    };
+    let grant = match auth_doc.oauth_access_tokens.get(&token) {
+        Some(v) => v,
+        None => return HttpResponse::Unauthorized().json(json!({"error": "invalid_token"}))
+    };
+    if grant.expires_at < auth::now_timestamp() {
+        return HttpResponse::Unauthorized().json(json!({"error": "invalid_token", "error_description": "access_token expired"}));
+    }
+    match find_user_by_user_token(auth_doc, &grant.group, &grant.user_token) {
+        Some(user) => HttpResponse::Ok().json(Value::Object(get_user_claims(user, &grant.group))),
+        None => HttpResponse::Unauthorized().json(json!({"error": "invalid_token"}))
+    }
+}
+
+
+/// 读取DER中的一个TLV，返回 (tag, 内容, 剩余的数据)
+fn read_der(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let tag = *data.get(0)?;
+    let first = *data.get(1)? as usize;
+    let (length, start) = if first < 0x80 {
+        (first, 2)
+    } else {
+        let n = first & 0x7f;
+        if n > std::mem::size_of::<usize>() {
+            return None;
+        }
+        let mut length = 0usize;
+        for i in 0..n {
+            length = (length << 8) | *data.get(2 + i)? as usize;
+        }
+        (length, 2 + n)
+    };
+    let end = start.checked_add(length)?;
+    let content = data.get(start..end)?;
+    Some((tag, content, &data[end..]))
+}
+
+
+/// 读取pem文件中的DER数据
+fn pem_to_der(pem: &[u8]) -> Option<(String, Vec<u8>)> {
+    let pem = String::from_utf8_lossy(pem);
+    let label = pem.lines().find(|x| x.starts_with("-----BEGIN"))?.to_string();
+    let body: String = pem.lines().filter(|x| !x.starts_with("-----")).map(|x| x.trim()).collect();
+    Some((label, base64::decode(&body).ok()?))
+}
+
+
+fn base64_url(data: &[u8]) -> String {
+    // 去掉DER整数前面补的0
+    let mut data = data;
+    while data.len() > 1 && data[0] == 0 {
+        data = &data[1..];
+    }
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+
+/// 把RSA/EC公钥转换为jwk
+fn public_key_to_jwk(setting: &db::JwtSetting) -> Option<Value> {
+    let (label, der) = pem_to_der(&setting.public_key_data)?;
+    let alg = setting.algorithm.as_str();
+
+    // RSA PUBLIC KEY 是 PKCS#1，PUBLIC KEY 是 SubjectPublicKeyInfo
+    let key_data = if label.contains("RSA PUBLIC KEY") {
+        der.clone()
+    } else {
+        let (_, spki, _) = read_der(&der)?;
+        let (_, _algorithm, rest) = read_der(spki)?;
+        let (_, bit_string, _) = read_der(rest)?;
+        bit_string.get(1..)?.to_vec()
+    };
+
+    if alg.starts_with("ES") {
+        // EC 公钥: 0x04 || x || y
+        if key_data.get(0) != Some(&0x04) {
+            return None;
+        }
+        let n = (key_data.len() - 1) / 2;
+        let crv = if alg == "ES384" { "P-384" } else { "P-256" };
+        return Some(json!({
+            "kty": "EC", "use": "sig", "alg": alg, "kid": auth::JWT_KEY_ID, "crv": crv,
+            "x": base64::encode_config(key_data.get(1..1 + n)?, base64::URL_SAFE_NO_PAD),
+            "y": base64::encode_config(key_data.get(1 + n..)?, base64::URL_SAFE_NO_PAD),
+        }));
+    }
+
+    let (_, rsa_key, _) = read_der(&key_data)?;
+    let (_, n, rest) = read_der(rsa_key)?;
+    let (_, e, _) = read_der(rest)?;
+    Some(json!({
+        "kty": "RSA", "use": "sig", "alg": alg, "kid": auth::JWT_KEY_ID,
+        "n": base64_url(n), "e": base64_url(e),
+    }))
+}
+
+
+/// id_token 签名公钥，HMAC 签名的没有公钥
+/// GET /__oauth/jwks
+pub async fn jwks(data: web::Data<Mutex<db::Database>>) -> HttpResponse {
+    let data = data.lock().unwrap();
+    let oauth = match data.auth_doc.as_ref().and_then(|x| x.oauth.as_ref()) {
+        Some(v) => v,
+        None => return HttpResponse::NotFound().json(json!({"code": -1, "msg": "oauth is not enabled in _auth.json5"}))
+    };
+    let mut keys = Vec::new();
+    if !oauth.jwt.algorithm.starts_with("HS") {
+        match public_key_to_jwk(&oauth.jwt) {
+            Some(v) => keys.push(v),
+            None => println!("parse jwt public key {} error", oauth.jwt.public_key)
+        }
+    }
+    HttpResponse::Ok().json(json!({"keys": keys}))
+}
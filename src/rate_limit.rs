@@ -0,0 +1,369 @@
+//! 接口限流中间件
+//! 可以在 _settings.json5、接口文档、接口、_auth.json5的权限组中设置 rate_limit
+//! {mode: "fixed_window" 或 "token_bucket", limit: 10, window: 60, burst: 20, key: "token" | "ip" | "header:X-Client-Id" | "api", response: {...}, status: 429}
+
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use actix_service::{Service, Transform};
+use actix_web::body::{Body, ResponseBody};
+use actix_web::dev::{ResourceDef, ServiceRequest, ServiceResponse};
+use actix_web::http::{HeaderName, HeaderValue, StatusCode};
+use actix_web::{web, Error, HttpResponse};
+use futures::future::{ok, Future, Ready};
+use serde_json::{json, Value};
+
+use crate::auth;
+use crate::db;
+
+
+/// 一条限流规则
+#[derive(Debug, Clone)]
+struct RateLimitRule {
+    // 规则所在的层级，用于区分计数，如 global、doc:auth.json5、api:GET /login/、group:admin
+    scope: String,
+    token_bucket: bool,
+    limit: u64,
+    // 时间窗口，单位秒
+    window: u64,
+    // 令牌桶容量
+    burst: u64,
+    key: String,
+    status: u16,
+    response: Value,
+}
+
+
+/// 限流的计数状态
+#[derive(Debug, Clone)]
+enum Counter {
+    FixedWindow { start: Instant, count: u64 },
+    TokenBucket { tokens: f64, last: Instant },
+}
+
+
+/// 所有的计数，value为 (计数状态, 过期时间)，过期后的计数和新建的一样，可以移除
+struct Counters {
+    items: HashMap<String, (Counter, Instant)>,
+    last_prune: Instant,
+}
+
+
+impl Counters {
+    fn new() -> Self {
+        Counters { items: HashMap::new(), last_prune: Instant::now() }
+    }
+
+    /// 每秒最多清理一次过期的计数
+    fn prune(&mut self, now: Instant) {
+        if now.duration_since(self.last_prune) < Duration::from_secs(1) {
+            return;
+        }
+        self.last_prune = now;
+        self.items.retain(|_, (_, expires_at)| *expires_at > now);
+    }
+}
+
+
+/// 时间窗口和计数保存的最长时间，单位秒
+const MAX_WINDOW: u64 = 365 * 24 * 3600;
+
+
+/// 一条规则检查后的结果，用于生成 X-RateLimit-* header
+struct RateLimitState {
+    limit: u64,
+    remaining: u64,
+    reset: u64,
+    allowed: bool,
+}
+
+
+/// limit 为0的规则无效，令牌桶无法补充令牌
+fn parse_rule(scope: String, setting: &Value) -> Option<RateLimitRule> {
+    let limit = setting.get("limit").and_then(|x| x.as_u64()).filter(|x| *x > 0)?;
+    let mode = setting.get("mode").and_then(|x| x.as_str()).unwrap_or("fixed_window").to_lowercase();
+    Some(RateLimitRule {
+        scope,
+        token_bucket: mode.contains("bucket"),
+        limit,
+        window: setting.get("window").and_then(|x| x.as_u64()).unwrap_or(60).max(1).min(MAX_WINDOW),
+        burst: setting.get("burst").and_then(|x| x.as_u64()).unwrap_or(limit).max(1),
+        key: setting.get("key").and_then(|x| x.as_str()).unwrap_or("ip").to_string(),
+        status: setting.get("status").and_then(|x| x.as_u64()).unwrap_or(429) as u16,
+        response: match setting.get("response") {
+            Some(v) => v.clone(),
+            None => json!({"code": -1, "msg": "too many requests"})
+        },
+    })
+}
+
+
+/// 找到请求对应的所有限流规则: 全局、接口文档、接口、权限组
+fn get_rules(req: &ServiceRequest, db: &db::Database) -> Vec<RateLimitRule> {
+    let req_path = req.path();
+    let req_method = req.method().as_str();
+
+    let mut rules = Vec::new();
+    let mut is_api_match = false;
+    for (api_url, a_api_data) in &db.api_data {
+        if ResourceDef::new(api_url).is_match(req_path) {
+            // SSE接口可以用任意方法请求，和 find_response_data 的匹配一致
+            if let Some(a) = a_api_data.get(req_method).or(a_api_data.get("SSE")).or(a_api_data.get("*")) {
+                let api = a.lock().unwrap();
+                rules.extend(parse_rule(format!("doc:{}", api.doc_file), &api.doc_rate_limit));
+                rules.extend(parse_rule(format!("api:{} {}", req_method, api.url), &api.rate_limit));
+                is_api_match = true;
+                break;
+            }
+        }
+    }
+    // 只对接口文档中的接口限流
+    if !is_api_match {
+        return Vec::new();
+    }
+
+    if let Some(setting) = db.settings.as_ref().and_then(|x| x.get("rate_limit")) {
+        if let Some(rule) = parse_rule("global".to_string(), setting) {
+            rules.insert(0, rule);
+        }
+    }
+
+    if let Some(auth_doc) = &db.auth_doc {
+        let token = auth::get_token_from_head(req.headers(), req.query_string(), auth_doc);
+        if let Some((_, group_name)) = auth::find_user_by_token(auth_doc, &token) {
+            if let Some(group) = auth_doc.groups.iter().find(|x| &x.name == group_name) {
+                rules.extend(parse_rule(format!("group:{}", group.name), &group.rate_limit));
+            }
+        }
+    }
+    rules
+}
+
+
+/// 按照规则的key获取计数的key，token模式下没有token的按ip计数
+fn get_counter_key(req: &ServiceRequest, rule: &RateLimitRule, db: &db::Database) -> String {
+    let ip = || -> String {
+        let conn = req.connection_info();
+        let addr = conn.remote().unwrap_or("");
+        // 去掉端口号
+        if addr.starts_with("[") {
+            return addr.split(']').next().unwrap_or("").trim_start_matches("[").to_string();
+        }
+        match addr.rfind(':') {
+            Some(i) if addr.matches(':').count() == 1 => addr[..i].to_string(),
+            _ => addr.to_string()
+        }
+    };
+
+    let key = if rule.key == "api" {
+        "".to_string()
+    } else if rule.key == "token" {
+        let token = match &db.auth_doc {
+            Some(auth_doc) => auth::get_token_from_head(req.headers(), req.query_string(), auth_doc),
+            None => "".to_string()
+        };
+        if token == "" { format!("ip:{}", ip()) } else { format!("token:{}", token) }
+    } else if rule.key.starts_with("header:") {
+        let header = rule.key.trim_start_matches("header:").trim();
+        match req.headers().get(header).and_then(|x| x.to_str().ok()) {
+            Some(v) => format!("header:{}", v),
+            None => format!("ip:{}", ip())
+        }
+    } else {
+        format!("ip:{}", ip())
+    };
+    format!("{}|{}", rule.scope, key)
+}
+
+
+/// 检查并计数一次请求
+fn check_rule(counter: &mut Counter, rule: &RateLimitRule, now: Instant) -> RateLimitState {
+    match counter {
+        Counter::FixedWindow { start, count } => {
+            let elapsed = now.duration_since(*start).as_secs();
+            if elapsed >= rule.window {
+                *start = now;
+                *count = 0;
+            }
+            let reset = rule.window - now.duration_since(*start).as_secs();
+            if *count >= rule.limit {
+                return RateLimitState { limit: rule.limit, remaining: 0, reset, allowed: false };
+            }
+            *count += 1;
+            RateLimitState { limit: rule.limit, remaining: rule.limit - *count, reset, allowed: true }
+        }
+        Counter::TokenBucket { tokens, last } => {
+            // 每秒补充 limit / window 个令牌
+            let rate = rule.limit as f64 / rule.window as f64;
+            let elapsed = now.duration_since(*last).as_secs_f64();
+            *tokens = (*tokens + elapsed * rate).min(rule.burst as f64);
+            *last = now;
+            if *tokens < 1.0 {
+                let reset = ((1.0 - *tokens) / rate).ceil() as u64;
+                return RateLimitState { limit: rule.burst, remaining: 0, reset: reset.max(1), allowed: false };
+            }
+            *tokens -= 1.0;
+            let reset = ((rule.burst as f64 - *tokens) / rate).ceil() as u64;
+            RateLimitState { limit: rule.burst, remaining: *tokens as u64, reset, allowed: true }
+        }
+    }
+}
+
+
+/// 计数在什么时间之后和新建的一样: 固定窗口在窗口结束后，令牌桶在令牌补满后
+fn get_counter_expires_at(counter: &Counter, rule: &RateLimitRule) -> Instant {
+    match counter {
+        Counter::FixedWindow { start, .. } => *start + Duration::from_secs(rule.window),
+        Counter::TokenBucket { tokens, last } => {
+            let rate = rule.limit as f64 / rule.window as f64;
+            let secs = ((rule.burst as f64 - *tokens).max(0.0) / rate).ceil().min(MAX_WINDOW as f64);
+            *last + Duration::from_secs_f64(secs)
+        }
+    }
+}
+
+
+fn set_rate_limit_headers(headers: &mut actix_web::http::HeaderMap, state: &RateLimitState) {
+    let items = [
+        ("x-ratelimit-limit", state.limit),
+        ("x-ratelimit-remaining", state.remaining),
+        ("x-ratelimit-reset", state.reset),
+    ];
+    for (name, value) in items.iter() {
+        headers.insert(HeaderName::from_static(*name), HeaderValue::from(*value));
+    }
+}
+
+
+/// 限流中间件，计数保存在内存中，所有worker共用
+pub struct RateLimit {
+    db: web::Data<Mutex<db::Database>>,
+    counters: Arc<Mutex<Counters>>,
+}
+
+
+impl RateLimit {
+    pub fn new(db: web::Data<Mutex<db::Database>>) -> Self {
+        RateLimit { db, counters: Arc::new(Mutex::new(Counters::new())) }
+    }
+}
+
+
+impl Clone for RateLimit {
+    fn clone(&self) -> Self {
+        RateLimit { db: self.db.clone(), counters: self.counters.clone() }
+    }
+}
+
+
+impl<S, B> Transform<S> for RateLimit
+    where
+        S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S::Future: 'static,
+        B: actix_web::body::MessageBody + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<Body>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RateLimitMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RateLimitMiddleware { service, db: self.db.clone(), counters: self.counters.clone() })
+    }
+}
+
+
+pub struct RateLimitMiddleware<S> {
+    service: S,
+    db: web::Data<Mutex<db::Database>>,
+    counters: Arc<Mutex<Counters>>,
+}
+
+
+impl<S, B> Service for RateLimitMiddleware<S>
+    where
+        S: Service<Request=ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S::Future: 'static,
+        B: actix_web::body::MessageBody + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<Body>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output=Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        // 剩余次数最少的规则用于返回 X-RateLimit-* header
+        let mut header_state: Option<RateLimitState> = None;
+        let mut limited: Option<(RateLimitRule, RateLimitState)> = None;
+        {
+            let db = self.db.lock().unwrap();
+            let rules = get_rules(&req, &db);
+            if !rules.is_empty() {
+                let now = Instant::now();
+                let mut counters = self.counters.lock().unwrap();
+                counters.prune(now);
+                // 先检查所有的规则，全部通过后再保存计数，被拒绝的请求不占用其他规则的次数
+                let mut updated = Vec::new();
+                for rule in rules {
+                    let key = get_counter_key(&req, &rule, &db);
+                    let mut counter = match counters.items.get(&key) {
+                        Some((v, _)) => v.clone(),
+                        None => if rule.token_bucket {
+                            Counter::TokenBucket { tokens: rule.burst as f64, last: now }
+                        } else {
+                            Counter::FixedWindow { start: now, count: 0 }
+                        }
+                    };
+                    let state = check_rule(&mut counter, &rule, now);
+                    if !state.allowed {
+                        limited = Some((rule, state));
+                        break;
+                    }
+                    let is_less = match &header_state {
+                        Some(v) => state.remaining < v.remaining,
+                        None => true
+                    };
+                    if is_less {
+                        header_state = Some(state);
+                    }
+                    let expires_at = get_counter_expires_at(&counter, &rule);
+                    updated.push((key, counter, expires_at));
+                }
+                if limited.is_none() {
+                    for (key, counter, expires_at) in updated {
+                        counters.items.insert(key, (counter, expires_at));
+                    }
+                }
+            }
+        }
+
+        if let Some((rule, state)) = limited {
+            let status = StatusCode::from_u16(rule.status).unwrap_or(StatusCode::TOO_MANY_REQUESTS);
+            let mut response = HttpResponse::build(status)
+                .header("Retry-After", state.reset.to_string())
+                .json(&rule.response);
+            set_rate_limit_headers(response.headers_mut(), &state);
+            return Box::pin(async move {
+                Ok(req.into_response(response))
+            });
+        }
+
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let mut res = fut.await?;
+            if let Some(state) = header_state {
+                set_rate_limit_headers(res.headers_mut(), &state);
+            }
+            Ok(res.map_body(|_, body| ResponseBody::Other(Body::Message(Box::new(body)))))
+        })
+    }
+}
@@ -0,0 +1,344 @@
+//! websocket、SSE接口的测试客户端
+//! 按照接口文档中的test_data，连接到真实的后端，发送消息并校验返回的消息顺序、内容和时间
+
+use std::time::{Duration, Instant};
+
+use awc::ws;
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+
+use crate::api;
+use crate::db;
+
+
+/// 一个测试场景的结果
+struct ScenarioResult {
+    kind: &'static str,
+    url: String,
+    name: String,
+    error: Option<String>,
+    elapsed: Duration,
+}
+
+
+/// 把收到的文本转换为json，不是json的按字符串处理
+fn parse_message(text: &str) -> Value {
+    match serde_json::from_str(text) {
+        Ok(v) => v,
+        Err(_) => Value::String(text.to_string())
+    }
+}
+
+
+fn get_test_case_name(test_case_data: &Value, index: usize) -> String {
+    match test_case_data.get("name").and_then(|x| x.as_str()) {
+        Some(v) => v.to_string(),
+        None => format!("case {}", index + 1)
+    }
+}
+
+
+/// 获取test_data中的列表设置，如 messages 或者 message
+fn get_list(test_case_data: &Value, list_key: &str, key: &str) -> Vec<Value> {
+    if let Some(v) = test_case_data.get(list_key).and_then(|x| x.as_array()) {
+        return v.clone();
+    }
+    match test_case_data.get(key) {
+        Some(v) => vec![v.clone()],
+        None => Vec::new()
+    }
+}
+
+
+/// 测试请求的地址，接口地址中有路由参数的时候，使用test_data中的 url，或者用 path_params 替换，如 /ws/room/{id}/
+fn get_case_url(api_url: &str, test_case_data: &Value) -> Result<String, String> {
+    if let Some(url) = test_case_data.get("url").and_then(|x| x.as_str()) {
+        return Ok(url.to_string());
+    }
+    let mut url = api_url.to_string();
+    if let Some(params) = test_case_data.get("path_params").and_then(|x| x.as_object()) {
+        for (k, v) in params {
+            let v = match v.as_str() {
+                Some(s) => s.to_string(),
+                None => v.to_string()
+            };
+            url = url.replace(&format!("{{{}}}", k), &v);
+        }
+    }
+    if url.contains("{") {
+        return Err(format!("api url {} has route params, set url or path_params in the test case", url));
+    }
+    Ok(url)
+}
+
+
+fn add_query(url: &str, key: &str, value: &str) -> String {
+    format!("{}{}{}={}", url, if url.contains("?") { "&" } else { "?" }, key, value)
+}
+
+
+/// 按照 _auth.json5 的 auth_place、auth_key、auth_scheme 放token，和 auth::get_credential 读取的位置一致
+/// 没有 _auth.json5 的时候使用 Authorization: Bearer
+fn add_credential(mut request: awc::ClientRequest, token: &str, auth_doc: &Option<db::AuthDoc>) -> (awc::ClientRequest, Option<(String, String)>) {
+    let (place, key, scheme) = match auth_doc {
+        Some(v) => (v.auth_place.as_str(), v.auth_key.as_str(), v.auth_scheme.as_str()),
+        None => ("headers", "Authorization", "Bearer")
+    };
+    let mut form = None;
+    match place {
+        "query" => {
+            let url = add_query(&request.get_uri().to_string(), key, token);
+            request = request.uri(url.as_str());
+        }
+        "cookie" => request = request.header("Cookie", format!("{}={}", key, token)),
+        "form" => form = Some((key.to_string(), token.to_string())),
+        _ => {
+            let value = if scheme != "" { format!("{} {}", scheme, token) } else { token.to_string() };
+            request = request.header(key, value);
+        }
+    }
+    (request, form)
+}
+
+
+/// 把 http 地址转换为 websocket 地址
+fn to_ws_url(target: &str) -> String {
+    if target.starts_with("https://") {
+        target.replacen("https://", "wss://", 1)
+    } else if target.starts_with("http://") {
+        target.replacen("http://", "ws://", 1)
+    } else {
+        target.to_string()
+    }
+}
+
+
+/// 测试一个websocket场景: 发送 message/messages，按顺序校验 response/responses
+/// timeout 为等待每条消息的最长毫秒数，min_delay 为第一条回复最少需要等待的毫秒数
+/// 浏览器的websocket不能设置header，token放在chat设置的 token_param 参数中，默认为 token
+async fn run_websocket_case(url: &str, token: &Option<String>, token_param: &str, test_case_data: &Value) -> Result<(), String> {
+    let mut url = url.to_string();
+    if let Some(token) = token {
+        url = add_query(&url, token_param, token);
+    }
+    let (_, mut framed) = awc::Client::new()
+        .ws(url.as_str())
+        .connect()
+        .await
+        .map_err(|e| format!("connect error: {}", e))?;
+
+    let timeout = test_case_data.get("timeout").and_then(|x| x.as_u64()).unwrap_or(3000);
+    let min_delay = test_case_data.get("min_delay").and_then(|x| x.as_u64()).unwrap_or(0);
+
+    let start = Instant::now();
+    for message in get_list(test_case_data, "messages", "message") {
+        let text = match message {
+            Value::String(v) => v,
+            v => v.to_string()
+        };
+        framed.send(ws::Message::Text(text)).await.map_err(|e| format!("send error: {}", e))?;
+    }
+
+    for (i, expected) in get_list(test_case_data, "responses", "response").iter().enumerate() {
+        let received = loop {
+            let frame = match actix_rt::time::timeout(Duration::from_millis(timeout), framed.next()).await {
+                Ok(Some(Ok(frame))) => frame,
+                Ok(Some(Err(e))) => return Err(format!("receive error: {}", e)),
+                Ok(None) => return Err(format!("connection closed before message {}", i + 1)),
+                Err(_) => return Err(format!("message {} not received in {}ms", i + 1, timeout))
+            };
+            match frame {
+                ws::Frame::Text(bytes) => break parse_message(&String::from_utf8_lossy(&bytes)),
+                ws::Frame::Ping(bytes) => {
+                    let _ = framed.send(ws::Message::Pong(bytes)).await;
+                }
+                ws::Frame::Close(_) => return Err(format!("connection closed before message {}", i + 1)),
+                _ => ()
+            }
+        };
+
+        if i == 0 && (start.elapsed().as_millis() as u64) < min_delay {
+            return Err(format!("first message received in {}ms, expected at least {}ms", start.elapsed().as_millis(), min_delay));
+        }
+        if !api::is_value_equal(&received, expected) {
+            return Err(format!("message {} expected {} but received {}", i + 1, expected, received));
+        }
+    }
+
+    let _ = framed.send(ws::Message::Close(None)).await;
+    Ok(())
+}
+
+
+/// 把SSE的一个事件块解析为 {event, id, data}
+fn parse_sse_event(block: &str) -> Value {
+    let mut event = json!({});
+    let mut data: Vec<&str> = Vec::new();
+    for line in block.lines() {
+        let mut parts = line.splitn(2, ':');
+        let key = parts.next().unwrap_or("");
+        let value = parts.next().unwrap_or("").trim_start();
+        match key {
+            "data" => data.push(value),
+            "event" | "id" | "retry" => event[key] = json!(value),
+            _ => ()
+        }
+    }
+    event["data"] = parse_message(&data.join("\n"));
+    event
+}
+
+
+/// 判断收到的事件是否和期望的一致，期望中设置了的 event、id、data 才校验
+fn is_sse_event_match(received: &Value, expected: &Value) -> Result<(), String> {
+    for key in ["event", "id"].iter() {
+        if let Some(v) = expected.get(*key) {
+            let v = match v.as_str() {
+                Some(s) => s.to_string(),
+                None => v.to_string()
+            };
+            if received.get(*key).and_then(|x| x.as_str()) != Some(v.as_str()) {
+                return Err(format!("{} expected {} but received {}", key, v, received.get(*key).unwrap_or(&Value::Null)));
+            }
+        }
+    }
+    if let Some(v) = expected.get("data") {
+        let received_data = received.get("data").unwrap_or(&Value::Null);
+        if !api::is_value_equal(received_data, v) {
+            return Err(format!("data expected {} but received {}", v, received_data));
+        }
+    }
+    Ok(())
+}
+
+
+/// 测试一个SSE场景: 请求接口，按顺序校验 events 和 end 事件
+async fn run_sse_case(url: &str, method: &str, token: &Option<String>, auth_doc: &Option<db::AuthDoc>, test_case_data: &Value) -> Result<(), String> {
+    let mut url = url.to_string();
+    if let Some(query) = test_case_data.get("query").and_then(|x| x.as_object()) {
+        let query: Vec<String> = query.iter().map(|(k, v)| {
+            match v.as_str() {
+                Some(s) => format!("{}={}", k, s),
+                None => format!("{}={}", k, v)
+            }
+        }).collect();
+        url = format!("{}?{}", url, query.join("&"));
+    }
+
+    // 用测试数据的 method 和 body 请求，和 mock 服务匹配 test_data 的方式一样
+    let method = test_case_data.get("method").and_then(|x| x.as_str()).unwrap_or(method);
+    let method = awc::http::Method::from_bytes(method.as_bytes()).map_err(|e| format!("method {} error: {}", method, e))?;
+    let mut request = awc::Client::new().request(method, url.as_str()).header("Accept", "text/event-stream");
+    let mut form = None;
+    if let Some(token) = token {
+        let (r, f) = add_credential(request, token, auth_doc);
+        request = r;
+        form = f;
+    }
+    let body = test_case_data.get("body").filter(|x| !x.is_null());
+    let mut response = match (form, body) {
+        (Some((key, token)), body) => {
+            // token 放在表单中的时候，body 的字段也作为表单字段
+            let mut fields: Vec<(String, String)> = match body.and_then(|x| x.as_object()) {
+                Some(obj) => obj.iter().map(|(k, v)| (k.to_string(), v.as_str().map(|s| s.to_string()).unwrap_or(v.to_string()))).collect(),
+                None => Vec::new()
+            };
+            fields.push((key, token));
+            request.send_form(&fields).await
+        }
+        (None, Some(body)) => request.send_json(body).await,
+        (None, None) => request.send().await
+    }.map_err(|e| format!("request error: {}", e))?;
+
+    let timeout = test_case_data.get("timeout").and_then(|x| x.as_u64()).unwrap_or(3000);
+    let mut expected_events = get_list(test_case_data, "events", "response");
+    if let Some(end) = test_case_data.get("end") {
+        if !end.is_null() && end.as_bool() != Some(false) {
+            expected_events.push(if end.is_object() { end.clone() } else { json!({"data": end}) });
+        }
+    }
+
+    let mut buffer = String::new();
+    for (i, expected) in expected_events.iter().enumerate() {
+        let received = loop {
+            if let Some(pos) = buffer.find("\n\n") {
+                let block = buffer[..pos].to_string();
+                buffer = buffer[pos + 2..].to_string();
+                break parse_sse_event(&block);
+            }
+            match actix_rt::time::timeout(Duration::from_millis(timeout), response.next()).await {
+                Ok(Some(Ok(bytes))) => buffer.push_str(&String::from_utf8_lossy(&bytes).replace("\r\n", "\n")),
+                Ok(Some(Err(e))) => return Err(format!("receive error: {}", e)),
+                Ok(None) => return Err(format!("stream ended before event {}", i + 1)),
+                Err(_) => return Err(format!("event {} not received in {}ms", i + 1, timeout))
+            }
+        };
+        is_sse_event_match(&received, expected).map_err(|e| format!("event {} {}", i + 1, e))?;
+    }
+    Ok(())
+}
+
+
+/// 运行所有websocket、SSE接口的测试，全部通过返回true
+pub async fn run_realtime_tests(target: &str, token: Option<String>) -> bool {
+    let target = target.trim_end_matches("/");
+    let db = db::Database::load();
+
+    // (类型, 接口地址, test_data, websocket的token参数, SSE默认的请求方法)
+    let mut apis = Vec::new();
+    for (api_url, a_api_data) in &db.api_data {
+        if let Some(a) = a_api_data.get("WEBSOCKET") {
+            let a = a.lock().unwrap();
+            let token_param = a.chat.get("token_param").and_then(|x| x.as_str()).unwrap_or("token").to_string();
+            apis.push(("ws", api_url.to_string(), a.test_data.clone(), token_param, "GET".to_string()));
+        }
+        // 和 find_response_data 一样判断事件流接口，一个接口有多个方法时只测试一次
+        let mut methods: Vec<&String> = a_api_data.keys().filter(|x| x.as_str() != "WEBSOCKET").collect();
+        methods.sort();
+        for method in methods {
+            let a = a_api_data[method].lock().unwrap();
+            if api::is_sse_api(&a) {
+                let method = if method == "SSE" || method == "*" { "GET" } else { method.as_str() };
+                apis.push(("sse", api_url.to_string(), a.test_data.clone(), "".to_string(), method.to_string()));
+                break;
+            }
+        }
+    }
+    apis.sort_by(|a, b| a.1.cmp(&b.1));
+
+    let mut results = Vec::new();
+    for (kind, api_url, test_data, token_param, method) in apis {
+        let test_data = match test_data.as_array() {
+            Some(v) => v.clone(),
+            None => continue
+        };
+        for (i, test_case_data) in test_data.iter().enumerate() {
+            let token = match test_case_data.get("token").and_then(|x| x.as_str()) {
+                Some(v) => Some(v.to_string()),
+                None => token.clone()
+            };
+            if kind == "ws" && test_case_data.get("message").is_none() && test_case_data.get("messages").is_none() {
+                continue;
+            }
+            if kind == "sse" && test_case_data.get("events").is_none() && test_case_data.get("response").is_none() {
+                continue;
+            }
+            let start = Instant::now();
+            let result = match get_case_url(&api_url, test_case_data) {
+                Ok(url) if kind == "ws" => run_websocket_case(&format!("{}{}", to_ws_url(target), url), &token, &token_param, test_case_data).await,
+                Ok(url) => run_sse_case(&format!("{}{}", target, url), &method, &token, &db.auth_doc, test_case_data).await,
+                Err(e) => Err(e)
+            };
+            let r = ScenarioResult { kind, url: api_url.clone(), name: get_test_case_name(test_case_data, i), error: result.err(), elapsed: start.elapsed() };
+            match &r.error {
+                None => println!("PASS {} {} {} ({}ms)", r.kind, r.url, r.name, r.elapsed.as_millis()),
+                Some(e) => println!("FAIL {} {} {} ({}ms): {}", r.kind, r.url, r.name, r.elapsed.as_millis(), e)
+            }
+            results.push(r);
+        }
+    }
+
+    let failed = results.iter().filter(|x| x.error.is_some()).count();
+    println!("{} scenarios, {} passed, {} failed", results.len(), results.len() - failed, failed);
+    failed == 0
+}
@@ -1,10 +1,11 @@
 //! `ChatServer` is an actor. It maintains list of connection client session.
 //! And manages available rooms. Peers send messages to other peers in same
-//! room through `ChatServer`.
+//! room through `ChatServer`. Every websocket api has its own rooms.
+//! Events sent to peers are json: join, leave, presence, message and history.
 
 use actix::prelude::*;
-use rand::{self, rngs::ThreadRng, Rng};
-use std::collections::{HashMap, HashSet};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet, VecDeque};
 
 /// Chat server sends this messages to session
 #[derive(Message)]
@@ -18,6 +19,12 @@ pub struct Message(pub String);
 #[rtype(usize)]
 pub struct Connect {
     pub addr: Recipient<Message>,
+    /// websocket api url the session connected to
+    pub route: String,
+    /// peer name, the username of the authenticated user
+    pub name: String,
+    /// authenticated user in _auth.json5, null when not authenticated
+    pub user: Value,
 }
 
 /// Session is disconnected
@@ -27,6 +34,15 @@ pub struct Disconnect {
     pub id: usize,
 }
 
+/// Session is authenticated after connected, with the first message
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Identify {
+    pub id: usize,
+    pub name: String,
+    pub user: Value,
+}
+
 /// Send message to specific room
 #[derive(Message)]
 #[rtype(result = "()")]
@@ -39,13 +55,39 @@ pub struct ClientMessage {
     pub room: String,
 }
 
-/// List of available rooms
-pub struct ListRooms;
+/// Send message to sessions of a websocket api, or of a room when room is set.
+/// Returns how many sessions received the message
+#[derive(Message)]
+#[rtype(usize)]
+pub struct Inject {
+    /// websocket api url, empty means all websocket apis
+    pub route: String,
+    /// room name, empty means all rooms
+    pub room: String,
+    pub msg: String,
+}
+
+/// List of available rooms of a websocket api
+pub struct ListRooms {
+    /// websocket api url
+    pub route: String,
+}
 
 impl actix::Message for ListRooms {
     type Result = Vec<String>;
 }
 
+/// Members of a room of a websocket api
+pub struct Members {
+    /// websocket api url
+    pub route: String,
+    pub room: String,
+}
+
+impl actix::Message for Members {
+    type Result = Vec<Value>;
+}
+
 /// Join room, if room does not exists create new one.
 #[derive(Message)]
 #[rtype(result = "()")]
@@ -54,43 +96,129 @@ pub struct Join {
     pub id: usize,
     /// Room name
     pub name: String,
+    /// How many history messages are replayed to the client
+    pub history: usize,
+}
+
+/// Leave room
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Leave {
+    pub id: usize,
+    pub name: String,
+}
+
+/// A connected session
+struct SessionInfo {
+    addr: Recipient<Message>,
+    route: String,
+    name: String,
+    user: Value,
+}
+
+/// Max history messages kept for every room
+const MAX_HISTORY: usize = 100;
+
+/// Rooms of different websocket apis never share members or history,
+/// they are kept under `route#room`
+fn room_key(route: &str, room: &str) -> String {
+    format!("{}#{}", route, room)
+}
+
+/// Room name of a room key, as clients see it
+fn room_name(key: &str) -> &str {
+    key.splitn(2, '#').nth(1).unwrap_or(key)
 }
 
 /// `ChatServer` manages chat rooms and responsible for coordinating chat
 /// session. implementation is super primitive
 pub struct ChatServer {
-    sessions: HashMap<usize, Recipient<Message>>,
+    sessions: HashMap<usize, SessionInfo>,
+    /// room key to the sessions in the room
     rooms: HashMap<String, HashSet<usize>>,
-    rng: ThreadRng,
+    history: HashMap<String, VecDeque<String>>,
+    next_id: usize,
 }
 
 impl Default for ChatServer {
     fn default() -> ChatServer {
-        // default room
-        let mut rooms = HashMap::new();
-        rooms.insert("Main".to_owned(), HashSet::new());
-
         ChatServer {
             sessions: HashMap::new(),
-            rooms,
-            rng: rand::thread_rng(),
+            rooms: HashMap::new(),
+            history: HashMap::new(),
+            next_id: 1,
         }
     }
 }
 
 impl ChatServer {
+    /// Key of a room in the websocket api of the session
+    fn session_room(&self, id: usize, room: &str) -> String {
+        let route = self.sessions.get(&id).map(|x| x.route.as_str()).unwrap_or("");
+        room_key(route, room)
+    }
+
     /// Send message to all users in the room
     fn send_message(&self, room: &str, message: &str, skip_id: usize) {
         if let Some(sessions) = self.rooms.get(room) {
             for id in sessions {
                 if *id != skip_id {
-                    if let Some(addr) = self.sessions.get(id) {
-                        let _ = addr.do_send(Message(message.to_owned()));
+                    if let Some(session) = self.sessions.get(id) {
+                        let _ = session.addr.do_send(Message(message.to_owned()));
                     }
                 }
             }
         }
     }
+
+    /// Send message to one session
+    fn send_to(&self, id: usize, message: &str) {
+        if let Some(session) = self.sessions.get(&id) {
+            let _ = session.addr.do_send(Message(message.to_owned()));
+        }
+    }
+
+    /// Public info of a session, password and token of the user are removed
+    fn member(&self, id: usize) -> Value {
+        match self.sessions.get(&id) {
+            Some(session) => {
+                let mut user = session.user.clone();
+                if let Some(user) = user.as_object_mut() {
+                    user.remove("password");
+                    user.remove("token");
+                }
+                json!({"id": id, "name": session.name, "user": user})
+            }
+            None => json!({"id": id})
+        }
+    }
+
+    fn members(&self, room: &str) -> Vec<Value> {
+        let mut ids: Vec<usize> = match self.rooms.get(room) {
+            Some(sessions) => sessions.iter().cloned().collect(),
+            None => Vec::new(),
+        };
+        ids.sort();
+        ids.into_iter().map(|id| self.member(id)).collect()
+    }
+
+    /// Send join or leave event and the new member list of the room
+    fn send_presence(&self, room: &str, event: &str, id: usize) {
+        let event = json!({"type": event, "room": room_name(room), "member": self.member(id)});
+        self.send_message(room, &event.to_string(), id);
+        let presence = json!({"type": "presence", "room": room_name(room), "members": self.members(room)});
+        self.send_message(room, &presence.to_string(), 0);
+    }
+
+    fn leave(&mut self, id: usize, room: &str) {
+        let removed = match self.rooms.get_mut(room) {
+            Some(sessions) => sessions.remove(&id),
+            None => false,
+        };
+        if removed {
+            self.send_presence(room, "leave", id);
+        }
+    }
 }
 
 /// Make actor from `ChatServer`
@@ -107,17 +235,12 @@ impl Handler<Connect> for ChatServer {
     type Result = usize;
 
     fn handle(&mut self, msg: Connect, _: &mut Context<Self>) -> Self::Result {
-        println!("Someone joined");
-
-        // notify all users in same room
-        self.send_message(&"Main".to_owned(), "Someone joined", 0);
+        let id = self.next_id;
+        self.next_id += 1;
+        println!("websocket session {} connected to {}", id, msg.route);
 
-        // register session with random id
-        let id = self.rng.gen::<usize>();
-        self.sessions.insert(id, msg.addr);
-
-        // auto join session to Main room
-        self.rooms.get_mut(&"Main".to_owned()).unwrap().insert(id);
+        let name = if msg.name == "" { format!("guest{}", id) } else { msg.name };
+        self.sessions.insert(id, SessionInfo { addr: msg.addr, route: msg.route, name, user: msg.user });
 
         // send id back
         id
@@ -129,22 +252,30 @@ impl Handler<Disconnect> for ChatServer {
     type Result = ();
 
     fn handle(&mut self, msg: Disconnect, _: &mut Context<Self>) {
-        println!("Someone disconnected");
+        println!("websocket session {} disconnected", msg.id);
 
-        let mut rooms: Vec<String> = Vec::new();
+        let rooms: Vec<String> = self.rooms.iter()
+            .filter(|(_, sessions)| sessions.contains(&msg.id))
+            .map(|(name, _)| name.to_owned())
+            .collect();
+        // send leave event to other users, then remove the session
+        for room in rooms {
+            self.leave(msg.id, &room);
+        }
+        self.sessions.remove(&msg.id);
+    }
+}
 
-        // remove address
-        if self.sessions.remove(&msg.id).is_some() {
-            // remove session from all rooms
-            for (name, sessions) in &mut self.rooms {
-                if sessions.remove(&msg.id) {
-                    rooms.push(name.to_owned());
-                }
+/// Handler for Identify message.
+impl Handler<Identify> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Identify, _: &mut Context<Self>) {
+        if let Some(session) = self.sessions.get_mut(&msg.id) {
+            if msg.name != "" {
+                session.name = msg.name;
             }
-        }
-        // send message to other users
-        for room in rooms {
-            self.send_message(&room, "Someone disconnected", 0);
+            session.user = msg.user;
         }
     }
 }
@@ -154,7 +285,50 @@ impl Handler<ClientMessage> for ChatServer {
     type Result = ();
 
     fn handle(&mut self, msg: ClientMessage, _: &mut Context<Self>) {
-        self.send_message(&msg.room, msg.msg.as_str(), msg.id);
+        // only members of the room can send message to it
+        let key = self.session_room(msg.id, &msg.room);
+        if !self.rooms.get(&key).map_or(false, |x| x.contains(&msg.id)) {
+            let error = json!({"type": "error", "room": msg.room, "msg": format!("not joined room {}", msg.room)});
+            self.send_to(msg.id, &error.to_string());
+            return;
+        }
+
+        // message from client is json or plain text
+        let data: Value = match serde_json::from_str(&msg.msg) {
+            Ok(v) => v,
+            Err(_) => Value::String(msg.msg.clone()),
+        };
+        let event = json!({"type": "message", "room": msg.room, "from": self.member(msg.id), "data": data}).to_string();
+
+        let history = self.history.entry(key.clone()).or_insert_with(VecDeque::new);
+        history.push_back(event.clone());
+        if history.len() > MAX_HISTORY {
+            history.pop_front();
+        }
+
+        self.send_message(&key, &event, msg.id);
+    }
+}
+
+/// Handler for Inject message.
+impl Handler<Inject> for ChatServer {
+    type Result = usize;
+
+    fn handle(&mut self, msg: Inject, _: &mut Context<Self>) -> Self::Result {
+        let mut count = 0;
+        for (id, session) in &self.sessions {
+            if msg.route != "" && session.route != msg.route {
+                continue;
+            }
+            // the room of the websocket api the session connected to
+            if msg.room != "" && !self.rooms.get(&room_key(&session.route, &msg.room)).map_or(false, |x| x.contains(id)) {
+                continue;
+            }
+            if session.addr.do_send(Message(msg.msg.clone())).is_ok() {
+                count += 1;
+            }
+        }
+        count
     }
 }
 
@@ -162,41 +336,66 @@ impl Handler<ClientMessage> for ChatServer {
 impl Handler<ListRooms> for ChatServer {
     type Result = MessageResult<ListRooms>;
 
-    fn handle(&mut self, _: ListRooms, _: &mut Context<Self>) -> Self::Result {
+    fn handle(&mut self, msg: ListRooms, _: &mut Context<Self>) -> Self::Result {
         let mut rooms = Vec::new();
+        let prefix = room_key(&msg.route, "");
 
-        for key in self.rooms.keys() {
-            rooms.push(key.to_owned())
+        for (key, sessions) in &self.rooms {
+            if !sessions.is_empty() && key.starts_with(&prefix) {
+                rooms.push(room_name(key).to_owned())
+            }
         }
+        rooms.sort();
 
         MessageResult(rooms)
     }
 }
 
-/// Join room, send disconnect message to old room
-/// send join message to new room
+/// Handler for `Members` message.
+impl Handler<Members> for ChatServer {
+    type Result = MessageResult<Members>;
+
+    fn handle(&mut self, msg: Members, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.members(&room_key(&msg.route, &msg.room)))
+    }
+}
+
+/// Join room, replay history to the new member
+/// send join event and member list to the room
 impl Handler<Join> for ChatServer {
     type Result = ();
 
     fn handle(&mut self, msg: Join, _: &mut Context<Self>) {
-        let Join { id, name } = msg;
-        let mut rooms = Vec::new();
+        let Join { id, name, history } = msg;
+        let key = self.session_room(id, &name);
 
-        // remove session from all rooms
-        for (n, sessions) in &mut self.rooms {
-            if sessions.remove(&id) {
-                rooms.push(n.to_owned());
-            }
-        }
-        // send message to other users
-        for room in rooms {
-            self.send_message(&room, "Someone disconnected", 0);
+        let sessions = self.rooms.entry(key.clone()).or_insert_with(HashSet::new);
+        if !sessions.insert(id) {
+            return;
         }
 
-        if self.rooms.get_mut(&name).is_none() {
-            self.rooms.insert(name.clone(), HashSet::new());
+        if history > 0 {
+            if let Some(messages) = self.history.get(&key) {
+                let skip = messages.len().saturating_sub(history);
+                let messages: Vec<Value> = messages.iter().skip(skip)
+                    .filter_map(|x| serde_json::from_str(x).ok())
+                    .collect();
+                let event = json!({"type": "history", "room": name, "messages": messages});
+                self.send_to(id, &event.to_string());
+            }
         }
-        self.send_message(&name, "Someone connected", id);
-        self.rooms.get_mut(&name).unwrap().insert(id);
+
+        self.send_to(id, &json!({"type": "joined", "room": name, "members": self.members(&key)}).to_string());
+        self.send_presence(&key, "join", id);
+    }
+}
+
+/// Handler for Leave message.
+impl Handler<Leave> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Leave, _: &mut Context<Self>) {
+        let key = self.session_room(msg.id, &msg.name);
+        self.leave(msg.id, &key);
     }
 }
@@ -0,0 +1,102 @@
+//! 有状态的接口数据，接口设置了 store 后，数据保存在内存中，按照当前用户、权限组或者全局分开保存
+//! POST 新增，GET 查询列表或者单个，PUT 替换，PATCH 修改部分字段，DELETE 删除
+//! store: {name: "orders", scope: "user", key: "id", data: [{id: 1, title: "first order"}]}
+//! 重启服务或者重新加载 _settings.json5 后数据重置为 data
+
+use actix_web::dev::{Path, ResourceDef};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+use crate::auth;
+
+
+/// {store名称: {用户或者权限组: 数据列表}}
+pub type Stores = HashMap<String, HashMap<String, Vec<Value>>>;
+
+
+fn value_to_string(v: &Value) -> String {
+    match v.as_str() {
+        Some(s) => s.to_string(),
+        None => v.to_string()
+    }
+}
+
+
+/// 数据属于哪个用户或者权限组，scope 为 user、group、global，默认为 user，没有登录的请求共用一份数据
+fn get_owner(setting: &Value, current_user: Option<&(String, Value, String)>) -> String {
+    let scope = setting.get("scope").and_then(|x| x.as_str()).unwrap_or("user");
+    match (scope, current_user) {
+        ("global", _) | (_, None) => "".to_string(),
+        ("group", Some((_, _, group))) => format!("group:{}", group),
+        (_, Some((_, user, _))) => format!("user:{}", auth::get_user_subject(user)),
+    }
+}
+
+
+/// 新增的数据没有设置key的时候，使用当前最大的数字加1
+fn next_key(items: &Vec<Value>, key: &str) -> Value {
+    let max = items.iter().filter_map(|x| x.get(key).and_then(|v| v.as_i64())).max().unwrap_or(0);
+    json!(max + 1)
+}
+
+
+/// 处理设置了 store 的接口请求，返回 {code: 1, data: ...}，数据不存在时返回 {code: -1, msg: ...}
+/// api_url 中和 key 同名的路由参数为数据的key，如 /orders/{id}/
+pub fn handle(setting: &Value, api_url: &str, req_path: &str, req_method: &str, request_body: &Value, current_user: Option<&(String, Value, String)>, stores: &mut Stores) -> Value {
+    let name = setting.get("name").and_then(|x| x.as_str()).unwrap_or(api_url);
+    let key = setting.get("key").and_then(|x| x.as_str()).unwrap_or("id");
+    let owner = get_owner(setting, current_user);
+
+    let items = stores.entry(name.to_string()).or_insert_with(HashMap::new)
+        .entry(owner)
+        .or_insert_with(|| setting.get("data").and_then(|x| x.as_array()).cloned().unwrap_or_default());
+
+    let mut path = Path::new(req_path.to_string());
+    let id = if ResourceDef::new(api_url).match_path(&mut path) {
+        path.get(key).map(|x| x.to_string())
+    } else {
+        None
+    };
+    let index = id.as_ref().and_then(|id| items.iter().position(|x| x.get(key).map(value_to_string).as_ref() == Some(id)));
+
+    match (req_method, &id, index) {
+        ("GET", None, _) => json!({"code": 1, "data": items}),
+        ("POST", _, _) => {
+            let mut item = match request_body.as_object() {
+                Some(v) => v.clone(),
+                None => return json!({"code": -1, "msg": format!("{} data must be an object", name)})
+            };
+            if !item.contains_key(key) {
+                item.insert(key.to_string(), next_key(items, key));
+            } else if items.iter().any(|x| x.get(key) == item.get(key)) {
+                return json!({"code": -1, "msg": format!("{} {} already exists", name, value_to_string(&item[key]))});
+            }
+            let item = Value::Object(item);
+            items.push(item.clone());
+            json!({"code": 1, "data": item})
+        }
+        (_, None, _) => json!({"code": -1, "msg": format!("{} api url {} has no {{{}}} param", name, api_url, key)}),
+        (_, Some(id), None) => json!({"code": -1, "msg": format!("{} {} not found", name, id)}),
+        ("GET", _, Some(i)) => json!({"code": 1, "data": items[i]}),
+        ("PUT", _, Some(i)) | ("PATCH", _, Some(i)) => {
+            let fields = match request_body.as_object() {
+                Some(v) => v,
+                None => return json!({"code": -1, "msg": format!("{} data must be an object", name)})
+            };
+            let old_key = items[i][key].clone();
+            if req_method == "PUT" {
+                items[i] = json!({});
+            }
+            if let Some(item) = items[i].as_object_mut() {
+                for (k, v) in fields {
+                    item.insert(k.to_string(), v.clone());
+                }
+                // key 不能修改
+                item.insert(key.to_string(), old_key);
+            }
+            json!({"code": 1, "data": items[i]})
+        }
+        ("DELETE", _, Some(i)) => json!({"code": 1, "data": items.remove(i)}),
+        _ => json!({"code": -1, "msg": format!("{} does not support method {}", name, req_method)})
+    }
+}
@@ -0,0 +1,263 @@
+//! multipart 表单和文件上传
+//! 上传的文件保存在 _data/_upload/ 下，文件名为唯一id加上处理过的原文件名，不会覆盖已有的文件
+//! _settings.json5 中的 upload 为全局限制: {max_size: 10485760, max_files: 10, max_field_size: 1048576, retention: 86400}
+//! 接口body中 type: "file" 的字段可以设置 accept、max_size、max_count
+
+use std::fs;
+use std::io::prelude::*;
+use std::time::{Duration, SystemTime};
+
+use actix_multipart::Multipart;
+use futures::StreamExt;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::db;
+
+
+pub const UPLOAD_DIR: &str = "_data/_upload";
+
+
+lazy_static! {
+    /// 返回模板中的 {{files.avatar.url}}
+    static ref FILES_TEMPLATE_RE: Regex = Regex::new(r"\{\{files\.([\w.]+)\}\}").unwrap();
+}
+
+
+/// 上传的全局限制
+struct UploadSetting {
+    // 单个文件的最大字节数
+    max_size: u64,
+    // 一次请求最多上传的文件数
+    max_files: usize,
+    // 普通字段的最大字节数
+    max_field_size: usize,
+    // 上传文件保存的秒数，0为一直保存
+    retention: u64,
+}
+
+
+fn get_upload_setting(settings: &Option<Value>) -> UploadSetting {
+    let setting = settings.as_ref().and_then(|x| x.get("upload")).cloned().unwrap_or(Value::Null);
+    UploadSetting {
+        max_size: setting.get("max_size").and_then(|x| x.as_u64()).unwrap_or(10 * 1024 * 1024),
+        max_files: setting.get("max_files").and_then(|x| x.as_u64()).unwrap_or(10) as usize,
+        max_field_size: setting.get("max_field_size").and_then(|x| x.as_u64()).unwrap_or(1024 * 1024) as usize,
+        retention: setting.get("retention").and_then(|x| x.as_u64()).unwrap_or(0),
+    }
+}
+
+
+/// 去掉文件名中的路径和特殊字符，只保留字母、数字、中文和 . _ -
+pub fn sanitize_filename(filename: &str) -> String {
+    let name = filename.rsplit(|c| c == '/' || c == '\\').next().unwrap_or("");
+    let name: String = name.chars()
+        .map(|c| if c.is_alphanumeric() || c == '.' || c == '_' || c == '-' { c } else { '_' })
+        .collect();
+    let name = name.trim_start_matches('.');
+    let name: String = name.chars().rev().take(100).collect::<Vec<char>>().into_iter().rev().collect();
+    if name == "" { "file".to_string() } else { name }
+}
+
+
+/// accept 为 "image/*,.pdf" 或者 ["image/png", ".jpg"]
+fn is_content_type_accepted(accept: &Value, mime: &str, filename: &str) -> bool {
+    let patterns: Vec<String> = match accept {
+        Value::String(v) => v.split(',').map(|x| x.trim().to_lowercase()).collect(),
+        Value::Array(v) => v.iter().filter_map(|x| x.as_str()).map(|x| x.trim().to_lowercase()).collect(),
+        _ => return true
+    };
+    let mime = mime.split(';').next().unwrap_or("").trim().to_lowercase();
+    let filename = filename.to_lowercase();
+    patterns.iter().any(|p| {
+        if p.starts_with('.') {
+            filename.ends_with(p.as_str())
+        } else if p.ends_with("/*") {
+            mime.starts_with(&p[..p.len() - 1])
+        } else {
+            p == "*" || p == "*/*" || *p == mime
+        }
+    })
+}
+
+
+/// 删除超过保存时间的上传文件
+fn cleanup_uploads(retention: u64) {
+    let entries = match fs::read_dir(UPLOAD_DIR) {
+        Ok(v) => v,
+        Err(_) => return
+    };
+    let now = SystemTime::now();
+    for entry in entries.flatten() {
+        let modified = match entry.metadata().and_then(|x| x.modified()) {
+            Ok(v) => v,
+            Err(_) => continue
+        };
+        if now.duration_since(modified).unwrap_or_default() > Duration::from_secs(retention) {
+            if let Err(e) = fs::remove_file(entry.path()) {
+                println!("remove expired upload file {:?} error: {}", entry.path(), e);
+            }
+        }
+    }
+}
+
+
+/// 同名的字段转换为数组
+fn insert_value(obj: &mut Map<String, Value>, name: &str, value: Value) {
+    match obj.get_mut(name) {
+        Some(Value::Array(items)) => items.push(value),
+        Some(old) => {
+            let first = old.clone();
+            *old = json!([first, value]);
+        }
+        None => {
+            obj.insert(name.to_string(), value);
+        }
+    }
+}
+
+
+async fn read_form_data(payload: &mut Multipart, body_fields: &Value, setting: &UploadSetting, created: &mut Vec<String>) -> Result<(Value, Value), String> {
+    let mut form_data = Map::new();
+    let mut files = Map::new();
+    let mut file_count = 0;
+
+    while let Some(item) = payload.next().await {
+        let mut field = item.map_err(|e| format!("read multipart error: {}", e))?;
+        let disposition = match field.content_disposition() {
+            Some(v) => v,
+            None => return Err("multipart field has no content-disposition".to_string())
+        };
+        let name = disposition.get_name().unwrap_or("").to_string();
+
+        let filename = match disposition.get_filename() {
+            Some(v) => v.to_string(),
+            None => {
+                // 普通字段
+                let mut data = Vec::new();
+                while let Some(chunk) = field.next().await {
+                    let chunk = chunk.map_err(|e| format!("read field {} error: {}", name, e))?;
+                    if data.len() + chunk.len() > setting.max_field_size {
+                        return Err(format!("field {} exceeds {} bytes", name, setting.max_field_size));
+                    }
+                    data.extend_from_slice(&chunk);
+                }
+                insert_value(&mut form_data, &name, Value::String(String::from_utf8_lossy(&data).to_string()));
+                continue;
+            }
+        };
+
+        file_count += 1;
+        if file_count > setting.max_files {
+            return Err(format!("upload at most {} files", setting.max_files));
+        }
+
+        let field_def = body_fields.get(&name).cloned().unwrap_or(Value::Null);
+        let mime = field.content_type().to_string();
+        if let Some(accept) = field_def.get("accept") {
+            if !is_content_type_accepted(accept, &mime, &filename) {
+                return Err(format!("file {} of {} is {}, not accepted", filename, name, mime));
+            }
+        }
+        if let Some(max_count) = field_def.get("max_count").and_then(|x| x.as_u64()) {
+            let count = match files.get(&name) {
+                Some(Value::Array(v)) => v.len() as u64,
+                Some(_) => 1,
+                None => 0
+            };
+            if count >= max_count {
+                return Err(format!("{} accepts at most {} files", name, max_count));
+            }
+        }
+        let max_size = field_def.get("max_size").and_then(|x| x.as_u64()).unwrap_or(setting.max_size);
+
+        fs::create_dir_all(UPLOAD_DIR).map_err(|e| format!("create folder {} error: {}", UPLOAD_DIR, e))?;
+        let stored_name = format!("{}_{}", uuid::Uuid::new_v4().to_simple(), sanitize_filename(&filename));
+        let filepath = format!("{}/{}", UPLOAD_DIR, stored_name);
+        let mut f = fs::File::create(&filepath).map_err(|e| format!("create file {} error: {}", filepath, e))?;
+        created.push(filepath.clone());
+
+        let mut size = 0u64;
+        let mut hasher = Sha256::new();
+        while let Some(chunk) = field.next().await {
+            let chunk = chunk.map_err(|e| format!("read file {} error: {}", filename, e))?;
+            size += chunk.len() as u64;
+            if size > max_size {
+                return Err(format!("file {} of {} exceeds {} bytes", filename, name, max_size));
+            }
+            hasher.input(&chunk);
+            f.write_all(&chunk).map_err(|e| format!("write file {} error: {}", filepath, e))?;
+        }
+
+        insert_value(&mut form_data, &name, Value::String(filename.clone()));
+        insert_value(&mut files, &name, json!({
+            "filename": filename,
+            "name": stored_name,
+            "size": size,
+            "mime": mime,
+            "sha256": format!("{:x}", hasher.result()),
+            "url": format!("/_upload/{}", stored_name)
+        }));
+    }
+    Ok((Value::Object(form_data), Value::Object(files)))
+}
+
+
+/// 读取multipart表单，返回 (form_data, files)
+/// form_data 中文件字段的值为原文件名，files 为文件字段的 {filename, name, size, mime, sha256, url}
+/// 出错时删除本次请求已经保存的文件
+pub async fn get_request_form_data(mut payload: Multipart, api_data: Option<&db::ApiData>, settings: &Option<Value>) -> Result<(Value, Value), String> {
+    let setting = get_upload_setting(settings);
+    if setting.retention > 0 {
+        cleanup_uploads(setting.retention);
+    }
+    let body_fields = match api_data {
+        Some(v) => v.body.clone(),
+        None => Value::Null
+    };
+
+    let mut created = Vec::new();
+    let result = read_form_data(&mut payload, &body_fields, &setting, &mut created).await;
+    if result.is_err() {
+        for filepath in &created {
+            let _ = fs::remove_file(filepath);
+        }
+    }
+    result
+}
+
+
+/// 替换返回模板中的 {{files.avatar}} {{files.avatar.url}}
+pub fn render_files_template(template: &Value, files: &Value) -> Value {
+    match template {
+        Value::String(s) => {
+            if !s.contains("{{files.") {
+                return template.clone();
+            }
+            let t = s.trim();
+            if t.starts_with("{{files.") && t.ends_with("}}") && t.matches("{{").count() == 1 {
+                let key = &t[8..t.len() - 2];
+                return files.pointer(&format!("/{}", key.replace(".", "/"))).cloned().unwrap_or(Value::Null);
+            }
+            let s = FILES_TEMPLATE_RE.replace_all(s, |caps: &regex::Captures| {
+                match files.pointer(&format!("/{}", caps[1].replace(".", "/"))) {
+                    Some(Value::String(v)) => v.to_string(),
+                    Some(Value::Null) | None => "".to_string(),
+                    Some(v) => v.to_string()
+                }
+            });
+            Value::String(s.to_string())
+        }
+        Value::Object(obj) => {
+            let mut new_obj = Map::new();
+            for (k, v) in obj {
+                new_obj.insert(k.to_string(), render_files_template(v, files));
+            }
+            Value::Object(new_obj)
+        }
+        Value::Array(items) => Value::Array(items.iter().map(|x| render_files_template(x, files)).collect()),
+        _ => template.clone()
+    }
+}
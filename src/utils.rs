@@ -13,6 +13,7 @@ use std::env;
 use chrono::Local;
 
 use crate::db;
+use crate::grpc;
 
 
 /// 建立异步线程，监控文件改动，当改动的时候，就重新生成文件
@@ -56,7 +57,6 @@ fn update_api_data(filepath: &str, current_dir: &str, data: web::Data<Mutex<db::
     let mut api_docs: HashMap<String, db::ApiDoc> = HashMap::new();
     let mut api_data: HashMap<String, HashMap<String, Arc<Mutex<db::ApiData>>>> = HashMap::new();
     let mut fileindex_data: HashMap<String, HashSet<String>> = HashMap::new();
-    let websocket_api = Arc::new(Mutex::new(db::ApiData::default()));
 
     let mut data = data.lock().unwrap();
     let filename = filepath.trim_start_matches(&format!("{}/", current_dir));
@@ -68,7 +68,8 @@ fn update_api_data(filepath: &str, current_dir: &str, data: web::Data<Mutex<db::
         let (basic_data, settings_value) = db::load_basic_data();
         data.basic_data = basic_data;
         data.settings = settings_value;
-    } else if !filepath.ends_with(".json5") && !filepath.ends_with(".json") {
+    } else if !filepath.ends_with(".json5") && !filepath.ends_with(".json") && !data.fileindex_data.contains_key(filename) {
+        // 不是json文件的时候，只处理接口文档引用的文件，如 graphql 的 schema 文件
         return;
     } else if filename == "_settings.json" || filename == "_settings.json5" {
         // 全局重新加载
@@ -81,13 +82,18 @@ fn update_api_data(filepath: &str, current_dir: &str, data: web::Data<Mutex<db::
         data.auth_doc = auth_data;
         println!("{} data update done. {}", filepath, Local::now());
         return;
-    } else if filename.contains("_data/") {
+    } else if filename == "_data/_types.json" || filename == "_data/_types.json5" {
+        // 重新加载自定义字段类型
+        data.custom_types = db::load_custom_types(&data.settings);
+        println!("{} data update done. {}", filepath, Local::now());
+        return;
+    } else if filename.contains("_data/") || data.fileindex_data.contains_key(filename) {
         // 如果修改的是_data里面的文件，需要通过fileindex_datal来找到对应文件更新
         match data.fileindex_data.get(filename) {
             Some(ref_files) => {
                 // 把找到的文件全部重新load一遍
                 for ref_file in ref_files {
-                    parse_error_code = db::Database::load_a_api_json_file(ref_file, &data.basic_data, &mut api_data, &mut api_docs, websocket_api.clone(), &mut fileindex_data);
+                    parse_error_code = db::Database::load_a_api_json_file(ref_file, &data.basic_data, &mut api_data, &mut api_docs, &mut fileindex_data);
                     if parse_error_code == -2 {
                         delete_files.push(ref_file.to_string());
                     }
@@ -96,7 +102,7 @@ fn update_api_data(filepath: &str, current_dir: &str, data: web::Data<Mutex<db::
             None => ()
         }
     } else {
-        parse_error_code = db::Database::load_a_api_json_file(filename, &data.basic_data, &mut api_data, &mut api_docs, websocket_api.clone(), &mut fileindex_data);
+        parse_error_code = db::Database::load_a_api_json_file(filename, &data.basic_data, &mut api_data, &mut api_docs, &mut fileindex_data);
         if parse_error_code == -2 {
             delete_files.push(filename.to_string());
         }
@@ -136,8 +142,6 @@ fn update_api_data(filepath: &str, current_dir: &str, data: web::Data<Mutex<db::
         data.api_docs.insert(k, v);
     }
 
-    data.websocket_api = websocket_api;
-
     for (ref_file, doc_files) in fileindex_data {
         if &ref_file != "" {
             match data.fileindex_data.get_mut(&ref_file) {
@@ -152,4 +156,7 @@ fn update_api_data(filepath: &str, current_dir: &str, data: web::Data<Mutex<db::
             }
         }
     }
-}
\ No newline at end of file
+
+    // 接口文档或者proto文件修改后重新解析proto文件
+    data.proto_schema = grpc::load_proto_schema(&data.api_docs);
+}
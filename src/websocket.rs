@@ -1,9 +1,14 @@
+use std::sync::Mutex;
 use std::time::{Duration, Instant};
 
 use actix::*;
-use actix_web::{web, App, Error, HttpRequest, HttpResponse, HttpServer};
+use actix_web::web;
 use actix_web_actors::ws;
+use serde_json::{json, Value};
 
+use crate::api;
+use crate::auth;
+use crate::db;
 use crate::server;
 
 /// How often heartbeat pings are sent
@@ -17,12 +22,22 @@ pub struct WsChatSession {
     /// Client must send ping at least once per 10 seconds (CLIENT_TIMEOUT),
     /// otherwise we drop connection.
     pub hb: Instant,
-    /// joined room
+    /// joined room, messages without a room are sent to it
     pub room: String,
     /// peer name
     pub name: Option<String>,
     /// Chat server
     pub addr: Addr<server::ChatServer>,
+    /// websocket api url in api docs
+    pub api_url: String,
+    /// api docs data, used to find replies in test_data
+    pub db: web::Data<Mutex<db::Database>>,
+    /// authenticated user in _auth.json5
+    pub user: Value,
+    /// false when the api needs auth and the token is expected in the first message
+    pub authed: bool,
+    /// how many history messages are replayed on join
+    pub history: usize,
 }
 
 impl Actor for WsChatSession {
@@ -43,17 +58,28 @@ impl Actor for WsChatSession {
         self.addr
             .send(server::Connect {
                 addr: addr.recipient(),
+                route: self.api_url.clone(),
+                name: self.name.clone().unwrap_or_default(),
+                user: self.user.clone(),
             })
             .into_actor(self)
             .then(|res, act, ctx| {
                 match res {
-                    Ok(res) => act.id = res,
+                    Ok(res) => {
+                        act.id = res;
+                        // join the default room once authenticated
+                        if act.authed {
+                            act.join(act.room.clone());
+                        }
+                    }
                     // something is wrong with chat server
                     _ => ctx.stop(),
                 }
                 fut::ready(())
             })
             .wait(ctx);
+
+        self.start_pushes(ctx);
     }
 
     fn stopping(&mut self, _: &mut Self::Context) -> Running {
@@ -68,7 +94,9 @@ impl Handler<server::Message> for WsChatSession {
     type Result = ();
 
     fn handle(&mut self, msg: server::Message, ctx: &mut Self::Context) {
-        ctx.text(msg.0);
+        if self.authed {
+            ctx.text(msg.0);
+        }
     }
 }
 
@@ -98,65 +126,116 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsChatSession {
             }
             ws::Message::Text(text) => {
                 let m = text.trim();
+                let json_msg: Value = serde_json::from_str(m).unwrap_or(Value::Null);
+                let msg_type = json_msg.get("type").and_then(|x| x.as_str()).unwrap_or("").to_string();
+
+                if !self.authed {
+                    // the first message must be {"type": "auth", "token": "xxx"}
+                    let token = json_msg.get("token").and_then(|x| x.as_str()).unwrap_or("");
+                    match self.find_user(token) {
+                        Ok(user) if msg_type == "auth" => {
+                            self.set_user(user.clone());
+                            self.authed = true;
+                            self.addr.do_send(server::Identify {
+                                id: self.id,
+                                name: self.name.clone().unwrap_or_default(),
+                                user: self.user.clone(),
+                            });
+                            ctx.text(json!({"type": "auth", "code": 1, "user": auth::get_public_user(&user)}).to_string());
+                            self.join(self.room.clone());
+                        }
+                        Ok(_) => {
+                            ctx.text(self.no_perm_response().to_string());
+                            ctx.stop();
+                        }
+                        Err(no_perm_response) => {
+                            ctx.text(no_perm_response.to_string());
+                            ctx.stop();
+                        }
+                    }
+                    return;
+                }
+
+                // test_data replies come before the chat commands, a case can match {"type": "join"} too
+                if let Some(replies) = self.find_test_reply(m) {
+                    self.send_replies(replies, ctx);
+                    return;
+                }
+
+                // json commands: join, leave, members, rooms and message to a room
+                match msg_type.as_str() {
+                    "join" | "leave" | "members" => {
+                        let room = json_msg.get("room").and_then(|x| x.as_str()).unwrap_or(&self.room).to_string();
+                        match msg_type.as_str() {
+                            "join" => {
+                                self.room = room.clone();
+                                self.join(room);
+                            }
+                            "leave" => self.addr.do_send(server::Leave { id: self.id, name: room }),
+                            _ => self.send_members(room, ctx),
+                        }
+                        return;
+                    }
+                    "rooms" => {
+                        self.send_rooms(ctx);
+                        return;
+                    }
+                    "message" => {
+                        let room = json_msg.get("room").and_then(|x| x.as_str()).unwrap_or(&self.room).to_string();
+                        let data = json_msg.get("data").cloned().unwrap_or(Value::Null);
+                        self.addr.do_send(server::ClientMessage {
+                            id: self.id,
+                            msg: data.to_string(),
+                            room,
+                        });
+                        return;
+                    }
+                    _ => (),
+                }
+
                 // we check for /sss type of messages
                 if m.starts_with('/') {
                     let v: Vec<&str> = m.splitn(2, ' ').collect();
                     match v[0] {
-                        "/list" => {
-                            // Send ListRooms message to chat server and wait for
-                            // response
-                            println!("List rooms");
-                            self.addr
-                                .send(server::ListRooms)
-                                .into_actor(self)
-                                .then(|res, _, ctx| {
-                                    match res {
-                                        Ok(rooms) => {
-                                            for room in rooms {
-                                                ctx.text(room);
-                                            }
-                                        }
-                                        _ => println!("Something is wrong"),
-                                    }
-                                    fut::ready(())
-                                })
-                                .wait(ctx)
-                            // .wait(ctx) pauses all events in context,
-                            // so actor wont receive any new messages until it get list
-                            // of rooms back
-                        }
+                        "/list" => self.send_rooms(ctx),
                         "/join" => {
                             if v.len() == 2 {
                                 self.room = v[1].to_owned();
-                                self.addr.do_send(server::Join {
-                                    id: self.id,
-                                    name: self.room.clone(),
-                                });
-
-                                ctx.text("joined");
+                                self.join(self.room.clone());
                             } else {
                                 ctx.text("!!! room name is required");
                             }
                         }
+                        "/leave" => {
+                            let room = if v.len() == 2 { v[1].to_owned() } else { self.room.clone() };
+                            self.addr.do_send(server::Leave { id: self.id, name: room });
+                        }
+                        "/members" => {
+                            let room = if v.len() == 2 { v[1].to_owned() } else { self.room.clone() };
+                            self.send_members(room, ctx);
+                        }
                         "/name" => {
                             if v.len() == 2 {
                                 self.name = Some(v[1].to_owned());
+                                self.addr.do_send(server::Identify {
+                                    id: self.id,
+                                    name: v[1].to_owned(),
+                                    user: self.user.clone(),
+                                });
                             } else {
                                 ctx.text("!!! name is required");
                             }
                         }
                         _ => ctx.text(format!("!!! unknown command: {:?}", m)),
                     }
+                } else if let Some(replies) = self.find_reply(m) {
+                    // reply with mock response of the websocket api
+                    self.send_replies(replies, ctx);
                 } else {
-                    let msg = if let Some(ref name) = self.name {
-                        format!("{}: {}", name, m)
-                    } else {
-                        m.to_owned()
-                    };
                     // send message to chat server
                     self.addr.do_send(server::ClientMessage {
                         id: self.id,
-                        msg,
+                        msg: m.to_owned(),
                         room: self.room.clone(),
                     })
                 }
@@ -174,6 +253,133 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsChatSession {
 }
 
 impl WsChatSession {
+    /// find replies of the message in the websocket api docs
+    fn find_reply(&self, message: &str) -> Option<Vec<Value>> {
+        let db_data = self.db.lock().unwrap();
+        api::find_websocket_reply(&self.api_url, message, &db_data)
+    }
+
+    /// find replies of the message in test_data only
+    fn find_test_reply(&self, message: &str) -> Option<Vec<Value>> {
+        let db_data = self.db.lock().unwrap();
+        api::find_websocket_test_reply(&self.api_url, message, &db_data)
+    }
+
+    fn send_replies(&self, replies: Vec<Value>, ctx: &mut ws::WebsocketContext<Self>) {
+        for reply in replies {
+            match reply {
+                Value::String(v) => ctx.text(v),
+                v => ctx.text(v.to_string()),
+            }
+        }
+    }
+
+    /// find user and group of the token in _auth.json5
+    /// find the user of the token and check the perms of its group like chat_route does
+    fn find_user(&self, token: &str) -> Result<Value, Value> {
+        let db_data = self.db.lock().unwrap();
+        let auth_doc = match &db_data.auth_doc {
+            Some(v) => v,
+            None => return Err(json!({"code": -1, "msg": "no perm to visit"})),
+        };
+        let (user, group) = match auth::find_user_by_token(auth_doc, token) {
+            Some(v) => v,
+            None => return Err(auth_doc.no_perm_response.clone()),
+        };
+        let group = match auth_doc.groups.iter().find(|x| &x.name == group) {
+            Some(v) => v,
+            None => return Err(auth_doc.no_perm_response.clone()),
+        };
+        if let Some(no_perm_response) = api::group_perm_validator(group, &self.api_url, "WEBSOCKET", &auth_doc.no_perm_response) {
+            return Err(no_perm_response.clone());
+        }
+        Ok(user.clone())
+    }
+
+    fn no_perm_response(&self) -> Value {
+        let db_data = self.db.lock().unwrap();
+        match &db_data.auth_doc {
+            Some(auth_doc) => auth_doc.no_perm_response.clone(),
+            None => json!({"code": -1, "msg": "no perm to visit"}),
+        }
+    }
+
+    /// use username of the user as peer name
+    pub fn set_user(&mut self, user: Value) {
+        if let Some(name) = user.get("username").and_then(|x| x.as_str()) {
+            self.name = Some(name.to_string());
+        } else if !user.is_null() {
+            self.name = Some(auth::get_user_subject(&user));
+        }
+        self.user = user;
+    }
+
+    fn join(&self, room: String) {
+        self.addr.do_send(server::Join {
+            id: self.id,
+            name: room,
+            history: self.history,
+        });
+    }
+
+    fn send_rooms(&self, ctx: &mut ws::WebsocketContext<Self>) {
+        // Send ListRooms message to chat server and wait for
+        // response
+        self.addr
+            .send(server::ListRooms { route: self.api_url.clone() })
+            .into_actor(self)
+            .then(|res, _, ctx| {
+                match res {
+                    Ok(rooms) => ctx.text(json!({"type": "rooms", "rooms": rooms}).to_string()),
+                    _ => println!("Something is wrong"),
+                }
+                fut::ready(())
+            })
+            .wait(ctx)
+        // .wait(ctx) pauses all events in context,
+        // so actor wont receive any new messages until it get list
+        // of rooms back
+    }
+
+    fn send_members(&self, room: String, ctx: &mut ws::WebsocketContext<Self>) {
+        self.addr
+            .send(server::Members { route: self.api_url.clone(), room: room.clone() })
+            .into_actor(self)
+            .then(move |res, _, ctx| {
+                match res {
+                    Ok(members) => ctx.text(json!({"type": "members", "room": room, "members": members}).to_string()),
+                    _ => println!("Something is wrong"),
+                }
+                fut::ready(())
+            })
+            .wait(ctx)
+    }
+
+    /// start pushes defined in `push` of the websocket api
+    /// {interval: 1000} repeats every interval milliseconds, {delay: 500} sends once after connect
+    /// `message` is sent as it is, `response` fields generate a mock message every time
+    fn start_pushes(&self, ctx: &mut ws::WebsocketContext<Self>) {
+        for push in api::get_websocket_pushes(&self.api_url, &self.db.lock().unwrap()) {
+            let interval = push.get("interval").and_then(|x| x.as_u64()).unwrap_or(0);
+            let delay = push.get("delay").and_then(|x| x.as_u64()).unwrap_or(0);
+            if interval > 0 {
+                ctx.run_later(Duration::from_millis(delay), move |_, ctx| {
+                    ctx.run_interval(Duration::from_millis(interval), move |act, ctx| {
+                        if act.authed {
+                            ctx.text(api::create_websocket_push_message(&push, &act.db.lock().unwrap().custom_types));
+                        }
+                    });
+                });
+            } else {
+                ctx.run_later(Duration::from_millis(delay), move |act, ctx| {
+                    if act.authed {
+                        ctx.text(api::create_websocket_push_message(&push, &act.db.lock().unwrap().custom_types));
+                    }
+                });
+            }
+        }
+    }
+
     /// helper method that sends ping to client every second.
     ///
     /// also this method checks heartbeats from client
@@ -197,4 +403,4 @@ impl WsChatSession {
             ctx.ping(b"");
         });
     }
-}
\ No newline at end of file
+}